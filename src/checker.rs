@@ -0,0 +1,192 @@
+use super::error::{ErrorCode, LangError};
+use super::parser::{AstNode, MatchArm, NodeKind, Pattern, TemplatePart};
+use std::collections::HashMap;
+
+// Variant names and value counts of each enum, or None when the name is declared
+// more than once and a `match` on it cannot be checked statically
+type EnumTable = HashMap<String, Option<Vec<(String, usize)>>>;
+
+// Static checks run after parsing and before evaluation. They only produce
+// warnings; the program still runs.
+pub fn check_program(program: &AstNode) -> Vec<LangError> {
+    let mut enums = EnumTable::new();
+    visit(program, &mut |node| {
+        if let NodeKind::EnumDefinition { name, variants } = &node.kind {
+            let variants = variants.iter().map(|(variant, fields)| (variant.clone(), fields.len())).collect();
+            enums.entry(name.clone())
+                .and_modify(|existing| *existing = None)
+                .or_insert(Some(variants));
+        }
+    });
+
+    let mut warnings = Vec::new();
+    visit(program, &mut |node| {
+        if let NodeKind::Match { arms, .. } = &node.kind {
+            if let Some(missing) = missing_cases(&unguarded_patterns(arms), &enums) {
+                warnings.push(LangError::new(ErrorCode::NonExhaustiveMatch,
+                    format!("Non-exhaustive match: {}", missing)).at(node.span));
+            }
+        }
+    });
+    warnings
+}
+
+// A guarded arm may be skipped, so it never makes a match exhaustive
+fn unguarded_patterns(arms: &[MatchArm]) -> Vec<&Pattern> {
+    arms.iter().filter(|arm| arm.guard.is_none()).map(|arm| &arm.pattern).collect()
+}
+
+fn is_irrefutable(pattern: &Pattern) -> bool {
+    match pattern {
+        Pattern::Wildcard | Pattern::Binding(_) => true,
+        // `[..rest]` matches every array, but the value might not be an array
+        _ => false,
+    }
+}
+
+// Describes the values that none of `patterns` matches, or None when they cover everything
+fn missing_cases(patterns: &[&Pattern], enums: &EnumTable) -> Option<String> {
+    if patterns.iter().any(|pattern| is_irrefutable(pattern)) {
+        return None;
+    }
+
+    // `true` and `false` together cover a boolean
+    let has_bool = |b: bool| patterns.iter().any(|pattern| matches!(pattern,
+        Pattern::Literal(AstNode { kind: NodeKind::Boolean(value), .. }) if *value == b));
+    match (has_bool(true), has_bool(false)) {
+        (true, true) => return None,
+        (true, false) => return Some("missing false".to_string()),
+        (false, true) => return Some("missing true".to_string()),
+        (false, false) => {}
+    }
+
+    // Every variant of the matched enum must be covered
+    let enum_name = patterns.iter().find_map(|pattern| match pattern {
+        Pattern::Variant { enum_name, .. } => Some(enum_name),
+        _ => None,
+    });
+    if let Some(enum_name) = enum_name {
+        // Unknown enums are reported when the match runs
+        let Some(Some(variants)) = enums.get(enum_name) else {
+            return None;
+        };
+        let missing: Vec<String> = variants.iter()
+            .filter(|(variant, count)| !variant_covered(enum_name, variant, *count, patterns, enums))
+            .map(|(variant, _)| format!("{}.{}", enum_name, variant))
+            .collect();
+        if missing.is_empty() {
+            return None;
+        }
+        return Some(format!("missing {}", missing.join(", ")));
+    }
+
+    Some("add a '_' arm to handle the remaining values".to_string())
+}
+
+// A variant is covered by an arm whose sub-patterns all match anything. For a
+// single-value variant the sub-patterns may instead cover the value between them.
+fn variant_covered(enum_name: &str, variant: &str, count: usize, patterns: &[&Pattern], enums: &EnumTable) -> bool {
+    let sub_patterns: Vec<&Vec<Pattern>> = patterns.iter()
+        .filter_map(|pattern| match pattern {
+            Pattern::Variant { enum_name: name, variant: v, fields } if name == enum_name && v == variant => Some(fields),
+            _ => None,
+        })
+        .collect();
+    if sub_patterns.iter().any(|fields| fields.iter().all(is_irrefutable)) {
+        return true;
+    }
+    if count == 1 {
+        let firsts: Vec<&Pattern> = sub_patterns.iter().filter_map(|fields| fields.first()).collect();
+        return !firsts.is_empty() && missing_cases(&firsts, enums).is_none();
+    }
+    false
+}
+
+// Calls `f` on `node` and every node nested inside it
+pub fn visit(node: &AstNode, f: &mut dyn FnMut(&AstNode)) {
+    f(node);
+    let mut children: Vec<&AstNode> = Vec::new();
+    match &node.kind {
+        NodeKind::Integer(_) | NodeKind::Number(_) | NodeKind::Boolean(_) | NodeKind::String(_) |
+        NodeKind::Identifier(_) | NodeKind::Null | NodeKind::BreakStatement | NodeKind::ContinueStatement |
+        NodeKind::StructDefinition { .. } | NodeKind::EnumDefinition { .. } => {}
+        NodeKind::InterpolatedString(parts) => {
+            children.extend(parts.iter().filter_map(|part| match part {
+                TemplatePart::Expression(expr) => Some(expr),
+                TemplatePart::Literal(_) => None,
+            }));
+        }
+        NodeKind::Array(items) | NodeKind::BlockStatement(items) | NodeKind::Program(items) |
+        NodeKind::ImplBlock { methods: items, .. } => children.extend(items),
+        NodeKind::MapLiteral(entries) => {
+            for (key, value) in entries {
+                children.push(key);
+                children.push(value);
+            }
+        }
+        NodeKind::ArrayAccess { array, index } | NodeKind::SafeArrayAccess { array, index } => {
+            children.push(array);
+            children.push(index);
+        }
+        NodeKind::FieldAccess { object, .. } => children.push(object),
+        NodeKind::Slice { array, start, end, step } => {
+            children.push(array);
+            children.extend([start, end, step].into_iter().flatten().map(|part| &**part));
+        }
+        NodeKind::LetStatement { value, .. } => children.push(value),
+        NodeKind::IfStatement { condition, then_branch, else_branch } => {
+            children.push(condition);
+            children.push(then_branch);
+            children.extend(else_branch.as_deref());
+        }
+        NodeKind::WhileStatement { condition, body } => {
+            children.push(condition);
+            children.push(body);
+        }
+        NodeKind::ForStatement { init, condition, increment, body } => {
+            children.extend([init, condition, increment, body].map(|part| &**part));
+        }
+        NodeKind::ForInStatement { iterable, body, .. } => {
+            children.push(iterable);
+            children.push(body);
+        }
+        NodeKind::FunctionDefinition { body, .. } | NodeKind::FunctionLiteral { body, .. } => children.push(body),
+        NodeKind::FunctionCall { function, arguments } => {
+            children.push(function);
+            children.extend(arguments);
+        }
+        NodeKind::ReturnStatement { value } => children.extend(value.as_deref()),
+        NodeKind::ThrowExpression { value } => children.push(value),
+        NodeKind::TryStatement { body, catch_body, finally_body, .. } => {
+            children.push(body);
+            children.extend(catch_body.as_deref());
+            children.extend(finally_body.as_deref());
+        }
+        NodeKind::Assignment { target, value, .. } => {
+            children.push(target);
+            children.push(value);
+        }
+        NodeKind::InfixExpression { left, right, .. } | NodeKind::LogicalExpression { left, right, .. } => {
+            children.push(left);
+            children.push(right);
+        }
+        NodeKind::PrefixExpression { right, .. } => children.push(right),
+        NodeKind::Range { start, end, .. } => {
+            children.push(start);
+            children.push(end);
+        }
+        NodeKind::Conditional { condition, consequence, alternative } => {
+            children.extend([condition, consequence, alternative].map(|part| &**part));
+        }
+        NodeKind::Match { subject, arms } => {
+            children.push(subject);
+            for arm in arms {
+                children.extend(arm.guard.as_ref());
+                children.push(&arm.body);
+            }
+        }
+    }
+    for child in children {
+        visit(child, f);
+    }
+}
@@ -0,0 +1,1486 @@
+use super::checker::visit;
+use super::error::{ErrorCode, LangError};
+use super::evaluator::depth_exceeded;
+use super::lexer::{Span, Token};
+use super::object::Object;
+use super::parser::{AstNode, NodeKind, Pattern, TemplatePart};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+// Where a variable lives at run time. Names declared at the top level of the program
+// are globals; every other variable is resolved to a place in its function's frame
+// when the program is compiled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Var {
+    // Slot of the frame
+    Local(u32),
+    // Cell of the frame, for variables that nested functions capture
+    Cell(u32),
+    // Cell captured by the running closure
+    Capture(u32),
+    Global(u32),
+    // Index into the chunk's chains, for names that resolve differently
+    // depending on which declarations have run
+    Chain(u32),
+}
+
+// Candidates for a name, tried in order until one is declared
+#[derive(Debug)]
+pub struct Chain {
+    pub name: String,
+    pub links: Vec<Var>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HandlerKind {
+    Catch,
+    Finally,
+}
+
+// One instruction. Operands index the tables of the chunk being run, depths count
+// temporaries above the frame's variables, and jump targets are positions in the code.
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+    Constant(u32),
+    Null,
+    Pop,
+    Dup,
+    Load(Var),
+    // Load of the function in a call by name, which has its own error message
+    LoadCallee(Var),
+    // Pops a value into a variable of the current scope
+    Define(Var),
+    // Gives a captured variable a fresh cell as its scope is entered
+    NewCell(u32),
+    // Moves a parameter from its slot into the cell of a captured parameter
+    ParamToCell(u32, u32),
+    // Pops a value into the temporary at this depth
+    Replace(u32),
+    // Drops temporaries down to this depth; `Unwind` keeps the value on top
+    Truncate(u32),
+    Unwind(u32),
+    Closure(u32),
+    // Declares the struct or enum in `types`
+    MakeType(u32),
+    // Pops the methods of `impls[i]` into the type held by the variable
+    Impl(Var, u32),
+    Array(u32),
+    // Checks that the value on top can be a map key
+    CheckKey,
+    Map(u32),
+    // Checks that the value on top can bound a range
+    RangeBound,
+    MakeRange(bool),
+    // Turns two range bounds into the cursor and exclusive end of a for-in loop
+    RangeStart(bool),
+    // Pushes the next integer of the range, or jumps once it is exhausted
+    RangeNext(u32),
+    // Replaces the value on top with the items a for-in loop visits, last first
+    IterPrepare(bool),
+    // Pushes the next item, the value and then the key of a pair, or jumps when done
+    IterNext(u32, bool),
+    Index,
+    SafeIndex,
+    JumpIfNullKeep(u32),
+    // Bits 0 to 2 tell whether start, end and step are on the stack
+    Slice(u8),
+    Field(u32),
+    // Reads through `paths[i]`, whose index keys are on the stack
+    LoadPath(u32),
+    // Like `LoadPath` but leaves the keys for a write back after a method call
+    Receiver(u32),
+    Assign(u32),
+    Infix(u32),
+    Prefix(u32),
+    Concat(u32),
+    Jump(u32),
+    JumpIfFalse(u32),
+    // Jump keeping the value on top if the test holds, otherwise pop it
+    JumpIfFalseKeep(u32),
+    JumpIfTrueKeep(u32),
+    JumpIfNotNullKeep(u32),
+    // Argument count and, for calls by name, the name in `names`
+    Call(u32, Option<u32>),
+    CallMethod(u32),
+    Return,
+    // End of the program, with the value of its last statement on top
+    Halt,
+    Throw,
+    // Fails with `errors[i]` as stored
+    Raise(u32),
+    PushHandler(HandlerKind, u32),
+    PopHandler,
+    // Continues with the error a `finally` block ran for
+    Rethrow,
+    DropPending,
+    // Matches the value on top against `patterns[i]`, pushing what it binds
+    // with the first binding on top, or jumps
+    Pattern(u32, u32),
+    NoMatch,
+    // `break` or `continue` that reached the end of a function
+    EscapeFunction,
+}
+
+impl Op {
+    fn target_mut(&mut self) -> Option<&mut u32> {
+        match self {
+            Op::RangeNext(target) | Op::IterNext(target, _) | Op::JumpIfNullKeep(target) |
+            Op::Jump(target) | Op::JumpIfFalse(target) | Op::JumpIfFalseKeep(target) |
+            Op::JumpIfTrueKeep(target) | Op::JumpIfNotNullKeep(target) |
+            Op::PushHandler(_, target) | Op::Pattern(_, target) => Some(target),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TypeDef {
+    Struct { name: String, fields: Vec<String> },
+    Enum { name: String, variants: Vec<(String, Vec<String>)> },
+}
+
+#[derive(Debug)]
+pub struct ImplDef {
+    pub name: String,
+    pub methods: Vec<String>,
+}
+
+// Variable, element or field an assignment writes to
+#[derive(Debug)]
+pub struct Path {
+    pub root: Root,
+    pub steps: Vec<Step>,
+    // Operator of a compound assignment
+    pub op: Option<Token>,
+}
+
+#[derive(Debug)]
+pub enum Root {
+    Var { var: Var, name: String, span: Span },
+    // Not a variable, element or field; assigning to it fails
+    Invalid(Span),
+}
+
+#[derive(Debug)]
+pub enum Step {
+    Index(Span),
+    Field(String, Span),
+}
+
+impl Path {
+    // Index keys the path takes from the stack
+    pub fn keys(&self) -> usize {
+        self.steps.iter().filter(|step| matches!(step, Step::Index(_))).count()
+    }
+}
+
+#[derive(Debug)]
+pub struct MethodCall {
+    pub name: String,
+    pub argc: usize,
+    // Receiver to update when the method changes `self`
+    pub path: Option<u32>,
+}
+
+#[derive(Debug)]
+pub enum CompiledPattern {
+    Wildcard,
+    Binding,
+    Literal(Object),
+    // Literal that fails when evaluated, such as an infinite number
+    Invalid(LangError),
+    Array { prefix: Vec<CompiledPattern>, rest: Option<Box<CompiledPattern>>, suffix: Vec<CompiledPattern> },
+    Variant { enum_var: Var, enum_name: String, variant: String, fields: Vec<CompiledPattern> },
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    pub spans: Vec<Span>,
+    pub constants: Vec<Object>,
+    pub names: Vec<String>,
+    pub functions: Vec<Rc<Proto>>,
+    pub chains: Vec<Chain>,
+    pub types: Vec<TypeDef>,
+    pub impls: Vec<ImplDef>,
+    pub paths: Vec<Path>,
+    pub methods: Vec<MethodCall>,
+    pub operators: Vec<Token>,
+    pub patterns: Vec<CompiledPattern>,
+    pub errors: Vec<LangError>,
+}
+
+// A compiled function; closures pair it with the cells it captures
+#[derive(Debug)]
+pub struct Proto {
+    pub parameters: Vec<String>,
+    pub chunk: Chunk,
+    // Slots the frame reserves, starting with the parameters
+    pub slot_count: usize,
+    pub cell_count: usize,
+    // Cells of the enclosing function handed to each closure, as `Cell` or `Capture`
+    pub captures: Vec<Var>,
+    // Where `self` lives, for methods that write it back to their receiver
+    pub self_var: Option<Var>,
+}
+
+#[derive(Debug)]
+pub struct Program {
+    pub main: Rc<Proto>,
+    // Names of the global variables, by index
+    pub globals: Vec<String>,
+}
+
+// Compiles a parsed program. Compilation never fails: code that can only fail,
+// like an assignment to a literal, compiles to an instruction raising its error.
+// `max_nesting` is the tree-walker's nesting limit, applied here to the depth of the syntax tree
+pub fn compile(program: &AstNode, max_nesting: usize) -> Program {
+    let mut compiler = Compiler {
+        functions: vec![Function::new(Vec::new(), captured_names(program))],
+        globals: Vec::new(),
+        global_index: HashMap::new(),
+        protos: HashMap::new(),
+        // The program node is the first level, as on the tree-walker
+        nesting: 1,
+        max_nesting,
+    };
+    let statements = match &program.kind {
+        NodeKind::Program(statements) => statements.as_slice(),
+        _ => std::slice::from_ref(program),
+    };
+    compiler.statements(statements, program.span);
+    compiler.emit(Op::Halt, program.span);
+    let main = compiler.finish_function();
+    Program { main: Rc::new(main), globals: compiler.globals }
+}
+
+// A declaration in scope. Simple ones are declared once, directly in their scope,
+// so reads before the declaration go to outer variables of the same name. Dynamic
+// ones are declared in a branch or loop and may or may not exist when read.
+struct Binding {
+    var: Var,
+    dynamic: bool,
+    declared: bool,
+}
+
+struct Scope {
+    bindings: HashMap<String, Binding>,
+    slot_start: u32,
+    cell_start: u32,
+}
+
+#[derive(Clone, Copy)]
+enum Exit<'a> {
+    Loop { break_label: u32, break_depth: u32, continue_label: u32, continue_depth: u32 },
+    // Expression whose value ends any control flow inside it
+    Expr { end: Option<u32>, depth: u32 },
+    // Handler of a `try` to pop when leaving it
+    Handler,
+    // `finally` block to run when leaving its `try`
+    Finally { body: &'a AstNode, scopes: usize, next_slot: u32, next_cell: u32 },
+    // Error kept while a `finally` block runs
+    Pending,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Jump {
+    Break,
+    Continue,
+    Return,
+}
+
+struct Function<'a> {
+    chunk: Chunk,
+    parameters: Vec<String>,
+    scopes: Vec<Scope>,
+    // Names referenced by functions nested in this one
+    captured: HashSet<String>,
+    captures: Vec<Var>,
+    next_slot: u32,
+    slot_count: u32,
+    next_cell: u32,
+    cell_count: u32,
+    // Temporaries on the stack at the current instruction
+    depth: u32,
+    exits: Vec<Exit<'a>>,
+    // Position of each label once bound
+    labels: Vec<Option<u32>>,
+    self_var: Option<Var>,
+}
+
+impl Function<'_> {
+    fn new(parameters: Vec<String>, captured: HashSet<String>) -> Self {
+        Function {
+            chunk: Chunk::default(),
+            next_slot: parameters.len() as u32,
+            slot_count: parameters.len() as u32,
+            parameters,
+            scopes: Vec::new(),
+            captured,
+            captures: Vec::new(),
+            next_cell: 0,
+            cell_count: 0,
+            depth: 0,
+            exits: Vec::new(),
+            labels: Vec::new(),
+            self_var: None,
+        }
+    }
+}
+
+struct Compiler<'a> {
+    // Functions being compiled, innermost last; the first is the program itself
+    functions: Vec<Function<'a>>,
+    globals: Vec<String>,
+    global_index: HashMap<String, u32>,
+    // Functions compiled already, by node, for definitions compiled more than once
+    // (hoisted functions and `finally` blocks copied to each exit)
+    protos: HashMap<*const AstNode, Rc<Proto>>,
+    // Nodes being compiled inside one another
+    nesting: usize,
+    max_nesting: usize,
+}
+
+impl<'a> Compiler<'a> {
+    fn f(&mut self) -> &mut Function<'a> {
+        self.functions.last_mut().expect("a function is being compiled")
+    }
+
+    fn emit(&mut self, op: Op, span: Span) {
+        let f = self.f();
+        f.depth = match op {
+            Op::Constant(_) | Op::Null | Op::Dup | Op::Load(_) | Op::LoadCallee(_) |
+            Op::Closure(_) | Op::MakeType(_) | Op::RangeNext(_) => f.depth + 1,
+            Op::Pop | Op::Define(_) | Op::Replace(_) | Op::Index | Op::SafeIndex | Op::Infix(_) |
+            Op::MakeRange(_) | Op::JumpIfFalse(_) | Op::JumpIfFalseKeep(_) | Op::JumpIfTrueKeep(_) |
+            Op::JumpIfNotNullKeep(_) | Op::Throw => f.depth - 1,
+            Op::Truncate(depth) => depth,
+            Op::Unwind(depth) => depth + 1,
+            Op::Array(n) => f.depth + 1 - n,
+            Op::Map(n) => f.depth + 1 - 2 * n,
+            Op::Concat(n) => f.depth + 1 - n,
+            Op::Slice(flags) => f.depth - flags.count_ones(),
+            Op::IterNext(_, pair) => f.depth + 1 + pair as u32,
+            Op::LoadPath(p) => f.depth + 1 - f.chunk.paths[p as usize].keys() as u32,
+            Op::Receiver(_) => f.depth + 1,
+            Op::Assign(p) => f.depth - f.chunk.paths[p as usize].keys() as u32,
+            Op::Call(argc, _) => f.depth - argc,
+            Op::CallMethod(m) => {
+                let method = &f.chunk.methods[m as usize];
+                let keys = method.path.map_or(0, |p| f.chunk.paths[p as usize].keys());
+                f.depth - (method.argc + keys) as u32
+            }
+            Op::Impl(_, i) => f.depth + 1 - f.chunk.impls[i as usize].methods.len() as u32,
+            Op::Pattern(p, _) => f.depth + pattern_bindings(&f.chunk.patterns[p as usize]) as u32,
+            Op::Return | Op::Halt => f.depth - 1,
+            _ => f.depth,
+        };
+        f.chunk.code.push(op);
+        f.chunk.spans.push(span);
+    }
+
+    // Sets the depth for code reached only by a jump, or not at all
+    fn set_depth(&mut self, depth: u32) {
+        self.f().depth = depth;
+    }
+
+    fn label(&mut self) -> u32 {
+        let f = self.f();
+        f.labels.push(None);
+        f.labels.len() as u32 - 1
+    }
+
+    fn bind(&mut self, label: u32) {
+        let f = self.f();
+        f.labels[label as usize] = Some(f.chunk.code.len() as u32);
+    }
+
+    fn constant(&mut self, value: Object, span: Span) {
+        let constants = &mut self.f().chunk.constants;
+        constants.push(value);
+        let index = constants.len() as u32 - 1;
+        self.emit(Op::Constant(index), span);
+    }
+
+    fn raise(&mut self, error: LangError, span: Span) {
+        self.fail(error.at(span), span);
+    }
+
+    // Like `raise`, but the error keeps whatever span it has
+    fn fail(&mut self, error: LangError, span: Span) {
+        let errors = &mut self.f().chunk.errors;
+        errors.push(error);
+        let index = errors.len() as u32 - 1;
+        self.emit(Op::Raise(index), span);
+    }
+
+    fn name(&mut self, name: &str) -> u32 {
+        let names = &mut self.f().chunk.names;
+        names.push(name.to_string());
+        names.len() as u32 - 1
+    }
+
+    fn operator(&mut self, op: &Token) -> u32 {
+        let operators = &mut self.f().chunk.operators;
+        operators.push(op.clone());
+        operators.len() as u32 - 1
+    }
+
+    fn global(&mut self, name: &str) -> u32 {
+        if let Some(&index) = self.global_index.get(name) {
+            return index;
+        }
+        self.globals.push(name.to_string());
+        let index = self.globals.len() as u32 - 1;
+        self.global_index.insert(name.to_string(), index);
+        index
+    }
+
+    fn finish_function(&mut self) -> Proto {
+        let mut f = self.functions.pop().expect("a function is being compiled");
+        for op in &mut f.chunk.code {
+            if let Some(target) = op.target_mut() {
+                *target = f.labels[*target as usize].expect("jump to a bound label");
+            }
+        }
+        Proto {
+            parameters: f.parameters,
+            chunk: f.chunk,
+            slot_count: f.slot_count as usize,
+            cell_count: f.cell_count as usize,
+            captures: f.captures,
+            self_var: f.self_var,
+        }
+    }
+
+    // Scopes
+
+    fn enter_scope(&mut self, declarations: &[(&str, bool)], span: Span) {
+        let mut names: Vec<(&str, bool)> = Vec::new();
+        for &(name, direct) in declarations {
+            match names.iter_mut().find(|(seen, _)| *seen == name) {
+                Some((_, dynamic)) => *dynamic |= !direct,
+                None => names.push((name, !direct)),
+            }
+        }
+        let f = self.f();
+        let mut scope = Scope { bindings: HashMap::new(), slot_start: f.next_slot, cell_start: f.next_cell };
+        let mut cells = Vec::new();
+        for (name, dynamic) in names {
+            let var = if dynamic || f.captured.contains(name) {
+                f.next_cell += 1;
+                f.cell_count = f.cell_count.max(f.next_cell);
+                cells.push(f.next_cell - 1);
+                Var::Cell(f.next_cell - 1)
+            } else {
+                f.next_slot += 1;
+                f.slot_count = f.slot_count.max(f.next_slot);
+                Var::Local(f.next_slot - 1)
+            };
+            scope.bindings.insert(name.to_string(), Binding { var, dynamic, declared: false });
+        }
+        f.scopes.push(scope);
+        for cell in cells {
+            self.emit(Op::NewCell(cell), span);
+        }
+    }
+
+    fn exit_scope(&mut self) {
+        let f = self.f();
+        let scope = f.scopes.pop().expect("a scope to exit");
+        f.next_slot = scope.slot_start;
+        f.next_cell = scope.cell_start;
+    }
+
+    // Variable a declaration of `name` writes to
+    fn declare(&mut self, name: &str) -> Var {
+        if self.functions.len() == 1 && self.f().scopes.is_empty() {
+            return Var::Global(self.global(name));
+        }
+        let f = self.f();
+        let scope = f.scopes.last_mut().expect("a scope to declare in");
+        if let Some(binding) = scope.bindings.get(name) {
+            return binding.var;
+        }
+        // Every declaration is found when its scope is entered; this only guards
+        // against a statement that was missed
+        f.next_slot += 1;
+        f.slot_count = f.slot_count.max(f.next_slot);
+        let var = Var::Local(f.next_slot - 1);
+        scope.bindings.insert(name.to_string(), Binding { var, dynamic: false, declared: false });
+        var
+    }
+
+    fn mark_declared(&mut self, name: &str) {
+        if let Some(binding) = self.f().scopes.last_mut().and_then(|scope| scope.bindings.get_mut(name)) {
+            binding.declared = true;
+        }
+    }
+
+    // Variable `name` refers to at this point of the code
+    fn resolve(&mut self, name: &str) -> Var {
+        let level = self.functions.len() - 1;
+        let (links, definite) = self.lookup(level, name, true);
+        if links.len() == 1 && (definite || matches!(links[0], Var::Global(_))) {
+            return links[0];
+        }
+        let chains = &mut self.f().chunk.chains;
+        chains.push(Chain { name: name.to_string(), links });
+        Var::Chain(chains.len() as u32 - 1)
+    }
+
+    // Candidates for `name` in the function at `level`, innermost first, ending with a
+    // global unless one is certain to be declared. For an enclosing function the code
+    // compiled so far is where the closure is created, and declarations still to come
+    // may run before the closure is called.
+    fn lookup(&mut self, level: usize, name: &str, at_use: bool) -> (Vec<Var>, bool) {
+        let mut links = Vec::new();
+        for scope in self.functions[level].scopes.iter().rev() {
+            let Some(binding) = scope.bindings.get(name) else { continue };
+            if binding.dynamic {
+                links.push(binding.var);
+            } else if binding.declared {
+                links.push(binding.var);
+                return (links, true);
+            } else if !at_use {
+                links.push(binding.var);
+            }
+        }
+        if level == 0 {
+            links.push(Var::Global(self.global(name)));
+            return (links, false);
+        }
+        let (outer, definite) = self.lookup(level - 1, name, false);
+        for var in outer {
+            links.push(match var {
+                Var::Global(_) => var,
+                source => Var::Capture(self.capture(level, source)),
+            });
+        }
+        (links, definite)
+    }
+
+    fn capture(&mut self, level: usize, source: Var) -> u32 {
+        let captures = &mut self.functions[level].captures;
+        match captures.iter().position(|&var| var == source) {
+            Some(index) => index as u32,
+            None => {
+                captures.push(source);
+                captures.len() as u32 - 1
+            }
+        }
+    }
+
+    // Statements
+
+    // Statements of a program or block, leaving the value of the last one
+    fn statements(&mut self, statements: &'a [AstNode], span: Span) {
+        for stmt in statements {
+            if let NodeKind::FunctionDefinition { name, parameters, body } = &stmt.kind {
+                let function = self.function(stmt, parameters, body);
+                self.emit(Op::Closure(function), stmt.span);
+                let var = self.declare(name);
+                self.emit(Op::Define(var), stmt.span);
+                self.mark_declared(name);
+            }
+        }
+        match statements.split_last() {
+            Some((last, rest)) => {
+                for stmt in rest {
+                    self.discarded(stmt);
+                }
+                self.node(last);
+            }
+            None => self.emit(Op::Null, span),
+        }
+    }
+
+    // Statement whose value is not used
+    fn discarded(&mut self, node: &'a AstNode) {
+        match &node.kind {
+            NodeKind::LetStatement { name, value } => self.let_statement(name, value, node.span),
+            NodeKind::FunctionDefinition { name, parameters, body } => {
+                let function = self.function(node, parameters, body);
+                self.emit(Op::Closure(function), node.span);
+                let var = self.declare(name);
+                self.emit(Op::Define(var), node.span);
+                self.mark_declared(name);
+            }
+            _ => {
+                self.node(node);
+                self.emit(Op::Pop, node.span);
+            }
+        }
+    }
+
+    fn let_statement(&mut self, name: &str, value: &'a AstNode, span: Span) {
+        self.expr(value);
+        let var = self.declare(name);
+        self.emit(Op::Define(var), span);
+        self.mark_declared(name);
+    }
+
+    fn block(&mut self, statements: &'a [AstNode], span: Span) {
+        let mut names = Vec::new();
+        for stmt in statements {
+            declarations(stmt, true, &mut names);
+        }
+        self.enter_scope(&names, span);
+        self.statements(statements, span);
+        self.exit_scope();
+    }
+
+    // Value of an expression, where `return`, `break` and `continue` end the
+    // expression instead of the statement around it
+    fn expr(&mut self, node: &'a AstNode) {
+        let depth = self.f().depth;
+        self.f().exits.push(Exit::Expr { end: None, depth });
+        self.node(node);
+        if let Some(Exit::Expr { end: Some(end), .. }) = self.f().exits.pop() {
+            self.bind(end);
+        }
+    }
+
+    // Leaves through the enclosing loops, handlers and `finally` blocks for a
+    // `break`, `continue` or `return`, whose value is on top for a return
+    fn jump(&mut self, jump: Jump, span: Span) {
+        let mut i = self.f().exits.len();
+        while i > 0 {
+            i -= 1;
+            match self.f().exits[i] {
+                Exit::Loop { break_label, break_depth, continue_label, continue_depth } => {
+                    let (label, depth) = match jump {
+                        Jump::Break => (break_label, break_depth),
+                        Jump::Continue => (continue_label, continue_depth),
+                        Jump::Return => continue,
+                    };
+                    self.emit(Op::Truncate(depth), span);
+                    self.emit(Op::Jump(label), span);
+                    return;
+                }
+                Exit::Expr { end, depth } => {
+                    let end = match end {
+                        Some(end) => end,
+                        None => {
+                            let end = self.label();
+                            self.f().exits[i] = Exit::Expr { end: Some(end), depth };
+                            end
+                        }
+                    };
+                    if jump == Jump::Return {
+                        self.emit(Op::Unwind(depth), span);
+                    } else {
+                        self.emit(Op::Truncate(depth), span);
+                        self.emit(Op::Null, span);
+                    }
+                    self.emit(Op::Jump(end), span);
+                    return;
+                }
+                Exit::Handler => self.emit(Op::PopHandler, span),
+                Exit::Pending => self.emit(Op::DropPending, span),
+                Exit::Finally { body, scopes, next_slot, next_cell } => {
+                    // The block runs with the scopes of its `try`, and jumps inside it
+                    // only see the exits around the `try`
+                    let f = self.f();
+                    let inner_exits = f.exits.split_off(i);
+                    let inner_scopes = f.scopes.split_off(scopes);
+                    let (slot, cell) = (f.next_slot, f.next_cell);
+                    f.next_slot = next_slot;
+                    f.next_cell = next_cell;
+                    self.node(body);
+                    self.emit(Op::Pop, body.span);
+                    let f = self.f();
+                    f.exits.extend(inner_exits);
+                    f.scopes.extend(inner_scopes);
+                    f.next_slot = slot;
+                    f.next_cell = cell;
+                }
+            }
+        }
+        match jump {
+            Jump::Return => self.emit(Op::Return, span),
+            _ if self.functions.len() > 1 => self.emit(Op::EscapeFunction, span),
+            Jump::Break => self.fail(LangError::new(ErrorCode::InvalidControlFlow, "break statement outside of loop"), span),
+            Jump::Continue => self.fail(LangError::new(ErrorCode::InvalidControlFlow, "continue statement outside of loop"), span),
+        }
+    }
+
+    fn function(&mut self, node: &'a AstNode, parameters: &[String], body: &'a AstNode) -> u32 {
+        let key = node as *const AstNode;
+        let proto = match self.protos.get(&key) {
+            Some(proto) => proto.clone(),
+            None => {
+                let proto = Rc::new(self.compile_function(parameters, body));
+                self.protos.insert(key, proto.clone());
+                proto
+            }
+        };
+        let functions = &mut self.f().chunk.functions;
+        functions.push(proto);
+        functions.len() as u32 - 1
+    }
+
+    fn compile_function(&mut self, parameters: &[String], body: &'a AstNode) -> Proto {
+        self.functions.push(Function::new(parameters.to_vec(), captured_names(body)));
+
+        // Parameters share a scope with the declarations of a body that is not a block
+        let mut names = Vec::new();
+        declarations(body, true, &mut names);
+        names.retain(|(name, _)| !parameters.iter().any(|p| p == name));
+        self.enter_scope(&names, body.span);
+        for (slot, name) in parameters.iter().enumerate() {
+            let f = self.f();
+            let var = if f.captured.contains(name) {
+                f.next_cell += 1;
+                f.cell_count = f.cell_count.max(f.next_cell);
+                let cell = f.next_cell - 1;
+                self.emit(Op::ParamToCell(slot as u32, cell), body.span);
+                Var::Cell(cell)
+            } else {
+                Var::Local(slot as u32)
+            };
+            let binding = Binding { var, dynamic: false, declared: true };
+            self.f().scopes[0].bindings.insert(name.clone(), binding);
+        }
+        let f = self.f();
+        f.self_var = f.scopes[0].bindings.get("self").map(|binding| binding.var);
+
+        self.node(body);
+        self.emit(Op::Return, body.span);
+        self.finish_function()
+    }
+
+    // Compiles any node, leaving exactly one value on the stack
+    // Past the nesting limit a node compiles to the error the tree-walker would raise on
+    // reaching it, which also keeps this recursion within the stack
+    fn node(&mut self, node: &'a AstNode) {
+        if self.nesting >= self.max_nesting {
+            let depth = self.f().depth;
+            self.raise(depth_exceeded("nesting depth", self.max_nesting), node.span);
+            self.set_depth(depth + 1);
+            return;
+        }
+        self.nesting += 1;
+        self.node_kind(node);
+        self.nesting -= 1;
+    }
+
+    fn node_kind(&mut self, node: &'a AstNode) {
+        let span = node.span;
+        let depth = self.f().depth;
+        match &node.kind {
+            NodeKind::Program(statements) => self.statements(statements, span),
+            NodeKind::BlockStatement(statements) => self.block(statements, span),
+
+            NodeKind::Integer(n) => self.constant(Object::Integer(n.clone()), span),
+            NodeKind::Number(_) | NodeKind::String(_) | NodeKind::Boolean(_) => match literal(node) {
+                Ok(value) => self.constant(value, span),
+                Err(err) => {
+                    self.raise(err, span);
+                    self.set_depth(depth + 1);
+                }
+            },
+            NodeKind::Null => self.emit(Op::Null, span),
+            NodeKind::InterpolatedString(parts) => {
+                for part in parts {
+                    match part {
+                        TemplatePart::Literal(s) => self.constant(Object::String(s.clone()), span),
+                        TemplatePart::Expression(expr) => self.expr(expr),
+                    }
+                }
+                self.emit(Op::Concat(parts.len() as u32), span);
+            }
+            NodeKind::Identifier(name) => {
+                let var = self.resolve(name);
+                self.emit(Op::Load(var), span);
+            }
+
+            NodeKind::Array(elements) => {
+                for element in elements {
+                    self.expr(element);
+                }
+                self.emit(Op::Array(elements.len() as u32), span);
+            }
+            NodeKind::MapLiteral(entries) => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.emit(Op::CheckKey, key.span);
+                    self.expr(value);
+                }
+                self.emit(Op::Map(entries.len() as u32), span);
+            }
+            NodeKind::ArrayAccess { array, index } => {
+                if !self.read_path(node) {
+                    self.expr(array);
+                    self.expr(index);
+                    self.emit(Op::Index, span);
+                }
+            }
+            NodeKind::FieldAccess { object, field } => {
+                if !self.read_path(node) {
+                    self.expr(object);
+                    let name = self.name(field);
+                    self.emit(Op::Field(name), span);
+                }
+            }
+            NodeKind::SafeArrayAccess { array, index } => {
+                let end = self.label();
+                self.expr(array);
+                self.emit(Op::JumpIfNullKeep(end), span);
+                self.expr(index);
+                self.emit(Op::SafeIndex, span);
+                self.bind(end);
+            }
+            NodeKind::Slice { array, start, end, step } => {
+                self.expr(array);
+                let mut flags = 0;
+                for (bit, part) in [start, end, step].into_iter().enumerate() {
+                    if let Some(part) = part {
+                        self.expr(part);
+                        flags |= 1 << bit;
+                    }
+                }
+                self.emit(Op::Slice(flags), span);
+            }
+
+            NodeKind::LetStatement { name, value } => {
+                self.let_statement(name, value, span);
+                self.emit(Op::Null, span);
+            }
+
+            NodeKind::IfStatement { condition, then_branch, else_branch } => {
+                let (otherwise, end) = (self.label(), self.label());
+                self.expr(condition);
+                self.emit(Op::JumpIfFalse(otherwise), span);
+                self.node(then_branch);
+                self.emit(Op::Jump(end), span);
+                self.bind(otherwise);
+                self.set_depth(depth);
+                match else_branch {
+                    Some(else_branch) => self.node(else_branch),
+                    None => self.emit(Op::Null, span),
+                }
+                self.bind(end);
+            }
+
+            NodeKind::WhileStatement { condition, body } => {
+                let (top, exit) = (self.label(), self.label());
+                self.emit(Op::Null, span);
+                self.bind(top);
+                self.expr(condition);
+                self.emit(Op::JumpIfFalse(exit), span);
+                self.loop_body(body, exit, top, depth + 1);
+                self.emit(Op::Jump(top), span);
+                self.bind(exit);
+            }
+
+            NodeKind::ForStatement { init, condition, increment, body } => {
+                let (top, next, exit) = (self.label(), self.label(), self.label());
+                let mut names = Vec::new();
+                declarations(init, true, &mut names);
+                declarations(body, false, &mut names);
+                self.enter_scope(&names, span);
+                self.expr(init);
+                self.emit(Op::Pop, span);
+                self.emit(Op::Null, span);
+                self.bind(top);
+                self.expr(condition);
+                self.emit(Op::JumpIfFalse(exit), span);
+                self.loop_body(body, exit, next, depth + 1);
+                self.bind(next);
+                self.expr(increment);
+                self.emit(Op::Pop, span);
+                self.emit(Op::Jump(top), span);
+                self.bind(exit);
+                self.exit_scope();
+            }
+
+            NodeKind::ForInStatement { variables, iterable, body } => {
+                let (top, exit, done) = (self.label(), self.label(), self.label());
+                let pair = variables.len() == 2;
+                self.emit(Op::Null, span);
+                match &iterable.kind {
+                    NodeKind::Range { .. } if pair => {
+                        self.raise(LangError::new(ErrorCode::TypeMismatch,
+                            "Range items cannot be destructured into two variables"), iterable.span);
+                        self.set_depth(depth + 2);
+                        self.bind(top);
+                        self.emit(Op::IterNext(exit, pair), span);
+                    }
+                    // Ranges are walked with a cursor so `for i in 0..n` never builds the array
+                    NodeKind::Range { start, end, inclusive } => {
+                        self.expr(start);
+                        self.emit(Op::RangeBound, start.span);
+                        self.expr(end);
+                        self.emit(Op::RangeBound, end.span);
+                        self.emit(Op::RangeStart(*inclusive), span);
+                        self.bind(top);
+                        self.emit(Op::RangeNext(exit), span);
+                    }
+                    _ => {
+                        self.expr(iterable);
+                        self.emit(Op::IterPrepare(pair), iterable.span);
+                        self.bind(top);
+                        self.emit(Op::IterNext(exit, pair), span);
+                    }
+                }
+                let state_depth = self.f().depth - variables.len() as u32;
+
+                // Each iteration gets its own scope, so closures capture that iteration's values
+                let mut names: Vec<(&str, bool)> = variables.iter().map(|name| (name.as_str(), true)).collect();
+                declarations(body, true, &mut names);
+                self.enter_scope(&names, span);
+                for name in variables {
+                    let var = self.declare(name);
+                    self.emit(Op::Define(var), span);
+                }
+                for name in variables {
+                    self.mark_declared(name);
+                }
+                self.f().exits.push(Exit::Loop {
+                    break_label: done,
+                    break_depth: depth + 1,
+                    continue_label: top,
+                    continue_depth: state_depth,
+                });
+                self.node(body);
+                self.f().exits.pop();
+                self.emit(Op::Replace(depth), span);
+                self.exit_scope();
+                self.emit(Op::Jump(top), span);
+                self.bind(exit);
+                self.set_depth(state_depth);
+                self.emit(Op::Truncate(depth + 1), span);
+                self.bind(done);
+            }
+
+            NodeKind::Range { start, end, inclusive } => {
+                self.expr(start);
+                self.emit(Op::RangeBound, start.span);
+                self.expr(end);
+                self.emit(Op::RangeBound, end.span);
+                self.emit(Op::MakeRange(*inclusive), span);
+            }
+
+            NodeKind::FunctionDefinition { name, parameters, body } => {
+                let function = self.function(node, parameters, body);
+                self.emit(Op::Closure(function), span);
+                self.emit(Op::Dup, span);
+                let var = self.declare(name);
+                self.emit(Op::Define(var), span);
+                self.mark_declared(name);
+            }
+            NodeKind::FunctionLiteral { parameters, body } => {
+                let function = self.function(node, parameters, body);
+                self.emit(Op::Closure(function), span);
+            }
+
+            NodeKind::StructDefinition { name, fields } => {
+                self.type_definition(name, TypeDef::Struct { name: name.clone(), fields: fields.clone() }, span);
+            }
+            NodeKind::EnumDefinition { name, variants } => {
+                self.type_definition(name, TypeDef::Enum { name: name.clone(), variants: variants.clone() }, span);
+            }
+            NodeKind::ImplBlock { name, methods } => {
+                let target = self.resolve(name);
+                let mut names = Vec::new();
+                for method in methods {
+                    if let NodeKind::FunctionDefinition { name, parameters, body } = &method.kind {
+                        let function = self.function(method, parameters, body);
+                        self.emit(Op::Closure(function), method.span);
+                        names.push(name.clone());
+                    }
+                }
+                let impls = &mut self.f().chunk.impls;
+                impls.push(ImplDef { name: name.clone(), methods: names });
+                let index = impls.len() as u32 - 1;
+                self.emit(Op::Impl(target, index), span);
+            }
+
+            NodeKind::Match { subject, arms } => {
+                let end = self.label();
+                self.expr(subject);
+                for arm in arms {
+                    let next = self.label();
+                    let mut bindings = Vec::new();
+                    let pattern = self.pattern(&arm.pattern, &mut bindings);
+                    let patterns = &mut self.f().chunk.patterns;
+                    patterns.push(pattern);
+                    let index = patterns.len() as u32 - 1;
+                    self.emit(Op::Pattern(index, next), span);
+
+                    // Bindings get their own scope so they never leak out of the arm
+                    let mut names: Vec<(&str, bool)> = bindings.iter().map(|name| (*name, true)).collect();
+                    declarations(&arm.body, true, &mut names);
+                    self.enter_scope(&names, span);
+                    for name in &bindings {
+                        let var = self.declare(name);
+                        self.emit(Op::Define(var), span);
+                    }
+                    for name in &bindings {
+                        self.mark_declared(name);
+                    }
+                    if let Some(guard) = &arm.guard {
+                        self.expr(guard);
+                        self.emit(Op::JumpIfFalse(next), guard.span);
+                    }
+                    self.node(&arm.body);
+                    self.emit(Op::Replace(depth), span);
+                    self.emit(Op::Jump(end), span);
+                    self.exit_scope();
+                    self.bind(next);
+                    self.set_depth(depth + 1);
+                }
+                self.emit(Op::NoMatch, span);
+                self.bind(end);
+            }
+
+            NodeKind::FunctionCall { function, arguments } => match &function.kind {
+                NodeKind::FieldAccess { object, field } => self.method_call(object, field, arguments, span),
+                _ => {
+                    let name = match &function.kind {
+                        NodeKind::Identifier(name) => {
+                            let var = self.resolve(name);
+                            self.emit(Op::LoadCallee(var), span);
+                            Some(self.name(name))
+                        }
+                        _ => {
+                            self.expr(function);
+                            None
+                        }
+                    };
+                    for arg in arguments {
+                        self.expr(arg);
+                    }
+                    self.emit(Op::Call(arguments.len() as u32, name), span);
+                }
+            },
+
+            NodeKind::ReturnStatement { value } => {
+                match value {
+                    Some(value) => self.expr(value),
+                    None => self.emit(Op::Null, span),
+                }
+                self.jump(Jump::Return, span);
+                self.set_depth(depth + 1);
+            }
+            NodeKind::BreakStatement => {
+                self.jump(Jump::Break, span);
+                self.set_depth(depth + 1);
+            }
+            NodeKind::ContinueStatement => {
+                self.jump(Jump::Continue, span);
+                self.set_depth(depth + 1);
+            }
+            NodeKind::ThrowExpression { value } => {
+                self.expr(value);
+                self.emit(Op::Throw, span);
+                self.set_depth(depth + 1);
+            }
+            NodeKind::TryStatement { body, catch_variable, catch_body, finally_body } => {
+                self.try_statement(body, catch_variable.as_deref(), catch_body.as_deref(), finally_body.as_deref());
+            }
+
+            NodeKind::PrefixExpression { op, right } => {
+                self.expr(right);
+                let op = self.operator(op);
+                self.emit(Op::Prefix(op), span);
+            }
+            NodeKind::InfixExpression { op, left, right } => {
+                self.expr(left);
+                self.expr(right);
+                let op = self.operator(op);
+                self.emit(Op::Infix(op), span);
+            }
+            NodeKind::Conditional { condition, consequence, alternative } => {
+                let (otherwise, end) = (self.label(), self.label());
+                self.expr(condition);
+                self.emit(Op::JumpIfFalse(otherwise), span);
+                self.expr(consequence);
+                self.emit(Op::Jump(end), span);
+                self.bind(otherwise);
+                self.set_depth(depth);
+                self.expr(alternative);
+                self.bind(end);
+            }
+            // Returns whichever operand decided the result
+            NodeKind::LogicalExpression { op, left, right } => {
+                let end = self.label();
+                self.expr(left);
+                let short_circuit = match op {
+                    Token::And => Op::JumpIfFalseKeep(end),
+                    Token::Or => Op::JumpIfTrueKeep(end),
+                    Token::NullCoalesce => Op::JumpIfNotNullKeep(end),
+                    _ => {
+                        self.emit(Op::Pop, span);
+                        self.raise(LangError::new(ErrorCode::UnknownOperator,
+                            format!("Unknown logical operator: {:?}", op)), span);
+                        self.set_depth(depth + 1);
+                        return;
+                    }
+                };
+                self.emit(short_circuit, span);
+                self.expr(right);
+                self.bind(end);
+            }
+
+            NodeKind::Assignment { target, op, value } => {
+                self.expr(value);
+                let mut steps = Vec::new();
+                let mut place = &**target;
+                // Index keys are evaluated from the innermost access outwards
+                let root = loop {
+                    match &place.kind {
+                        NodeKind::Identifier(name) => {
+                            break Root::Var { var: self.resolve(name), name: name.clone(), span: place.span };
+                        }
+                        NodeKind::ArrayAccess { array, index } => {
+                            self.expr(index);
+                            steps.push(Step::Index(place.span));
+                            place = array;
+                        }
+                        NodeKind::FieldAccess { object, field } => {
+                            steps.push(Step::Field(field.clone(), place.span));
+                            place = object;
+                        }
+                        _ => break Root::Invalid(place.span),
+                    }
+                };
+                if let Root::Invalid(target_span) = root {
+                    self.raise(LangError::new(ErrorCode::InvalidAssignmentTarget, "Invalid assignment target")
+                        .at(target_span), span);
+                    self.set_depth(depth + 1);
+                    return;
+                }
+                steps.reverse();
+                let paths = &mut self.f().chunk.paths;
+                paths.push(Path { root, steps, op: op.clone() });
+                let index = paths.len() as u32 - 1;
+                self.emit(Op::Assign(index), span);
+            }
+        }
+    }
+
+    // Loop body whose value becomes the loop's result, at `result_depth - 1`
+    fn loop_body(&mut self, body: &'a AstNode, exit: u32, next: u32, result_depth: u32) {
+        self.f().exits.push(Exit::Loop {
+            break_label: exit,
+            break_depth: result_depth,
+            continue_label: next,
+            continue_depth: result_depth,
+        });
+        self.node(body);
+        self.f().exits.pop();
+        self.emit(Op::Replace(result_depth - 1), body.span);
+    }
+
+    fn type_definition(&mut self, name: &str, definition: TypeDef, span: Span) {
+        let types = &mut self.f().chunk.types;
+        types.push(definition);
+        let index = types.len() as u32 - 1;
+        self.emit(Op::MakeType(index), span);
+        let var = self.declare(name);
+        self.emit(Op::Define(var), span);
+        self.mark_declared(name);
+        self.emit(Op::Null, span);
+    }
+
+    // Reads an element or field of a variable in place, without copying the
+    // containers on the way. Keys must not change the variable while it is read.
+    fn read_path(&mut self, node: &'a AstNode) -> bool {
+        let mut accesses = Vec::new();
+        let mut place = node;
+        let name = loop {
+            match &place.kind {
+                NodeKind::Identifier(name) => break name,
+                NodeKind::ArrayAccess { array, index } if is_pure(index) => {
+                    accesses.push(place);
+                    place = array;
+                }
+                NodeKind::FieldAccess { object, .. } => {
+                    accesses.push(place);
+                    place = object;
+                }
+                _ => return false,
+            }
+        };
+        // The tree-walker nests once per access; past the limit the accesses are
+        // compiled one by one so the error comes from the same node
+        if self.nesting + accesses.len() > self.max_nesting {
+            return false;
+        }
+        let root = Root::Var { var: self.resolve(name), name: name.clone(), span: place.span };
+        let steps = self.path_steps(&accesses);
+        let paths = &mut self.f().chunk.paths;
+        paths.push(Path { root, steps, op: None });
+        let index = paths.len() as u32 - 1;
+        self.emit(Op::LoadPath(index), node.span);
+        true
+    }
+
+    // Steps from the root outwards for accesses listed innermost last, pushing index keys
+    fn path_steps(&mut self, accesses: &[&'a AstNode]) -> Vec<Step> {
+        let mut steps = Vec::new();
+        for access in accesses.iter().rev() {
+            match &access.kind {
+                NodeKind::ArrayAccess { index, .. } => {
+                    self.expr(index);
+                    steps.push(Step::Index(access.span));
+                }
+                NodeKind::FieldAccess { field, .. } => steps.push(Step::Field(field.clone(), access.span)),
+                _ => unreachable!("only accesses are collected"),
+            }
+        }
+        steps
+    }
+
+    // `receiver.name(args)`. A receiver that is a variable, element or field gets the
+    // new `self` written back when the method changes it.
+    fn method_call(&mut self, object: &'a AstNode, field: &str, arguments: &'a [AstNode], span: Span) {
+        let mut accesses = Vec::new();
+        let mut place = object;
+        let root = loop {
+            match &place.kind {
+                NodeKind::Identifier(name) => break Some(name),
+                NodeKind::ArrayAccess { array, .. } => {
+                    accesses.push(place);
+                    place = array;
+                }
+                NodeKind::FieldAccess { object, .. } => {
+                    accesses.push(place);
+                    place = object;
+                }
+                _ => break None,
+            }
+        };
+        // The receiver is one level below the call, and each access one more
+        let path = match root {
+            Some(name) if self.nesting + 1 + accesses.len() <= self.max_nesting => {
+                let root = Root::Var { var: self.resolve(name), name: name.clone(), span: place.span };
+                let steps = self.path_steps(&accesses);
+                let paths = &mut self.f().chunk.paths;
+                paths.push(Path { root, steps, op: None });
+                let index = paths.len() as u32 - 1;
+                self.emit(Op::Receiver(index), object.span);
+                Some(index)
+            }
+            _ => {
+                self.expr(object);
+                if matches!(object.kind, NodeKind::ArrayAccess { .. } | NodeKind::FieldAccess { .. }) {
+                    let paths = &mut self.f().chunk.paths;
+                    paths.push(Path { root: Root::Invalid(place.span), steps: Vec::new(), op: None });
+                    Some(paths.len() as u32 - 1)
+                } else {
+                    None
+                }
+            }
+        };
+        for arg in arguments {
+            self.expr(arg);
+        }
+        let methods = &mut self.f().chunk.methods;
+        methods.push(MethodCall { name: field.to_string(), argc: arguments.len(), path });
+        let index = methods.len() as u32 - 1;
+        self.emit(Op::CallMethod(index), span);
+    }
+
+    // Handlers for `catch` and `finally` are pushed before the body runs. An error
+    // the body or catch block raises runs the `finally` block and then continues;
+    // leaving through `return`, `break` or `continue` runs a copy of it on the way out.
+    fn try_statement(
+        &mut self,
+        body: &'a AstNode,
+        catch_variable: Option<&'a str>,
+        catch_body: Option<&'a AstNode>,
+        finally_body: Option<&'a AstNode>,
+    ) {
+        let span = body.span;
+        let depth = self.f().depth;
+        let on_error = self.label();
+        if let Some(finally_body) = finally_body {
+            let f = self.f();
+            let (scopes, next_slot, next_cell) = (f.scopes.len(), f.next_slot, f.next_cell);
+            f.exits.push(Exit::Finally { body: finally_body, scopes, next_slot, next_cell });
+            self.emit(Op::PushHandler(HandlerKind::Finally, on_error), span);
+            self.f().exits.push(Exit::Handler);
+        }
+
+        match catch_body {
+            Some(catch_body) => {
+                let (caught, after) = (self.label(), self.label());
+                self.emit(Op::PushHandler(HandlerKind::Catch, caught), span);
+                self.f().exits.push(Exit::Handler);
+                self.node(body);
+                self.f().exits.pop();
+                self.emit(Op::PopHandler, span);
+                self.emit(Op::Jump(after), span);
+
+                self.bind(caught);
+                self.set_depth(depth + 1);
+                let names: Vec<(&str, bool)> = catch_variable.iter().map(|name| (*name, true)).collect();
+                self.enter_scope(&names, catch_body.span);
+                match catch_variable {
+                    Some(name) => {
+                        let var = self.declare(name);
+                        self.emit(Op::Define(var), catch_body.span);
+                        self.mark_declared(name);
+                    }
+                    None => self.emit(Op::Pop, catch_body.span),
+                }
+                self.node(catch_body);
+                self.exit_scope();
+                self.bind(after);
+            }
+            None => self.node(body),
+        }
+
+        if let Some(finally_body) = finally_body {
+            let end = self.label();
+            let exits = &mut self.f().exits;
+            exits.truncate(exits.len() - 2);
+            self.emit(Op::PopHandler, span);
+            self.node(finally_body);
+            self.emit(Op::Pop, finally_body.span);
+            self.emit(Op::Jump(end), span);
+
+            self.bind(on_error);
+            self.set_depth(depth);
+            self.f().exits.push(Exit::Pending);
+            self.node(finally_body);
+            self.emit(Op::Pop, finally_body.span);
+            self.f().exits.pop();
+            self.emit(Op::Rethrow, finally_body.span);
+            self.bind(end);
+            self.set_depth(depth + 1);
+        }
+    }
+
+    // Pattern with its literals evaluated; names it binds are added to `bindings`
+    // in the order the match pushes their values
+    fn pattern(&mut self, pattern: &'a Pattern, bindings: &mut Vec<&'a str>) -> CompiledPattern {
+        match pattern {
+            Pattern::Wildcard => CompiledPattern::Wildcard,
+            Pattern::Binding(name) => {
+                bindings.push(name);
+                CompiledPattern::Binding
+            }
+            Pattern::Literal(node) => match literal(node) {
+                Ok(value) => CompiledPattern::Literal(value),
+                Err(err) => CompiledPattern::Invalid(err.at(node.span)),
+            },
+            Pattern::Array { prefix, rest, suffix } => {
+                let prefix = prefix.iter().map(|p| self.pattern(p, bindings)).collect();
+                let suffix = suffix.iter().map(|p| self.pattern(p, bindings)).collect();
+                let rest = rest.as_ref().map(|p| Box::new(self.pattern(p, bindings)));
+                CompiledPattern::Array { prefix, rest, suffix }
+            }
+            Pattern::Variant { enum_name, variant, fields } => CompiledPattern::Variant {
+                enum_var: self.resolve(enum_name),
+                enum_name: enum_name.clone(),
+                variant: variant.clone(),
+                fields: fields.iter().map(|p| self.pattern(p, bindings)).collect(),
+            },
+        }
+    }
+}
+
+pub fn pattern_bindings(pattern: &CompiledPattern) -> usize {
+    match pattern {
+        CompiledPattern::Binding => 1,
+        CompiledPattern::Array { prefix, rest, suffix } => {
+            prefix.iter().chain(suffix).chain(rest.as_deref()).map(pattern_bindings).sum()
+        }
+        CompiledPattern::Variant { fields, .. } => fields.iter().map(pattern_bindings).sum(),
+        _ => 0,
+    }
+}
+
+// Value of a literal node
+fn literal(node: &AstNode) -> Result<Object, LangError> {
+    match &node.kind {
+        NodeKind::Integer(n) => Ok(Object::Integer(n.clone())),
+        NodeKind::Number(n) if n.is_infinite() || n.is_nan() => {
+            Err(LangError::new(ErrorCode::ArithmeticError, "Invalid number: infinity or NaN"))
+        }
+        NodeKind::Number(n) => Ok(Object::Number(*n)),
+        NodeKind::String(s) => Ok(Object::String(s.clone())),
+        NodeKind::Boolean(b) => Ok(Object::Boolean(*b)),
+        _ => Ok(Object::Null),
+    }
+}
+
+// Keys that read variables and constants only, so evaluating them cannot change
+// the container they index
+fn is_pure(node: &AstNode) -> bool {
+    match &node.kind {
+        NodeKind::Integer(_) | NodeKind::Number(_) | NodeKind::String(_) | NodeKind::Boolean(_) |
+        NodeKind::Null | NodeKind::Identifier(_) => true,
+        NodeKind::InfixExpression { left, right, .. } => is_pure(left) && is_pure(right),
+        NodeKind::PrefixExpression { right, .. } => is_pure(right),
+        NodeKind::FieldAccess { object, .. } => is_pure(object),
+        NodeKind::ArrayAccess { array, index } => is_pure(array) && is_pure(index),
+        _ => false,
+    }
+}
+
+// Names a statement declares in the scope it runs in. `direct` is false inside
+// branches and loop bodies, where the declaration may run any number of times.
+fn declarations<'n>(node: &'n AstNode, direct: bool, names: &mut Vec<(&'n str, bool)>) {
+    match &node.kind {
+        NodeKind::LetStatement { name, .. } | NodeKind::FunctionDefinition { name, .. } |
+        NodeKind::StructDefinition { name, .. } | NodeKind::EnumDefinition { name, .. } => {
+            names.push((name, direct));
+        }
+        NodeKind::IfStatement { then_branch, else_branch, .. } => {
+            declarations(then_branch, false, names);
+            if let Some(else_branch) = else_branch {
+                declarations(else_branch, false, names);
+            }
+        }
+        NodeKind::WhileStatement { body, .. } => declarations(body, false, names),
+        _ => {}
+    }
+}
+
+// Names used inside functions nested in `body`, which must live in cells
+// so the closures share them with the code that declares them
+fn captured_names(body: &AstNode) -> HashSet<String> {
+    let mut names = HashSet::new();
+    visit(body, &mut |node| {
+        if let NodeKind::FunctionDefinition { body, .. } | NodeKind::FunctionLiteral { body, .. } = &node.kind {
+            visit(body, &mut |inner| referenced_names(inner, &mut names));
+        }
+    });
+    names
+}
+
+fn referenced_names(node: &AstNode, names: &mut HashSet<String>) {
+    match &node.kind {
+        NodeKind::Identifier(name) | NodeKind::ImplBlock { name, .. } => {
+            names.insert(name.clone());
+        }
+        NodeKind::Match { arms, .. } => {
+            for arm in arms {
+                pattern_enums(&arm.pattern, names);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn pattern_enums(pattern: &Pattern, names: &mut HashSet<String>) {
+    match pattern {
+        Pattern::Array { prefix, rest, suffix } => {
+            for pattern in prefix.iter().chain(suffix).chain(rest.as_deref()) {
+                pattern_enums(pattern, names);
+            }
+        }
+        Pattern::Variant { enum_name, fields, .. } => {
+            names.insert(enum_name.clone());
+            for field in fields {
+                pattern_enums(field, names);
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,326 @@
+use super::object::{EnumType, Object, StructType};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+// One lexical scope: its own bindings plus a link to the enclosing scope
+#[derive(Default)]
+struct Scope {
+    values: HashMap<String, Object>,
+    parent: Option<Environment>,
+}
+
+thread_local! {
+    // Scopes that were still reachable from outside when closed, but also hold
+    // functions that close over them; see `release_escaped`
+    static ESCAPED: RefCell<Vec<Weak<RefCell<Scope>>>> = const { RefCell::new(Vec::new()) };
+    // Approximate heap bytes held by the bindings of every live scope on this thread
+    static BOUND_BYTES: Cell<usize> = const { Cell::new(0) };
+}
+
+// Clears every escaped scope that is still alive. Called once a run is over, after
+// the global scope is cleared, so cycles left behind by returned closures are freed.
+// Types defined in the scope lose their methods too, since those close over it.
+pub fn release_escaped() {
+    for scope in ESCAPED.with(|escaped| escaped.take()) {
+        if let Some(scope) = scope.upgrade() {
+            for value in scope.borrow().values.values() {
+                match value {
+                    Object::Struct(struct_type) => struct_type.methods.borrow_mut().clear(),
+                    Object::Enum(enum_type) => enum_type.methods.borrow_mut().clear(),
+                    _ => {}
+                }
+            }
+            Environment(scope).clear();
+        }
+    }
+}
+
+// A struct or enum type reached from a scope, whose methods may close over it
+enum DeclaredType {
+    Struct(Rc<StructType>),
+    Enum(Rc<EnumType>),
+}
+
+impl DeclaredType {
+    fn is(&self, other: &DeclaredType) -> bool {
+        match (self, other) {
+            (DeclaredType::Struct(a), DeclaredType::Struct(b)) => Rc::ptr_eq(a, b),
+            (DeclaredType::Enum(a), DeclaredType::Enum(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            DeclaredType::Struct(t) => Rc::strong_count(t),
+            DeclaredType::Enum(t) => Rc::strong_count(t),
+        }
+    }
+
+    fn methods(&self) -> &RefCell<HashMap<String, Object>> {
+        match self {
+            DeclaredType::Struct(t) => &t.methods,
+            DeclaredType::Enum(t) => &t.methods,
+        }
+    }
+}
+
+// Bytes currently held by variables, as measured by `Object::heap_size`
+pub fn bound_bytes() -> usize {
+    BOUND_BYTES.with(Cell::get)
+}
+
+// Records a bound value growing or shrinking from `old` to `new` bytes. Values changed
+// in place through `with_binding` must be reported here by the caller.
+pub fn record_resize(old: usize, new: usize) {
+    BOUND_BYTES.with(|bytes| bytes.set((bytes.get() + new).saturating_sub(old)));
+}
+
+fn release(values: &HashMap<String, Object>) {
+    record_resize(values.values().map(Object::heap_size).sum(), 0);
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        release(&self.values);
+    }
+}
+
+// Shared handle to a scope. Cloning the handle shares the frame, so closures and
+// nested blocks see (and can update) the same variables as the code that created them.
+#[derive(Clone, Default)]
+pub struct Environment(Rc<RefCell<Scope>>);
+
+impl Environment {
+    pub fn new() -> Self {
+        Environment::default()
+    }
+
+    // New empty scope whose lookups fall back to `parent`
+    pub fn enclosed(parent: &Environment) -> Self {
+        Environment(Rc::new(RefCell::new(Scope {
+            values: HashMap::new(),
+            parent: Some(parent.clone()),
+        })))
+    }
+
+    pub fn get(&self, name: &str) -> Option<Object> {
+        let scope = self.0.borrow();
+        match scope.values.get(name) {
+            Some(value) => Some(value.clone()),
+            None => scope.parent.as_ref().and_then(|parent| parent.get(name)),
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        let scope = self.0.borrow();
+        scope.values.contains_key(name)
+            || scope.parent.as_ref().is_some_and(|parent| parent.contains(name))
+    }
+
+    // Declares `name` in this scope, shadowing any outer binding
+    pub fn define(&self, name: String, value: Object) {
+        let size = value.heap_size();
+        let replaced = self.0.borrow_mut().values.insert(name, value);
+        record_resize(replaced.map_or(0, |old| old.heap_size()), size);
+    }
+
+    // Runs `f` on the nearest existing binding of `name`; None if it is not declared anywhere
+    pub fn with_binding<R>(&self, name: &str, f: impl FnOnce(&mut Object) -> R) -> Option<R> {
+        let mut scope = self.0.borrow_mut();
+        if let Some(slot) = scope.values.get_mut(name) {
+            return Some(f(slot));
+        }
+        let parent = scope.parent.clone();
+        drop(scope);
+        parent.and_then(|parent| parent.with_binding(name, f))
+    }
+
+    pub fn ptr_eq(&self, other: &Environment) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    // Drops every binding in this scope. Functions stored in a scope also hold that
+    // scope as their closure, so the global scope is cleared after a run to break
+    // the reference cycle.
+    pub fn clear(&self) {
+        let values = std::mem::take(&mut self.0.borrow_mut().values);
+        release(&values);
+    }
+
+    // Called by the code that created this scope once it is done with it. If the
+    // only other handles left belong to functions bound in the scope itself, nothing
+    // can reach it any more, so its bindings are dropped to break the cycle.
+    pub fn close(self) {
+        let (own, shared) = self.self_references();
+        if own == 0 && !shared {
+            return;
+        }
+        if Rc::strong_count(&self.0) - 1 == own {
+            self.clear();
+        } else {
+            ESCAPED.with(|escaped| {
+                let mut escaped = escaped.borrow_mut();
+                // Forget scopes that have been freed since, before the list grows
+                if escaped.len() == escaped.capacity() {
+                    escaped.retain(|scope| scope.strong_count() > 0);
+                }
+                escaped.push(Rc::downgrade(&self.0));
+            });
+        }
+    }
+
+    // Handles to this scope held by its own bindings, and whether some type whose
+    // methods close over the scope is also held from outside it
+    fn self_references(&self) -> (usize, bool) {
+        let scope = self.0.borrow();
+        let mut types = Vec::new();
+        let mut own: usize = scope.values.values().map(|value| self.references_in(value, &mut types)).sum();
+        let mut shared = false;
+        for (declared, seen) in types {
+            let methods: usize = declared.methods().borrow().values()
+                .map(|method| self.references_in(method, &mut Vec::new()))
+                .sum();
+            // Methods only count when every handle to their type is in this scope
+            if declared.strong_count() - 1 == seen {
+                own += methods;
+            } else if methods > 0 {
+                shared = true;
+            }
+        }
+        (own, shared)
+    }
+
+    // Handles to this scope held by `value`. Struct and enum types are collected
+    // into `types` with the number of times they were seen, so their methods can
+    // be counted once the whole scope has been walked.
+    fn references_in(&self, value: &Object, types: &mut Vec<(DeclaredType, usize)>) -> usize {
+        let mut see = |declared: DeclaredType| {
+            match types.iter_mut().find(|(seen, _)| seen.is(&declared)) {
+                Some((_, count)) => *count += 1,
+                None => types.push((declared, 1)),
+            }
+        };
+        match value {
+            Object::Function { closure, .. } => usize::from(self.ptr_eq(closure)),
+            Object::Array(items) => items.iter().map(|item| self.references_in(item, types)).sum(),
+            Object::Map(entries) => entries.values().map(|item| self.references_in(item, types)).sum(),
+            Object::Struct(struct_type) => {
+                see(DeclaredType::Struct(struct_type.clone()));
+                0
+            }
+            Object::Enum(enum_type) => {
+                see(DeclaredType::Enum(enum_type.clone()));
+                0
+            }
+            Object::Instance { struct_type, fields } => {
+                see(DeclaredType::Struct(struct_type.clone()));
+                fields.iter().map(|item| self.references_in(item, types)).sum()
+            }
+            Object::Variant { enum_type, values, .. } => {
+                see(DeclaredType::Enum(enum_type.clone()));
+                values.iter().map(|item| self.references_in(item, types)).sum()
+            }
+            _ => 0,
+        }
+    }
+}
+
+// Scopes can reach themselves through closures, so never print their contents
+impl fmt::Debug for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Environment { .. }")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Span;
+    use crate::parser::{AstNode, NodeKind};
+
+    fn function_over(closure: &Environment) -> Object {
+        Object::Function {
+            parameters: Vec::new(),
+            body: AstNode::new(NodeKind::BlockStatement(Vec::new()), Span::default()),
+            closure: closure.clone(),
+        }
+    }
+
+    #[test]
+    fn closing_a_scope_frees_functions_defined_in_it() {
+        let global = Environment::new();
+        let block = Environment::enclosed(&global);
+        block.define("f".to_string(), function_over(&block));
+        let weak = Rc::downgrade(&block.0);
+        block.close();
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn escaped_scopes_are_released_after_the_run() {
+        let global = Environment::new();
+        let block = Environment::enclosed(&global);
+        block.define("f".to_string(), function_over(&block));
+        // The function escapes the block, e.g. by being returned
+        global.define("g".to_string(), block.get("f").unwrap());
+        let weak = Rc::downgrade(&block.0);
+        block.close();
+        assert!(weak.upgrade().is_some());
+
+        global.clear();
+        release_escaped();
+        assert!(weak.upgrade().is_none());
+    }
+
+    fn struct_with_method_over(closure: &Environment) -> Rc<StructType> {
+        let methods = HashMap::from([("make".to_string(), function_over(closure))]);
+        Rc::new(StructType { name: "S".to_string(), fields: Vec::new(), methods: RefCell::new(methods) })
+    }
+
+    #[test]
+    fn closing_a_scope_frees_types_whose_methods_close_over_it() {
+        let global = Environment::new();
+        let block = Environment::enclosed(&global);
+        block.define("S".to_string(), Object::Struct(struct_with_method_over(&block)));
+        let weak = Rc::downgrade(&block.0);
+        block.close();
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn scopes_whose_types_escape_are_released_after_the_run() {
+        let global = Environment::new();
+        let block = Environment::enclosed(&global);
+        let struct_type = struct_with_method_over(&block);
+        block.define("S".to_string(), Object::Struct(struct_type.clone()));
+        // An instance outlives the block, so its methods must keep working
+        global.define("s".to_string(), Object::Instance { struct_type, fields: Vec::new() });
+        let weak = Rc::downgrade(&block.0);
+        block.close();
+        assert!(weak.upgrade().is_some());
+
+        global.clear();
+        release_escaped();
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn closing_a_scope_frees_enums_whose_methods_close_over_it() {
+        let global = Environment::new();
+        let block = Environment::enclosed(&global);
+        let methods = HashMap::from([("name".to_string(), function_over(&block))]);
+        let enum_type = Rc::new(EnumType {
+            name: "E".to_string(),
+            variants: vec![("A".to_string(), Vec::new())],
+            methods: RefCell::new(methods),
+        });
+        block.define("E".to_string(), Object::Enum(enum_type.clone()));
+        block.define("a".to_string(), Object::Variant { enum_type, variant: 0, values: Vec::new() });
+        let weak = Rc::downgrade(&block.0);
+        block.close();
+        assert!(weak.upgrade().is_none());
+    }
+}
@@ -0,0 +1,150 @@
+use super::lexer::Span;
+use serde::Serialize;
+use std::fmt;
+
+// Pipeline stage that rejected or flagged the program
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Lexer,
+    Parser,
+    // Static checks on a program that parsed
+    Checker,
+    Runtime,
+    LimitExceeded,
+}
+
+// Warnings are reported alongside a successful result; errors stop the program
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+// Stable machine-readable codes, so clients never have to match on message text
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    // Lexer
+    UnexpectedCharacter,
+    UnterminatedString,
+    UnterminatedComment,
+    InvalidEscape,
+    InvalidNumber,
+
+    // Parser
+    UnexpectedToken,
+    UnexpectedEof,
+    InvalidAssignmentTarget,
+
+    // Runtime
+    UndefinedVariable,
+    NotCallable,
+    ArgumentCount,
+    TypeMismatch,
+    UnknownOperator,
+    InvalidArgument,
+    DivisionByZero,
+    IndexOutOfBounds,
+    KeyNotFound,
+    UnknownField,
+    NoMatchingArm,
+    UncaughtException,
+    ArithmeticError,
+    InvalidControlFlow,
+
+    // Limits
+    StepLimit,
+    StackOverflow,
+    MemoryLimit,
+
+    // Warnings
+    NonExhaustiveMatch,
+}
+
+impl ErrorCode {
+    pub fn kind(self) -> ErrorKind {
+        match self {
+            ErrorCode::UnexpectedCharacter
+            | ErrorCode::UnterminatedString
+            | ErrorCode::UnterminatedComment
+            | ErrorCode::InvalidEscape
+            | ErrorCode::InvalidNumber => ErrorKind::Lexer,
+
+            ErrorCode::UnexpectedToken
+            | ErrorCode::UnexpectedEof
+            | ErrorCode::InvalidAssignmentTarget => ErrorKind::Parser,
+
+            ErrorCode::NonExhaustiveMatch => ErrorKind::Checker,
+
+            ErrorCode::StepLimit | ErrorCode::StackOverflow | ErrorCode::MemoryLimit => ErrorKind::LimitExceeded,
+
+            _ => ErrorKind::Runtime,
+        }
+    }
+
+    pub fn severity(self) -> Severity {
+        match self {
+            ErrorCode::NonExhaustiveMatch => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+// Error value given to `throw`, kept so a `catch` can bind it unchanged
+#[derive(Debug, Clone, PartialEq)]
+pub struct Thrown {
+    pub kind: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LangError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub span: Option<Span>,
+    pub thrown: Option<Box<Thrown>>,
+}
+
+impl LangError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        LangError { code, message: message.into(), span: None, thrown: None }
+    }
+
+    // Error raised by `throw` in user code
+    pub fn thrown(kind: String, message: String) -> Self {
+        LangError {
+            code: ErrorCode::UncaughtException,
+            message: format!("Uncaught {}: {}", kind, message),
+            span: None,
+            thrown: Some(Box::new(Thrown { kind, message })),
+        }
+    }
+
+    // Only runtime failures can be caught; exceeding a resource limit always ends the program
+    pub fn is_catchable(&self) -> bool {
+        self.kind() == ErrorKind::Runtime
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.code.kind()
+    }
+
+    // Attaches a location unless the error already carries a more precise one
+    pub fn at(mut self, span: Span) -> Self {
+        if self.span.is_none() {
+            self.span = Some(span);
+        }
+        self
+    }
+}
+
+impl fmt::Display for LangError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.span {
+            Some(span) => write!(f, "{} at {}", self.message, span.start),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
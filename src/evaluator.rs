@@ -1,37 +1,206 @@
-use super::parser::AstNode;
-use super::lexer::Token;
-use super::object::{Object, get_builtins};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use super::parser::{AstNode, NodeKind, Pattern, TemplatePart};
+use super::lexer::{Span, Token};
+use super::object::{EnumType, HashKey, Object, StructType, get_builtins};
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, Signed, ToPrimitive, Zero};
+use super::error::{ErrorCode, LangError};
+use super::environment::{bound_bytes, record_resize, Environment};
+use super::vm::Vm;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
 // Removed unused imports: std::io::{self, Write}
 
-pub type Environment = HashMap<String, Object>;
+// Printed output of the run in progress on this thread
+pub fn get_output() -> String {
+    RUN_STATE.with(|state| state.borrow().output.concat())
+}
+
+pub fn add_output(text: &str) {
+    RUN_STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.output_bytes += text.len();
+        state.output.push(text.to_string());
+    });
+}
 
-// Thread-safe output capture
-lazy_static::lazy_static! {
-    static ref OUTPUT_BUFFER: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+// Limits for a single run. Every evaluated node (or bytecode instruction, when the
+// program runs on the VM) costs one step, so the step budget bounds loops and
+// recursion alike. Each level of evaluation also uses native stack, so exceeding
+// the depth limits is reported as a stack overflow instead of crashing.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    // Evaluated nodes or executed instructions before the run is stopped
+    pub max_steps: u64,
+    // Approximate bytes held by variables and printed output
+    pub max_memory: usize,
+    // Nested calls of user functions
+    pub max_call_depth: usize,
+    // Nested statements and expressions, across all active calls. The VM applies it
+    // to the program text when compiling, as it does not recurse while running.
+    pub max_nesting: usize,
+    // Stack available to the evaluator when it runs on a thread of known size. Evaluation
+    // stops short of it even where frames are larger than usual, as in debug builds.
+    pub stack_size: Option<usize>,
 }
 
-pub fn clear_output() {
-    if let Ok(mut buffer) = OUTPUT_BUFFER.lock() {
-        buffer.clear();
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_steps: 10_000_000,
+            max_memory: 64 * 1024 * 1024,
+            max_call_depth: 1000,
+            max_nesting: 5000,
+            stack_size: None,
+        }
     }
 }
 
-pub fn get_output() -> String {
-    if let Ok(buffer) = OUTPUT_BUFFER.lock() {
-        buffer.join("")
-    } else {
-        String::new()
+// Stack left free at the deepest allowed level, for builtins and formatting values
+const STACK_RESERVE: usize = 1024 * 1024;
+
+// Native stack one level of nesting may use, measured on the tree-walker with room to
+// spare. Unoptimized builds give every local of `evaluate_node` its own slot.
+const NESTING_FRAME_SIZE: usize = if cfg!(debug_assertions) { 96 * 1024 } else { 12 * 1024 };
+
+impl Limits {
+    // Stack a thread needs for the depth limits to be reached before it runs out
+    pub fn stack_needed(&self) -> usize {
+        self.max_nesting.saturating_mul(NESTING_FRAME_SIZE).saturating_add(STACK_RESERVE)
     }
 }
 
-pub fn add_output(text: &str) {
-    if let Ok(mut buffer) = OUTPUT_BUFFER.lock() {
-        buffer.push(text.to_string());
+// Resource bookkeeping for the run in progress on this thread
+#[derive(Default)]
+struct RunState {
+    limits: Limits,
+    steps: u64,
+    call_depth: usize,
+    nesting: usize,
+    // Approximate address of the top of the stack when the run started
+    stack_base: usize,
+    // Bound bytes already in use before the run, such as a reused global scope
+    memory_base: usize,
+    // Printed text, which counts against the memory limit too
+    output: Vec<String>,
+    output_bytes: usize,
+    peak_memory: usize,
+}
+
+impl RunState {
+    // Fails unless `bytes` more fit in the memory budget on top of what the run holds now
+    fn reserve(&mut self, bytes: usize) -> Result<(), LangError> {
+        let in_use = bound_bytes().saturating_sub(self.memory_base) + self.output_bytes;
+        let needed = in_use.saturating_add(bytes);
+        if needed > self.limits.max_memory {
+            return Err(LangError::new(ErrorCode::MemoryLimit, format!("Memory limit of {:.1} MB exceeded",
+                self.limits.max_memory as f64 / (1024.0 * 1024.0))));
+        }
+        self.peak_memory = self.peak_memory.max(needed);
+        Ok(())
+    }
+}
+
+thread_local! {
+    static RUN_STATE: RefCell<RunState> = RefCell::new(RunState::default());
+}
+
+#[derive(Clone, Copy)]
+pub enum Depth {
+    Call,
+    Nesting,
+}
+
+// Holds one level of call depth or nesting and gives it back when dropped,
+// so early returns through `?` keep the counts right
+pub struct DepthGuard(Depth);
+
+impl DepthGuard {
+    pub fn enter(depth: Depth, span: Span) -> Result<DepthGuard, LangError> {
+        RUN_STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let limits = state.limits;
+            if let Some(stack_size) = limits.stack_size {
+                let marker = 0u8;
+                let used = state.stack_base.saturating_sub(&marker as *const u8 as usize);
+                if used + STACK_RESERVE > stack_size {
+                    return Err(runtime_error(ErrorCode::StackOverflow,
+                        "Stack overflow: program recursed too deeply", span));
+                }
+            }
+            let (count, limit, what) = match depth {
+                Depth::Call => (&mut state.call_depth, limits.max_call_depth, "call depth"),
+                Depth::Nesting => (&mut state.nesting, limits.max_nesting, "nesting depth"),
+            };
+            if *count >= limit {
+                return Err(depth_exceeded(what, limit).at(span));
+            }
+            *count += 1;
+            Ok(DepthGuard(depth))
+        })
     }
 }
 
+// Error for going past the limit on call depth or nesting depth
+pub fn depth_exceeded(what: &str, limit: usize) -> LangError {
+    LangError::new(ErrorCode::StackOverflow, format!("Stack overflow: maximum {} of {} exceeded", what, limit))
+}
+
+pub fn charge_step(span: Span) -> Result<(), LangError> {
+    RUN_STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.steps >= state.limits.max_steps {
+            return Err(runtime_error(ErrorCode::StepLimit,
+                format!("Execution exceeded the budget of {} steps (possible infinite loop)", state.limits.max_steps), span));
+        }
+        state.steps += 1;
+        // Variables grow without a step of their own, so check memory here too
+        state.reserve(0).map_err(|e| e.at(span))
+    })
+}
+
+// Called before building a value whose size is known up front, so a single huge
+// allocation is refused instead of attempted
+pub fn reserve_memory(bytes: usize) -> Result<(), LangError> {
+    RUN_STATE.with(|state| state.borrow_mut().reserve(bytes))
+}
+
+// Most memory the last run on this thread held at once, in bytes
+pub fn peak_memory() -> usize {
+    RUN_STATE.with(|state| state.borrow().peak_memory)
+}
+
+// Integer power, refused when the result could not fit in the memory budget
+pub fn checked_pow(base: &BigInt, exp: u32) -> Result<BigInt, LangError> {
+    // The result has at least (bits - 1) * exp bits; 0, 1 and -1 stay small
+    reserve_memory((base.bits().saturating_sub(1) * exp as u64 / 8) as usize)?;
+    Ok(base.pow(exp))
+}
+
+// Replaces a bound value in place, keeping the count of bound memory up to date
+pub fn store(slot: &mut Object, value: Object) {
+    record_resize(slot.heap_size(), value.heap_size());
+    *slot = value;
+}
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        RUN_STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            match self.0 {
+                Depth::Call => state.call_depth -= 1,
+                Depth::Nesting => state.nesting -= 1,
+            }
+        });
+    }
+}
+
+// Builtins reachable through method-call sugar: `"abc".upper()` is `upper("abc")`.
+// Looked up here rather than in the environment so user variables cannot shadow them.
+thread_local! {
+    static METHOD_BUILTINS: HashMap<String, Object> = get_builtins();
+}
+
 // Rest of the code remains the same...
 #[derive(Debug)]
 pub enum EvalResult {
@@ -39,92 +208,513 @@ pub enum EvalResult {
     Return(Object),
     Break,
     Continue,
+    // A thrown value or runtime error unwinding to the nearest `try`
+    Throw(LangError),
 }
 
 impl EvalResult {
-    fn unwrap_value(self) -> Object {
+    // Value of an expression; a throw keeps unwinding as an error
+    pub fn into_value(self) -> Result<Object, LangError> {
         match self {
-            EvalResult::Value(obj) => obj,
-            EvalResult::Return(obj) => obj,
-            _ => Object::Null,
+            EvalResult::Value(obj) => Ok(obj),
+            EvalResult::Return(obj) => Ok(obj),
+            EvalResult::Throw(err) => Err(err),
+            _ => Ok(Object::Null),
         }
     }
 }
 
-pub fn evaluate(node: &AstNode, env: &mut Environment) -> Result<Object, String> {
-    // Clear previous output
-    clear_output();
+// Resets output and resource accounting for a new run on this thread
+pub fn begin_run(limits: Limits) {
+    let marker = 0u8;
+    RUN_STATE.with(|state| *state.borrow_mut() = RunState {
+        limits,
+        stack_base: &marker as *const u8 as usize,
+        memory_base: bound_bytes(),
+        ..RunState::default()
+    });
+}
+
+// Result of a program that ran to its end: printed output if there was any,
+// otherwise the value of the last statement
+pub fn program_result(value: Object) -> Object {
+    let output = get_output();
+    if !output.is_empty() {
+        Object::String(output.trim_end().to_string())
+    } else if matches!(value, Object::Null) {
+        Object::String(String::new())
+    } else {
+        value
+    }
+}
+
+pub fn evaluate(node: &AstNode, env: &Environment, limits: Limits) -> Result<Object, LangError> {
+    begin_run(limits);
     
     // Add builtins to environment if not present
     for (name, builtin) in get_builtins() {
-        env.entry(name).or_insert(builtin);
+        if !env.contains(&name) {
+            env.define(name, builtin);
+        }
     }
     
     match evaluate_internal(node, env)? {
-        EvalResult::Value(obj) => {
-            // If there was printed output, return it; otherwise return the final value
-            let output = get_output();
-            if !output.is_empty() {
-                Ok(Object::String(output.trim_end().to_string()))
-            } else if matches!(obj, Object::Null) {
-                Ok(Object::String(String::new()))
-            } else {
-                Ok(obj)
+        EvalResult::Value(obj) => Ok(program_result(obj)),
+        EvalResult::Return(obj) => Ok(obj),
+        EvalResult::Break => Err(LangError::new(ErrorCode::InvalidControlFlow, "break statement outside of loop")),
+        EvalResult::Continue => Err(LangError::new(ErrorCode::InvalidControlFlow, "continue statement outside of loop")),
+        EvalResult::Throw(err) => Err(err),
+    }
+}
+
+// The closure is the defining scope itself (not a copy), so the function sees its own
+// name and any sibling defined in the same scope at call time
+fn make_function(parameters: &[String], body: &AstNode, env: &Environment) -> Object {
+    Object::Function {
+        parameters: parameters.to_vec(),
+        body: body.clone(),
+        closure: env.clone(),
+    }
+}
+
+// Binds every function declared directly in a block before its statements run, so
+// functions can be called before their definition and can call each other in any order
+fn hoist_functions(statements: &[AstNode], env: &Environment) {
+    for stmt in statements {
+        if let NodeKind::FunctionDefinition { name, parameters, body } = &stmt.kind {
+            env.define(name.clone(), make_function(parameters, body, env));
+        }
+    }
+}
+
+// Handle given to builtins so they can call back into user code, on whichever
+// backend is running the program
+pub struct Interpreter<'a> {
+    span: Span,
+    vm: Option<&'a mut Vm>,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn for_vm(span: Span, vm: &'a mut Vm) -> Self {
+        Interpreter { span, vm: Some(vm) }
+    }
+
+    pub fn call(&mut self, function: &Object, args: Vec<Object>) -> Result<Object, LangError> {
+        match self.vm.as_deref_mut() {
+            Some(vm) => vm.call_value(function.clone(), args, self.span),
+            None => call_function(function.clone(), args, "callback", self.span),
+        }
+    }
+
+    // Lets a builtin refuse to build a value that would not fit in the memory budget
+    pub fn reserve(&self, bytes: usize) -> Result<(), LangError> {
+        reserve_memory(bytes)
+    }
+}
+
+// Invokes a user-defined or builtin function with already evaluated arguments.
+// Calling a struct constructs an instance from its fields in declaration order.
+fn call_function(function: Object, args: Vec<Object>, name: &str, span: Span) -> Result<Object, LangError> {
+    match function {
+        Object::Function { parameters, body, closure } => {
+            run_function(&parameters, &body, &closure, args, name, span).map(|(result, _)| result)
+        }
+        Object::BuiltinFunction(func) => func(&mut Interpreter { span, vm: None }, &args).map_err(|e| e.at(span)),
+        Object::Struct(struct_type) => instantiate(struct_type, args).map_err(|e| e.at(span)),
+        _ => Err(runtime_error(ErrorCode::NotCallable, format!("{} is not a function", name), span)),
+    }
+}
+
+// Instance built by calling a struct with its field values in declaration order
+pub fn instantiate(struct_type: Rc<StructType>, args: Vec<Object>) -> Result<Object, LangError> {
+    if struct_type.fields.len() != args.len() {
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("Struct {} expects {} fields, got {}",
+            struct_type.name, struct_type.fields.len(), args.len())));
+    }
+    Ok(Object::Instance { struct_type, fields: args })
+}
+
+// Runs a user function body, also returning the call scope so method calls can
+// read back the final value of `self`
+fn run_function(
+    parameters: &[String],
+    body: &AstNode,
+    closure: &Environment,
+    args: Vec<Object>,
+    name: &str,
+    span: Span,
+) -> Result<(Object, Environment), LangError> {
+    if parameters.len() != args.len() {
+        return Err(runtime_error(ErrorCode::ArgumentCount, format!("Function {} expects {} arguments, got {}", 
+            name, parameters.len(), args.len()), span));
+    }
+    
+    let _depth = DepthGuard::enter(Depth::Call, span)?;
+    
+    // Bind arguments to parameters in a fresh scope on top of the closure
+    let call_env = Environment::enclosed(closure);
+    for (param, arg) in parameters.iter().zip(args) {
+        call_env.define(param.clone(), arg);
+    }
+    
+    match evaluate_internal(body, &call_env)? {
+        EvalResult::Return(obj) | EvalResult::Value(obj) => Ok((obj, call_env)),
+        EvalResult::Throw(err) => Err(err),
+        EvalResult::Break | EvalResult::Continue => Err(runtime_error(ErrorCode::InvalidControlFlow,
+            "break or continue outside of loop", span)),
+    }
+}
+
+// What `receiver.name(args)` calls
+pub enum MethodTarget {
+    // Method from an `impl` block whose first parameter is `self`
+    SelfMethod(Object),
+    // Function called with the arguments alone
+    Function(Object),
+    // Builtin called with the receiver as its first argument
+    Builtin(Object),
+    // `Shape.Circle(2)` builds a variant
+    Variant(Rc<EnumType>, usize),
+}
+
+// Methods from `impl` blocks come first, then instance fields holding functions,
+// then struct and enum functions and variants, then builtins
+pub fn resolve_method(receiver: &Object, name: &str) -> Option<MethodTarget> {
+    let method = match receiver {
+        Object::Instance { struct_type, .. } => struct_type.methods.borrow().get(name).cloned(),
+        Object::Variant { enum_type, .. } => enum_type.methods.borrow().get(name).cloned(),
+        _ => None,
+    };
+    match method {
+        Some(method) if takes_self(&method) => return Some(MethodTarget::SelfMethod(method)),
+        Some(method) => return Some(MethodTarget::Function(method)),
+        None => {}
+    }
+    
+    match receiver {
+        Object::Instance { struct_type, fields } => {
+            if let Some(idx) = struct_type.field_index(name) {
+                return Some(MethodTarget::Function(fields[idx].clone()));
+            }
+        }
+        Object::Struct(struct_type) => {
+            if let Some(function) = struct_type.methods.borrow().get(name) {
+                return Some(MethodTarget::Function(function.clone()));
             }
+        }
+        Object::Enum(enum_type) => {
+            if let Some(variant) = enum_type.variant_index(name) {
+                return Some(MethodTarget::Variant(enum_type.clone(), variant));
+            }
+            if let Some(function) = enum_type.methods.borrow().get(name) {
+                return Some(MethodTarget::Function(function.clone()));
+            }
+        }
+        _ => {}
+    }
+    
+    METHOD_BUILTINS.with(|builtins| builtins.get(name).cloned()).map(MethodTarget::Builtin)
+}
+
+fn takes_self(function: &Object) -> bool {
+    let parameters = match function {
+        Object::Function { parameters, .. } => parameters,
+        Object::Closure(closure) => &closure.proto.parameters,
+        _ => return false,
+    };
+    parameters.first().is_some_and(|p| p == "self")
+}
+
+// Variant of `enum_type` carrying `values`
+pub fn make_variant(enum_type: Rc<EnumType>, variant: usize, values: Vec<Object>) -> Result<Object, LangError> {
+    let (name, fields) = &enum_type.variants[variant];
+    if fields.len() != values.len() {
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("Variant {}.{} expects {} values, got {}",
+            enum_type.name, name, fields.len(), values.len())));
+    }
+    Ok(Object::Variant { enum_type, variant, values })
+}
+
+// `receiver.name(args)`. Values are copied on assignment, so a method that changes
+// `self` writes the new value back to the receiver when the receiver is a variable,
+// element or field.
+fn call_method(
+    receiver_node: &AstNode,
+    receiver: Object,
+    name: &str,
+    args: Vec<Object>,
+    env: &Environment,
+    span: Span,
+) -> Result<Object, LangError> {
+    match resolve_method(&receiver, name) {
+        Some(MethodTarget::SelfMethod(Object::Function { parameters, body, closure })) => {
+            let mut full_args = vec![receiver.clone()];
+            full_args.extend(args);
+            let (result, call_env) = run_function(&parameters, &body, &closure, full_args, name, span)?;
+            let updated = call_env.get("self").unwrap_or(Object::Null);
+            let is_place = matches!(receiver_node.kind,
+                NodeKind::Identifier(_) | NodeKind::ArrayAccess { .. } | NodeKind::FieldAccess { .. });
+            if is_place && updated != receiver {
+                update_place(receiver_node, env, &mut |slot| {
+                    store(slot, updated.clone());
+                    Ok(Object::Null)
+                }).map_err(|e| e.at(span))?;
+            }
+            Ok(result)
+        }
+        Some(MethodTarget::SelfMethod(function) | MethodTarget::Function(function)) => {
+            call_function(function, args, name, span)
+        }
+        Some(MethodTarget::Builtin(builtin)) => {
+            let mut full_args = vec![receiver];
+            full_args.extend(args);
+            call_function(builtin, full_args, name, span)
+        }
+        Some(MethodTarget::Variant(enum_type, variant)) => {
+            make_variant(enum_type, variant, args).map_err(|e| e.at(span))
+        }
+        None => Err(runtime_error(ErrorCode::NotCallable,
+            format!("{} has no method '{}'", type_label(&receiver), name), span)),
+    }
+}
+
+// What `catch (e)` binds: the thrown value itself, or an error value describing a runtime failure
+pub fn caught_value(err: LangError) -> Object {
+    match err.thrown {
+        Some(thrown) => Object::Error { kind: thrown.kind, message: thrown.message },
+        None => Object::Error { kind: format!("{:?}", err.code), message: err.message },
+    }
+}
+
+// `value.field`: an instance field, a struct or enum function, an enum variant
+// without values, or the kind or message of an error
+pub fn read_field(value: Object, field: &str) -> Result<Object, LangError> {
+    match value {
+        Object::Instance { struct_type, fields } => match struct_type.field_index(field) {
+            Some(idx) => Ok(fields[idx].clone()),
+            None => Err(LangError::new(ErrorCode::UnknownField,
+                format!("{} has no field '{}'", struct_type.name, field))),
         },
-        EvalResult::Return(obj) => Ok(obj),
-        EvalResult::Break => Err("break statement outside of loop".to_string()),
-        EvalResult::Continue => Err("continue statement outside of loop".to_string()),
+        Object::Struct(struct_type) => match struct_type.methods.borrow().get(field) {
+            Some(function) => Ok(function.clone()),
+            None => Err(LangError::new(ErrorCode::UnknownField,
+                format!("Struct {} has no function '{}'", struct_type.name, field))),
+        },
+        Object::Error { kind, message } => match field {
+            "kind" => Ok(Object::String(kind)),
+            "message" => Ok(Object::String(message)),
+            _ => Err(LangError::new(ErrorCode::UnknownField, format!("error has no field '{}'", field))),
+        },
+        // `Shape.Empty` is a value; variants carrying values must be called
+        Object::Enum(enum_type) => match enum_type.variant_index(field) {
+            Some(variant) if enum_type.variants[variant].1.is_empty() => {
+                Ok(Object::Variant { enum_type: enum_type.clone(), variant, values: Vec::new() })
+            }
+            Some(variant) => Err(LangError::new(ErrorCode::TypeMismatch,
+                format!("Variant {}.{} carries {} values; call it as {}.{}(...)",
+                    enum_type.name, field, enum_type.variants[variant].1.len(), enum_type.name, field))),
+            None => match enum_type.methods.borrow().get(field) {
+                Some(function) => Ok(function.clone()),
+                None => Err(LangError::new(ErrorCode::UnknownField,
+                    format!("Enum {} has no variant '{}'", enum_type.name, field))),
+            },
+        },
+        other => Err(LangError::new(ErrorCode::TypeMismatch,
+            format!("Cannot access field '{}' on {}", field, other.type_name()))),
     }
 }
 
-fn evaluate_internal(node: &AstNode, env: &mut Environment) -> Result<EvalResult, String> {
-    match node {
-        AstNode::Program(statements) => {
-            let mut result = Object::Null;
-            for stmt in statements {
-                match evaluate_internal(stmt, env)? {
-                    EvalResult::Value(obj) => result = obj,
-                    EvalResult::Return(obj) => return Ok(EvalResult::Return(obj)),
-                    EvalResult::Break => return Ok(EvalResult::Break),
-                    EvalResult::Continue => return Ok(EvalResult::Continue),
+// Struct and enum values are described by their own name in error messages
+pub fn type_label(obj: &Object) -> String {
+    match obj {
+        Object::Struct(struct_type) | Object::Instance { struct_type, .. } => struct_type.name.clone(),
+        Object::Enum(enum_type) | Object::Variant { enum_type, .. } => enum_type.name.clone(),
+        other => other.type_name().to_string(),
+    }
+}
+
+// Checks `value` against `pattern`, collecting the names it binds. Variant patterns
+// name their enum, which is looked up in `env` like any other value.
+fn match_pattern(
+    pattern: &Pattern,
+    value: &Object,
+    env: &Environment,
+    bindings: &mut Vec<(String, Object)>,
+) -> Result<bool, LangError> {
+    match pattern {
+        Pattern::Wildcard => Ok(true),
+        Pattern::Binding(name) => {
+            bindings.push((name.clone(), value.clone()));
+            Ok(true)
+        }
+        Pattern::Literal(literal) => {
+            let expected = evaluate_internal(literal, env)?.into_value()?;
+            Ok(objects_equal(&expected, value))
+        }
+        Pattern::Array { prefix, rest, suffix } => {
+            let Object::Array(items) = value else {
+                return Ok(false);
+            };
+            let fixed = prefix.len() + suffix.len();
+            let length_ok = match rest {
+                Some(_) => items.len() >= fixed,
+                None => items.len() == fixed,
+            };
+            if !length_ok {
+                return Ok(false);
+            }
+            let suffix_start = items.len() - suffix.len();
+            for (pattern, item) in prefix.iter().zip(items).chain(suffix.iter().zip(&items[suffix_start..])) {
+                if !match_pattern(pattern, item, env, bindings)? {
+                    return Ok(false);
                 }
             }
-            Ok(EvalResult::Value(result))
+            match rest {
+                Some(rest) => match_pattern(rest, &Object::Array(items[prefix.len()..suffix_start].to_vec()), env, bindings),
+                None => Ok(true),
+            }
         }
-        
-        AstNode::BlockStatement(statements) => {
-            // Create new scope for block
-            let mut block_env = env.clone();
-            let mut result = Object::Null;
-            
-            for stmt in statements {
-                match evaluate_internal(stmt, &mut block_env)? {
-                    EvalResult::Value(obj) => result = obj,
-                    EvalResult::Return(obj) => return Ok(EvalResult::Return(obj)),
-                    EvalResult::Break => return Ok(EvalResult::Break),
-                    EvalResult::Continue => return Ok(EvalResult::Continue),
-                }
+        Pattern::Variant { enum_name, variant, fields } => {
+            let enum_type = match env.get(enum_name) {
+                Some(Object::Enum(enum_type)) => enum_type,
+                Some(other) => return Err(LangError::new(ErrorCode::TypeMismatch,
+                    format!("'{}' is {}, not an enum", enum_name, other.type_name()))),
+                None => return Err(LangError::new(ErrorCode::UndefinedVariable, format!("Enum not found: {}", enum_name))),
+            };
+            let Some(index) = enum_type.variant_index(variant) else {
+                return Err(LangError::new(ErrorCode::UnknownField,
+                    format!("Enum {} has no variant '{}'", enum_name, variant)));
+            };
+            let expected = enum_type.variants[index].1.len();
+            if fields.len() != expected {
+                return Err(LangError::new(ErrorCode::ArgumentCount,
+                    format!("Pattern {}.{} expects {} values, got {}", enum_name, variant, expected, fields.len())));
             }
-            
-            // Copy back any function definitions to parent scope
-            for (key, value) in block_env.iter() {
-                if matches!(value, Object::Function { .. }) && !env.contains_key(key) {
-                    env.insert(key.clone(), value.clone());
+            match value {
+                Object::Variant { enum_type: actual, variant: actual_index, values }
+                    if Rc::ptr_eq(actual, &enum_type) && *actual_index == index =>
+                {
+                    for (pattern, item) in fields.iter().zip(values) {
+                        if !match_pattern(pattern, item, env, bindings)? {
+                            return Ok(false);
+                        }
+                    }
+                    Ok(true)
                 }
+                _ => Ok(false),
             }
-            
-            Ok(EvalResult::Value(result))
         }
+    }
+}
+
+// Values bound on each pass of a for-in loop: one per loop variable. Ranges are
+// walked lazily so `for i in 0..n` never builds the whole array.
+fn iteration_items(
+    iterable: &AstNode,
+    variable_count: usize,
+    env: &Environment,
+) -> Result<Box<dyn Iterator<Item = Vec<Object>>>, LangError> {
+    let pair = variable_count == 2;
+    
+    if let NodeKind::Range { start, end, inclusive } = &iterable.kind {
+        if pair {
+            return Err(runtime_error(ErrorCode::TypeMismatch, "Range items cannot be destructured into two variables", iterable.span));
+        }
+        let (start, end) = range_bounds(start, end, *inclusive, env)?;
+        let range = std::iter::successors(Some(start), |i| Some(i + 1))
+            .take_while(move |i| *i < end)
+            .map(|i| vec![Object::Integer(i)]);
+        return Ok(Box::new(range));
+    }
+    
+    let items: Vec<Vec<Object>> = match evaluate_internal(iterable, env)?.into_value()? {
+        Object::Array(arr) if pair => arr.into_iter().enumerate()
+            .map(|(i, item)| vec![Object::Integer(BigInt::from(i)), item])
+            .collect(),
+        Object::Array(arr) => arr.into_iter().map(|item| vec![item]).collect(),
+        Object::String(s) if pair => s.chars().enumerate()
+            .map(|(i, c)| vec![Object::Integer(BigInt::from(i)), Object::String(c.to_string())])
+            .collect(),
+        Object::String(s) => s.chars().map(|c| vec![Object::String(c.to_string())]).collect(),
+        Object::Map(map) if pair => map.into_iter().map(|(k, v)| vec![k.to_object(), v]).collect(),
+        Object::Map(map) => map.into_keys().map(|k| vec![k.to_object()]).collect(),
+        other => return Err(runtime_error(ErrorCode::TypeMismatch, format!("Cannot iterate over {}", other.type_name()), iterable.span)),
+    };
+    Ok(Box::new(items.into_iter()))
+}
+
+// Start and exclusive end of a range
+fn range_bounds(start: &AstNode, end: &AstNode, inclusive: bool, env: &Environment) -> Result<(BigInt, BigInt), LangError> {
+    let bound = |node: &AstNode| -> Result<BigInt, LangError> {
+        match evaluate_internal(node, env)?.into_value()? {
+            Object::Integer(n) => Ok(n),
+            other => Err(runtime_error(ErrorCode::TypeMismatch, format!("Range bounds must be integers, got {}", other.type_name()), node.span)),
+        }
+    };
+    let (start, mut end) = (bound(start)?, bound(end)?);
+    if inclusive {
+        end += 1;
+    }
+    Ok((start, end))
+}
+
+// Builds the array for a range used as a value, checking its size first
+pub fn range_array(start: BigInt, end: BigInt) -> Result<Object, LangError> {
+    let count = (&end - &start).max(BigInt::zero()).to_usize().unwrap_or(usize::MAX);
+    reserve_memory(count.saturating_mul(std::mem::size_of::<Object>()))?;
+    let items = std::iter::successors(Some(start), |i| Some(i + 1))
+        .take_while(|i| *i < end)
+        .map(Object::Integer)
+        .collect();
+    Ok(Object::Array(items))
+}
+
+fn runtime_error(code: ErrorCode, message: impl Into<String>, span: Span) -> LangError {
+    LangError::new(code, message).at(span)
+}
+
+// Runs a statement list in `env`, after hoisting its functions there
+fn evaluate_statements(statements: &[AstNode], env: &Environment) -> Result<EvalResult, LangError> {
+    hoist_functions(statements, env);
+    let mut result = Object::Null;
+    for stmt in statements {
+        match evaluate_internal(stmt, env)? {
+            EvalResult::Value(obj) => result = obj,
+            EvalResult::Return(obj) => return Ok(EvalResult::Return(obj)),
+            EvalResult::Throw(err) => return Ok(EvalResult::Throw(err)),
+            EvalResult::Break => return Ok(EvalResult::Break),
+            EvalResult::Continue => return Ok(EvalResult::Continue),
+        }
+    }
+    Ok(EvalResult::Value(result))
+}
+
+fn evaluate_internal(node: &AstNode, env: &Environment) -> Result<EvalResult, LangError> {
+    charge_step(node.span)?;
+    let _depth = DepthGuard::enter(Depth::Nesting, node.span)?;
+    evaluate_node(node, env)
+}
+
+fn evaluate_node(node: &AstNode, env: &Environment) -> Result<EvalResult, LangError> {
+    match &node.kind {
+        NodeKind::Program(statements) => evaluate_statements(statements, env),
         
-        AstNode::LetStatement { name, value } => {
-            let val = evaluate_internal(value, env)?.unwrap_value();
-            env.insert(name.clone(), val);
+        NodeKind::BlockStatement(statements) => {
+            // Create new scope for block; assignments to outer variables go through to them
+            let block_env = Environment::enclosed(env);
+            let result = evaluate_statements(statements, &block_env);
+            block_env.close();
+            result
+        }
+        
+        NodeKind::LetStatement { name, value } => {
+            let val = evaluate_internal(value, env)?.into_value()?;
+            env.define(name.clone(), val);
             Ok(EvalResult::Value(Object::Null))
         }
         
-        AstNode::IfStatement { condition, then_branch, else_branch } => {
-            let condition_val = evaluate_internal(condition, env)?.unwrap_value();
+        NodeKind::IfStatement { condition, then_branch, else_branch } => {
+            let condition_val = evaluate_internal(condition, env)?.into_value()?;
             
             if condition_val.is_truthy() {
                 evaluate_internal(then_branch, env)
@@ -135,18 +725,11 @@ fn evaluate_internal(node: &AstNode, env: &mut Environment) -> Result<EvalResult
             }
         }
         
-        AstNode::WhileStatement { condition, body } => {
+        NodeKind::WhileStatement { condition, body } => {
             let mut result = Object::Null;
-            let mut iterations = 0;
-            const MAX_ITERATIONS: usize = 10000; // Prevent infinite loops
             
             loop {
-                iterations += 1;
-                if iterations > MAX_ITERATIONS {
-                    return Err("Loop exceeded maximum iterations (possible infinite loop)".to_string());
-                }
-                
-                let condition_val = evaluate_internal(condition, env)?.unwrap_value();
+                let condition_val = evaluate_internal(condition, env)?.into_value()?;
                 if !condition_val.is_truthy() {
                     break;
                 }
@@ -154,6 +737,7 @@ fn evaluate_internal(node: &AstNode, env: &mut Environment) -> Result<EvalResult
                 match evaluate_internal(body, env)? {
                     EvalResult::Value(obj) => result = obj,
                     EvalResult::Return(obj) => return Ok(EvalResult::Return(obj)),
+                    EvalResult::Throw(err) => return Ok(EvalResult::Throw(err)),
                     EvalResult::Break => break,
                     EvalResult::Continue => continue,
                 }
@@ -162,290 +746,1142 @@ fn evaluate_internal(node: &AstNode, env: &mut Environment) -> Result<EvalResult
             Ok(EvalResult::Value(result))
         }
         
-        AstNode::ForStatement { init, condition, increment, body } => {
+        NodeKind::ForStatement { init, condition, increment, body } => {
             // Create new scope for for loop
-            let mut loop_env = env.clone();
+            let loop_env = Environment::enclosed(env);
             
             // Initialize
-            evaluate_internal(init, &mut loop_env)?;
+            evaluate_internal(init, &loop_env)?;
             
             let mut result = Object::Null;
-            let mut iterations = 0;
-            const MAX_ITERATIONS: usize = 10000;
             
             loop {
-                iterations += 1;
-                if iterations > MAX_ITERATIONS {
-                    return Err("Loop exceeded maximum iterations (possible infinite loop)".to_string());
-                }
-                
-                let condition_val = evaluate_internal(condition, &mut loop_env)?.unwrap_value();
+                let condition_val = evaluate_internal(condition, &loop_env)?.into_value()?;
                 if !condition_val.is_truthy() {
                     break;
                 }
                 
                 // Execute body
-                match evaluate_internal(body, &mut loop_env)? {
+                match evaluate_internal(body, &loop_env)? {
                     EvalResult::Value(obj) => result = obj,
                     EvalResult::Return(obj) => return Ok(EvalResult::Return(obj)),
+                    EvalResult::Throw(err) => return Ok(EvalResult::Throw(err)),
                     EvalResult::Break => break,
                     EvalResult::Continue => {
                         // Execute increment and continue
-                        evaluate_internal(increment, &mut loop_env)?;
+                        evaluate_internal(increment, &loop_env)?;
                         continue;
                     },
                 }
                 
                 // Execute increment
-                evaluate_internal(increment, &mut loop_env)?;
+                evaluate_internal(increment, &loop_env)?;
             }
             
             Ok(EvalResult::Value(result))
         }
         
-        AstNode::FunctionDefinition { name, parameters, body } => {
-            let function = Object::Function {
-                parameters: parameters.clone(),
-                body: (**body).clone(),
-                closure: env.clone(),
-            };
-            env.insert(name.clone(), function.clone());
+        NodeKind::ForInStatement { variables, iterable, body } => {
+            let items = iteration_items(iterable, variables.len(), env)?;
+            let mut result = Object::Null;
+            
+            for item in items {
+                // Each iteration gets its own scope, so closures capture that iteration's values
+                let iteration_env = Environment::enclosed(env);
+                for (name, value) in variables.iter().zip(item) {
+                    iteration_env.define(name.clone(), value);
+                }
+                
+                match evaluate_internal(body, &iteration_env)? {
+                    EvalResult::Value(obj) => result = obj,
+                    EvalResult::Return(obj) => return Ok(EvalResult::Return(obj)),
+                    EvalResult::Throw(err) => return Ok(EvalResult::Throw(err)),
+                    EvalResult::Break => break,
+                    EvalResult::Continue => continue,
+                }
+            }
+            
+            Ok(EvalResult::Value(result))
+        }
+        
+        // Outside a for-in loop the range is built as an array, so its size is checked first
+        NodeKind::Range { start, end, inclusive } => {
+            let (start, end) = range_bounds(start, end, *inclusive, env)?;
+            Ok(EvalResult::Value(range_array(start, end).map_err(|e| e.at(node.span))?))
+        }
+        
+        NodeKind::FunctionDefinition { name, parameters, body } => {
+            let function = make_function(parameters, body, env);
+            env.define(name.clone(), function.clone());
             Ok(EvalResult::Value(function))
         }
         
-        AstNode::FunctionCall { name, arguments } => {
-            let function = match env.get(name) {
-                Some(obj) => obj.clone(),
-                None => return Err(format!("Function not found: {}", name)),
+        NodeKind::FunctionLiteral { parameters, body } => {
+            Ok(EvalResult::Value(make_function(parameters, body, env)))
+        }
+        
+        NodeKind::StructDefinition { name, fields } => {
+            let struct_type = StructType {
+                name: name.clone(),
+                fields: fields.clone(),
+                methods: RefCell::new(HashMap::new()),
+            };
+            env.define(name.clone(), Object::Struct(Rc::new(struct_type)));
+            Ok(EvalResult::Value(Object::Null))
+        }
+        
+        NodeKind::EnumDefinition { name, variants } => {
+            let enum_type = EnumType {
+                name: name.clone(),
+                variants: variants.clone(),
+                methods: RefCell::new(HashMap::new()),
+            };
+            env.define(name.clone(), Object::Enum(Rc::new(enum_type)));
+            Ok(EvalResult::Value(Object::Null))
+        }
+        
+        // Methods close over the scope of the impl block, like any other function
+        NodeKind::ImplBlock { name, methods } => {
+            let target = env.get(name);
+            let method_table = match &target {
+                Some(Object::Struct(struct_type)) => &struct_type.methods,
+                Some(Object::Enum(enum_type)) => &enum_type.methods,
+                Some(other) => return Err(runtime_error(ErrorCode::TypeMismatch,
+                    format!("Cannot implement methods for {} '{}'", other.type_name(), name), node.span)),
+                None => return Err(runtime_error(ErrorCode::UndefinedVariable,
+                    format!("Struct or enum not found: {}", name), node.span)),
+            };
+            for method in methods {
+                if let NodeKind::FunctionDefinition { name, parameters, body } = &method.kind {
+                    method_table.borrow_mut().insert(name.clone(), make_function(parameters, body, env));
+                }
+            }
+            Ok(EvalResult::Value(Object::Null))
+        }
+        
+        NodeKind::Match { subject, arms } => {
+            let value = evaluate_internal(subject, env)?.into_value()?;
+            for arm in arms {
+                let mut bindings = Vec::new();
+                if !match_pattern(&arm.pattern, &value, env, &mut bindings).map_err(|e| e.at(node.span))? {
+                    continue;
+                }
+                // Bindings get their own scope so they never leak out of the arm
+                let arm_env = Environment::enclosed(env);
+                for (name, bound) in bindings {
+                    arm_env.define(name, bound);
+                }
+                if let Some(guard) = &arm.guard {
+                    if !evaluate_internal(guard, &arm_env)?.into_value()?.is_truthy() {
+                        continue;
+                    }
+                }
+                return evaluate_internal(&arm.body, &arm_env);
+            }
+            Err(runtime_error(ErrorCode::NoMatchingArm, format!("No match arm matches {}", value), node.span))
+        }
+        
+        // Reading `instance.field`, or `Struct.function` for functions without `self`
+        NodeKind::FieldAccess { object, field } => {
+            let value = evaluate_internal(object, env)?.into_value()?;
+            Ok(EvalResult::Value(read_field(value, field).map_err(|e| e.at(node.span))?))
+        }
+        
+        NodeKind::FunctionCall { function, arguments } if matches!(function.kind, NodeKind::FieldAccess { .. }) => {
+            let NodeKind::FieldAccess { object, field } = &function.kind else { unreachable!() };
+            let receiver = evaluate_internal(object, env)?.into_value()?;
+            let args: Result<Vec<Object>, LangError> = arguments.iter()
+                .map(|arg| evaluate_internal(arg, env).and_then(EvalResult::into_value))
+                .collect();
+            Ok(EvalResult::Value(call_method(object, receiver, field, args?, env, node.span)?))
+        }
+        
+        NodeKind::FunctionCall { function, arguments } => {
+            // Calling an unknown name gets a clearer message than a generic lookup failure
+            let (callee, name) = match &function.kind {
+                NodeKind::Identifier(name) => match env.get(name) {
+                    Some(obj) => (obj, name.clone()),
+                    None => return Err(runtime_error(ErrorCode::UndefinedVariable, format!("Function not found: {}", name), node.span)),
+                },
+                _ => {
+                    let obj = evaluate_internal(function, env)?.into_value()?;
+                    let name = match obj {
+                        Object::Function { .. } | Object::Closure(_) => "<anonymous>".to_string(),
+                        ref other => other.type_name().to_string(),
+                    };
+                    (obj, name)
+                }
             };
             
-            let args: Result<Vec<Object>, String> = arguments.iter()
-                .map(|arg| evaluate_internal(arg, env).map(|r| r.unwrap_value()))
+            let args: Result<Vec<Object>, LangError> = arguments.iter()
+                .map(|arg| evaluate_internal(arg, env).and_then(EvalResult::into_value))
                 .collect();
             let args = args?;
             
-            match function {
-                Object::Function { parameters, body, mut closure } => {
-                    if parameters.len() != args.len() {
-                        return Err(format!("Function {} expects {} arguments, got {}", 
-                            name, parameters.len(), args.len()));
-                    }
-                    
-                    // Bind arguments to parameters
-                    for (param, arg) in parameters.iter().zip(args.iter()) {
-                        closure.insert(param.clone(), arg.clone());
-                    }
-                    
-                    match evaluate_internal(&body, &mut closure)? {
-                        EvalResult::Return(obj) => Ok(EvalResult::Value(obj)),
-                        EvalResult::Value(obj) => Ok(EvalResult::Value(obj)),
-                        other => Ok(other),
-                    }
-                }
-                Object::BuiltinFunction(func) => {
-                    let result = func(&args)?;
-                    Ok(EvalResult::Value(result))
-                }
-                _ => Err(format!("{} is not a function", name)),
-            }
+            Ok(EvalResult::Value(call_function(callee, args, &name, node.span)?))
         }
         
-        AstNode::ReturnStatement { value } => {
+        NodeKind::ReturnStatement { value } => {
             let return_value = match value {
-                Some(expr) => evaluate_internal(expr, env)?.unwrap_value(),
+                Some(expr) => evaluate_internal(expr, env)?.into_value()?,
                 None => Object::Null,
             };
             Ok(EvalResult::Return(return_value))
         }
         
-        AstNode::BreakStatement => Ok(EvalResult::Break),
-        AstNode::ContinueStatement => Ok(EvalResult::Continue),
+        NodeKind::ThrowExpression { value } => {
+            let (kind, message) = match evaluate_internal(value, env)?.into_value()? {
+                Object::Error { kind, message } => (kind, message),
+                other => ("Error".to_string(), other.to_string()),
+            };
+            Ok(EvalResult::Throw(LangError::thrown(kind, message).at(node.span)))
+        }
+        
+        NodeKind::TryStatement { body, catch_variable, catch_body, finally_body } => {
+            let unwind = |result: EvalResult| match result {
+                EvalResult::Throw(err) => Err(err),
+                other => Ok(other),
+            };
+            let outcome = evaluate_internal(body, env).and_then(unwind);
+            let outcome = match (outcome, catch_body) {
+                (Err(err), Some(catch_body)) if err.is_catchable() => {
+                    let catch_env = Environment::enclosed(env);
+                    if let Some(name) = catch_variable {
+                        catch_env.define(name.clone(), caught_value(err));
+                    }
+                    evaluate_internal(catch_body, &catch_env).and_then(unwind)
+                }
+                (outcome, _) => outcome,
+            };
+            
+            // Resource limits end the program without running `finally`
+            if outcome.as_ref().is_err_and(|err| !err.is_catchable()) {
+                return outcome;
+            }
+            // A `finally` that returns, breaks, continues or throws replaces the earlier outcome
+            if let Some(finally_body) = finally_body {
+                match evaluate_internal(finally_body, env)? {
+                    EvalResult::Value(_) => {}
+                    unwind => return Ok(unwind),
+                }
+            }
+            outcome
+        }
+        
+        NodeKind::BreakStatement => Ok(EvalResult::Break),
+        NodeKind::ContinueStatement => Ok(EvalResult::Continue),
         
-        AstNode::Array(elements) => {
-            let values: Result<Vec<Object>, String> = elements.iter()
-                .map(|elem| evaluate_internal(elem, env).map(|r| r.unwrap_value()))
+        NodeKind::Array(elements) => {
+            let values: Result<Vec<Object>, LangError> = elements.iter()
+                .map(|elem| evaluate_internal(elem, env).and_then(EvalResult::into_value))
                 .collect();
             Ok(EvalResult::Value(Object::Array(values?)))
         }
         
-        AstNode::ArrayAccess { array, index } => {
-            let array_obj = evaluate_internal(array, env)?.unwrap_value();
-            let index_obj = evaluate_internal(index, env)?.unwrap_value();
-            
-            match (&array_obj, &index_obj) {
-                (Object::Array(arr), Object::Number(i)) => {
-                    let idx = *i as i32;
-                    if idx < 0 {
-                        return Err("Array index cannot be negative".to_string());
-                    }
-                    let idx = idx as usize;
-                    if idx >= arr.len() {
-                        return Err(format!("Array index {} out of bounds (length {})", idx, arr.len()));
-                    }
-                    Ok(EvalResult::Value(arr[idx].clone()))
+        NodeKind::MapLiteral(entries) => {
+            let mut map = BTreeMap::new();
+            for (key_node, value_node) in entries {
+                let key = evaluate_internal(key_node, env)?.into_value()?;
+                let key = HashKey::from_object(&key).map_err(|e| e.at(key_node.span))?;
+                let value = evaluate_internal(value_node, env)?.into_value()?;
+                map.insert(key, value);
+            }
+            Ok(EvalResult::Value(Object::Map(map)))
+        }
+        
+        NodeKind::ArrayAccess { array, index } => {
+            let array_obj = evaluate_internal(array, env)?.into_value()?;
+            let index_obj = evaluate_internal(index, env)?.into_value()?;
+            let value = index_value(&array_obj, &index_obj).map_err(|e| e.at(node.span))?;
+            Ok(EvalResult::Value(value))
+        }
+        
+        NodeKind::Slice { array, start, end, step } => {
+            let array_obj = evaluate_internal(array, env)?.into_value()?;
+            let bound = |part: &Option<Box<AstNode>>| -> Result<Option<Object>, LangError> {
+                match part {
+                    Some(expr) => Ok(Some(evaluate_internal(expr, env)?.into_value()?)),
+                    None => Ok(None),
                 }
-                (Object::String(s), Object::Number(i)) => {
-                    let idx = *i as i32;
-                    if idx < 0 {
-                        return Err("String index cannot be negative".to_string());
-                    }
-                    let idx = idx as usize;
-                    let chars: Vec<char> = s.chars().collect();
-                    if idx >= chars.len() {
-                        return Err(format!("String index {} out of bounds (length {})", idx, chars.len()));
-                    }
-                    Ok(EvalResult::Value(Object::String(chars[idx].to_string())))
+            };
+            let (start, end, step) = (bound(start)?, bound(end)?, bound(step)?);
+            let slice = slice_value(array_obj, start.as_ref(), end.as_ref(), step.as_ref());
+            Ok(EvalResult::Value(slice.map_err(|e| e.at(node.span))?))
+        }
+        
+        NodeKind::SafeArrayAccess { array, index } => {
+            let array_obj = evaluate_internal(array, env)?.into_value()?;
+            if array_obj == Object::Null {
+                return Ok(EvalResult::Value(Object::Null));
+            }
+            let index_obj = evaluate_internal(index, env)?.into_value()?;
+            match index_value(&array_obj, &index_obj) {
+                Ok(value) => Ok(EvalResult::Value(value)),
+                Err(e) if matches!(e.code, ErrorCode::IndexOutOfBounds | ErrorCode::KeyNotFound) => {
+                    Ok(EvalResult::Value(Object::Null))
                 }
-                (Object::Array(_), _) => Err("Array index must be a number".to_string()),
-                (Object::String(_), _) => Err("String index must be a number".to_string()),
-                _ => Err(format!("Cannot index into {}", array_obj.type_name())),
+                Err(e) => Err(e.at(node.span)),
             }
         }
         
-        AstNode::Identifier(name) => {
+        NodeKind::Identifier(name) => {
             match env.get(name) {
-                Some(obj) => Ok(EvalResult::Value(obj.clone())),
-                None => Err(format!("Identifier not found: {}", name)),
+                Some(obj) => Ok(EvalResult::Value(obj)),
+                None => Err(runtime_error(ErrorCode::UndefinedVariable, format!("Identifier not found: {}", name), node.span)),
             }
         }
         
-        AstNode::Number(n) => {
+        NodeKind::Integer(n) => Ok(EvalResult::Value(Object::Integer(n.clone()))),
+        NodeKind::Number(n) => {
             if n.is_infinite() || n.is_nan() {
-                return Err("Invalid number: infinity or NaN".to_string());
+                return Err(runtime_error(ErrorCode::ArithmeticError, "Invalid number: infinity or NaN", node.span));
             }
             Ok(EvalResult::Value(Object::Number(*n)))
         },
-        AstNode::Boolean(b) => Ok(EvalResult::Value(Object::Boolean(*b))),
-        AstNode::String(s) => Ok(EvalResult::Value(Object::String(s.clone()))),
+        NodeKind::Boolean(b) => Ok(EvalResult::Value(Object::Boolean(*b))),
+        NodeKind::Null => Ok(EvalResult::Value(Object::Null)),
+        NodeKind::String(s) => Ok(EvalResult::Value(Object::String(s.clone()))),
+        NodeKind::InterpolatedString(parts) => {
+            let mut result = String::new();
+            for part in parts {
+                match part {
+                    TemplatePart::Literal(s) => result.push_str(s),
+                    TemplatePart::Expression(expr) => {
+                        result.push_str(&evaluate_internal(expr, env)?.into_value()?.to_string());
+                    }
+                }
+            }
+            Ok(EvalResult::Value(Object::String(result)))
+        }
         
-        AstNode::PrefixExpression { op, right } => {
-            let right_val = evaluate_internal(right, env)?.unwrap_value();
-            match op {
-                Token::Not => Ok(EvalResult::Value(Object::Boolean(!right_val.is_truthy()))),
-                Token::Minus => match right_val {
-                    Object::Number(n) => Ok(EvalResult::Value(Object::Number(-n))),
-                    _ => Err(format!("Cannot negate {}", right_val.type_name())),
-                },
-                _ => Err(format!("Unknown prefix operator: {:?}", op)),
+        NodeKind::PrefixExpression { op, right } => {
+            let right_val = evaluate_internal(right, env)?.into_value()?;
+            Ok(EvalResult::Value(evaluate_prefix(op, right_val).map_err(|e| e.at(node.span))?))
+        }
+        
+        NodeKind::Conditional { condition, consequence, alternative } => {
+            let branch = if evaluate_internal(condition, env)?.into_value()?.is_truthy() {
+                consequence
+            } else {
+                alternative
+            };
+            Ok(EvalResult::Value(evaluate_internal(branch, env)?.into_value()?))
+        }
+        
+        // Returns whichever operand decided the result, like most scripting languages
+        NodeKind::LogicalExpression { op, left, right } => {
+            let left_val = evaluate_internal(left, env)?.into_value()?;
+            let short_circuits = match op {
+                Token::And => !left_val.is_truthy(),
+                Token::Or => left_val.is_truthy(),
+                Token::NullCoalesce => left_val != Object::Null,
+                _ => return Err(runtime_error(ErrorCode::UnknownOperator, format!("Unknown logical operator: {:?}", op), node.span)),
+            };
+            if short_circuits {
+                return Ok(EvalResult::Value(left_val));
             }
+            Ok(EvalResult::Value(evaluate_internal(right, env)?.into_value()?))
         }
         
-        AstNode::InfixExpression { op, left, right } => {
-            let left_val = evaluate_internal(left, env)?.unwrap_value();
-            let right_val = evaluate_internal(right, env)?.unwrap_value();
+        NodeKind::InfixExpression { op, left, right } => {
+            let left_val = evaluate_internal(left, env)?.into_value()?;
+            let right_val = evaluate_internal(right, env)?.into_value()?;
             
-            match (&left_val, &right_val) {
-                (Object::Number(l), Object::Number(r)) => {
-                    evaluate_number_infix_op(op, *l, *r)
-                }
-                (Object::Boolean(l), Object::Boolean(r)) => {
-                    evaluate_boolean_infix_op(op, *l, *r)
-                }
-                (Object::String(l), Object::String(r)) => {
-                    evaluate_string_infix_op(op, l, r)
-                }
-                // Mixed type comparisons
-                (_, _) if matches!(op, Token::Equal | Token::NotEqual) => {
-                    match op {
-                        Token::Equal => Ok(EvalResult::Value(Object::Boolean(objects_equal(&left_val, &right_val)))),
-                        Token::NotEqual => Ok(EvalResult::Value(Object::Boolean(!objects_equal(&left_val, &right_val)))),
-                        _ => unreachable!(),
-                    }
-                }
-                _ => Err(format!("Type mismatch: cannot apply {:?} to {} and {}", 
-                    op, left_val.type_name(), right_val.type_name())),
+            evaluate_infix(op, &left_val, &right_val).map_err(|e| e.at(node.span))
+        }
+        
+        NodeKind::Assignment { target, op, value } => {
+            let rhs = evaluate_internal(value, env)?.into_value()?;
+            
+            let mut update = |slot: &mut Object| -> Result<Object, LangError> {
+                let new_value = match op {
+                    Some(op) => evaluate_infix(op, slot, &rhs)?.into_value()?,
+                    None => rhs.clone(),
+                };
+                store(slot, new_value.clone());
+                Ok(new_value)
+            };
+            
+            let result = update_place(target, env, &mut update).map_err(|e| e.at(node.span))?;
+            Ok(EvalResult::Value(result))
+        }
+    }
+}
+
+pub fn evaluate_prefix(op: &Token, right: Object) -> Result<Object, LangError> {
+    match op {
+        Token::Not => Ok(Object::Boolean(!right.is_truthy())),
+        Token::Minus => match right {
+            Object::Integer(n) => Ok(Object::Integer(-n)),
+            Object::Number(n) => Ok(Object::Number(-n)),
+            _ => Err(LangError::new(ErrorCode::TypeMismatch, format!("Cannot negate {}", right.type_name()))),
+        },
+        Token::BitNot => match right {
+            Object::Integer(n) => Ok(Object::Integer(!n)),
+            _ => Err(LangError::new(ErrorCode::TypeMismatch, format!("Cannot apply '~' to {}", right.type_name()))),
+        },
+        _ => Err(LangError::new(ErrorCode::UnknownOperator, format!("Unknown prefix operator: {:?}", op))),
+    }
+}
+
+pub fn evaluate_infix(op: &Token, left_val: &Object, right_val: &Object) -> Result<EvalResult, LangError> {
+    match (left_val, right_val) {
+        (Object::Integer(l), Object::Integer(r)) => {
+            evaluate_integer_infix_op(op, l, r)
+        }
+        (Object::Number(l), Object::Number(r)) => {
+            evaluate_number_infix_op(op, *l, *r)
+        }
+        // Mixing an integer with a float promotes the integer
+        (Object::Integer(_), Object::Number(r)) => {
+            evaluate_number_infix_op(op, integer_to_float(left_val)?, *r)
+        }
+        (Object::Number(l), Object::Integer(_)) => {
+            evaluate_number_infix_op(op, *l, integer_to_float(right_val)?)
+        }
+        (Object::Boolean(l), Object::Boolean(r)) => {
+            evaluate_boolean_infix_op(op, *l, *r)
+        }
+        (Object::String(l), Object::String(r)) => {
+            evaluate_string_infix_op(op, l, r)
+        }
+        // Mixed type comparisons
+        (_, _) if matches!(op, Token::Equal | Token::NotEqual) => {
+            match op {
+                Token::Equal => Ok(EvalResult::Value(Object::Boolean(objects_equal(left_val, right_val)))),
+                Token::NotEqual => Ok(EvalResult::Value(Object::Boolean(!objects_equal(left_val, right_val)))),
+                _ => unreachable!(),
+            }
+        }
+        _ => Err(LangError::new(ErrorCode::TypeMismatch, format!("Type mismatch: cannot apply {:?} to {} and {}", 
+            op, left_val.type_name(), right_val.type_name()))),
+    }
+}
+
+// Resolves an assignment target to its storage slot and lets `update` modify it in place.
+// Variables must already be declared with `let`; elements are reached through their
+// container, so `a[i][j] = v` mutates `a` itself.
+fn update_place(
+    target: &AstNode,
+    env: &Environment,
+    update: &mut dyn FnMut(&mut Object) -> Result<Object, LangError>,
+) -> Result<Object, LangError> {
+    match &target.kind {
+        NodeKind::Identifier(name) => match env.with_binding(name, update) {
+            Some(result) => result,
+            None => Err(runtime_error(ErrorCode::UndefinedVariable,
+                format!("Cannot assign to undeclared variable: {}", name), target.span)),
+        },
+        NodeKind::ArrayAccess { array, index } => {
+            let index_obj = evaluate_internal(index, env)?.into_value()?;
+            let mut update_element = |container: &mut Object| -> Result<Object, LangError> {
+                let element = element_mut(container, &index_obj).map_err(|e| e.at(target.span))?;
+                update(element)
+            };
+            update_place(array, env, &mut update_element)
+        }
+        NodeKind::FieldAccess { object, field } => {
+            let mut update_field = |container: &mut Object| -> Result<Object, LangError> {
+                let slot = field_mut(container, field).map_err(|e| e.at(target.span))?;
+                update(slot)
+            };
+            update_place(object, env, &mut update_field)
+        }
+        _ => Err(runtime_error(ErrorCode::InvalidAssignmentTarget, "Invalid assignment target", target.span)),
+    }
+}
+
+pub fn slice_value(container: Object, start: Option<&Object>, end: Option<&Object>, step: Option<&Object>) -> Result<Object, LangError> {
+    match container {
+        Object::Array(arr) => {
+            let items = slice_positions(arr.len(), start, end, step)?.into_iter().map(|i| arr[i].clone()).collect();
+            Ok(Object::Array(items))
+        }
+        Object::String(s) => {
+            let chars: Vec<char> = s.chars().collect();
+            let sliced = slice_positions(chars.len(), start, end, step)?.into_iter().map(|i| chars[i]).collect();
+            Ok(Object::String(sliced))
+        }
+        other => Err(LangError::new(ErrorCode::TypeMismatch, format!("Cannot slice {}", other.type_name()))),
+    }
+}
+
+pub fn index_value(container: &Object, index: &Object) -> Result<Object, LangError> {
+    match container {
+        Object::String(s) => {
+            let chars: Vec<char> = s.chars().collect();
+            let idx = checked_index(index, chars.len(), "String")?;
+            Ok(Object::String(chars[idx].to_string()))
+        }
+        _ => element(container, index).cloned(),
+    }
+}
+
+// Array element or map value, borrowed from the container. Characters of a string
+// are not stored as values, so strings are read with `index_value` instead.
+pub fn element<'a>(container: &'a Object, index: &Object) -> Result<&'a Object, LangError> {
+    match (container, index) {
+        (Object::Array(arr), i) => {
+            let idx = checked_index(i, arr.len(), "Array")?;
+            Ok(&arr[idx])
+        }
+        (Object::Map(map), key) => {
+            let key = HashKey::from_object(key)?;
+            map.get(&key).ok_or_else(|| LangError::new(ErrorCode::KeyNotFound, format!("Key {} not found in map", key)))
+        }
+        _ => Err(LangError::new(ErrorCode::TypeMismatch, format!("Cannot index into {}", container.type_name()))),
+    }
+}
+
+pub fn element_mut<'a>(container: &'a mut Object, index: &Object) -> Result<&'a mut Object, LangError> {
+    match (container, index) {
+        (Object::Array(arr), i) => {
+            let idx = checked_index(i, arr.len(), "Array")?;
+            Ok(&mut arr[idx])
+        }
+        // Assigning to a missing key inserts it
+        (Object::Map(map), key) => {
+            let key = HashKey::from_object(key)?;
+            if !map.contains_key(&key) {
+                record_resize(0, key.entry_size());
             }
+            Ok(map.entry(key).or_insert(Object::Null))
         }
+        (Object::String(_), _) => Err(LangError::new(ErrorCode::TypeMismatch, "Cannot assign to a string index; strings are immutable")),
+        (other, _) => Err(LangError::new(ErrorCode::TypeMismatch, format!("Cannot index into {}", other.type_name()))),
     }
 }
 
-fn evaluate_string_infix_op(op: &Token, l: &str, r: &str) -> Result<EvalResult, String> {
+pub fn field_mut<'a>(container: &'a mut Object, field: &str) -> Result<&'a mut Object, LangError> {
+    match container {
+        Object::Instance { struct_type, fields } => match struct_type.field_index(field) {
+            Some(idx) => Ok(&mut fields[idx]),
+            None => Err(LangError::new(ErrorCode::UnknownField, format!("{} has no field '{}'", struct_type.name, field))),
+        },
+        other => Err(LangError::new(ErrorCode::TypeMismatch, format!("Cannot assign field '{}' on {}", field, other.type_name()))),
+    }
+}
+
+// Validates an index against the length of an array or string. Negative indices
+// count from the end; floats are accepted only when they hold a whole number.
+fn checked_index(index: &Object, len: usize, what: &str) -> Result<usize, LangError> {
+    let idx = integer_index(index, what)?;
+    let resolved = if idx.is_negative() { &idx + len } else { idx.clone() };
+    match resolved.to_usize() {
+        Some(i) if i < len => Ok(i),
+        _ => Err(LangError::new(ErrorCode::IndexOutOfBounds, format!("{} index {} out of bounds (length {})", what, idx, len))),
+    }
+}
+
+fn integer_index(index: &Object, what: &str) -> Result<BigInt, LangError> {
+    match index {
+        Object::Integer(i) => Ok(i.clone()),
+        Object::Number(n) if n.fract() == 0.0 => Ok(BigInt::from_f64(*n).unwrap_or_default()),
+        other => Err(LangError::new(ErrorCode::TypeMismatch,
+            format!("{} index must be an integer, got {}", what, other.type_name()))),
+    }
+}
+
+// Positions selected by a Python-style slice. Out-of-range bounds are clamped
+// rather than rejected, and a negative step walks backwards.
+fn slice_positions(len: usize, start: Option<&Object>, end: Option<&Object>, step: Option<&Object>) -> Result<Vec<usize>, LangError> {
+    let len = len as i64;
+    // Bounds far outside the sequence behave like its ends, so saturate them
+    let bound = |value: Option<&Object>| -> Result<Option<i64>, LangError> {
+        match value {
+            None | Some(Object::Null) => Ok(None),
+            Some(obj) => {
+                let i = integer_index(obj, "Slice")?;
+                Ok(Some(i.to_i64().unwrap_or(if i.is_negative() { i64::MIN } else { i64::MAX })))
+            }
+        }
+    };
+    let step = bound(step)?.unwrap_or(1);
+    if step == 0 {
+        return Err(LangError::new(ErrorCode::InvalidArgument, "Slice step cannot be zero"));
+    }
+    
+    let resolve = |i: i64, low: i64, high: i64| {
+        let i = if i < 0 { i.saturating_add(len) } else { i };
+        i.clamp(low, high)
+    };
+    let mut positions = Vec::new();
+    if step > 0 {
+        let start = bound(start)?.map_or(0, |i| resolve(i, 0, len));
+        let end = bound(end)?.map_or(len, |i| resolve(i, 0, len));
+        let mut i = start;
+        while i < end {
+            positions.push(i as usize);
+            i = i.saturating_add(step);
+        }
+    } else {
+        let start = bound(start)?.map_or(len - 1, |i| resolve(i, -1, len - 1));
+        let end = bound(end)?.map_or(-1, |i| resolve(i, -1, len - 1));
+        let mut i = start;
+        while i > end {
+            positions.push(i as usize);
+            i = i.saturating_add(step);
+        }
+    }
+    Ok(positions)
+}
+
+fn integer_to_float(value: &Object) -> Result<f64, LangError> {
+    match value.as_float() {
+        Some(f) if f.is_finite() => Ok(f),
+        _ => Err(LangError::new(ErrorCode::ArithmeticError, "Integer too large to convert to float")),
+    }
+}
+
+fn evaluate_string_infix_op(op: &Token, l: &str, r: &str) -> Result<EvalResult, LangError> {
     match op {
-        Token::Plus => Ok(EvalResult::Value(Object::String(format!("{}{}", l, r)))),
+        Token::Plus => {
+            reserve_memory(l.len() + r.len())?;
+            Ok(EvalResult::Value(Object::String(format!("{}{}", l, r))))
+        }
         Token::Equal => Ok(EvalResult::Value(Object::Boolean(l == r))),
         Token::NotEqual => Ok(EvalResult::Value(Object::Boolean(l != r))),
         Token::LessThan => Ok(EvalResult::Value(Object::Boolean(l < r))),
         Token::GreaterThan => Ok(EvalResult::Value(Object::Boolean(l > r))),
         Token::LessThanOrEqual => Ok(EvalResult::Value(Object::Boolean(l <= r))),
         Token::GreaterThanOrEqual => Ok(EvalResult::Value(Object::Boolean(l >= r))),
-        _ => Err(format!("Unknown operator for strings: {:?}", op)),
+        _ => Err(LangError::new(ErrorCode::UnknownOperator, format!("Unknown operator for strings: {:?}", op))),
     }
 }
 
-fn objects_equal(left: &Object, right: &Object) -> bool {
+pub fn objects_equal(left: &Object, right: &Object) -> bool {
     match (left, right) {
+        (Object::Integer(l), Object::Integer(r)) => l == r,
         (Object::Number(l), Object::Number(r)) => (l - r).abs() < f64::EPSILON,
+        (Object::Integer(_), Object::Number(r)) => left.as_float() == Some(*r),
+        (Object::Number(l), Object::Integer(_)) => right.as_float() == Some(*l),
         (Object::Boolean(l), Object::Boolean(r)) => l == r,
         (Object::String(l), Object::String(r)) => l == r,
         (Object::Null, Object::Null) => true,
         (Object::Array(l), Object::Array(r)) => {
             l.len() == r.len() && l.iter().zip(r.iter()).all(|(a, b)| objects_equal(a, b))
         },
+        (Object::Map(l), Object::Map(r)) => {
+            l.len() == r.len() && l.iter().zip(r.iter()).all(|((ka, va), (kb, vb))| ka == kb && objects_equal(va, vb))
+        },
+        (Object::Struct(l), Object::Struct(r)) => Rc::ptr_eq(l, r),
+        (Object::Instance { struct_type: lt, fields: lf }, Object::Instance { struct_type: rt, fields: rf }) => {
+            Rc::ptr_eq(lt, rt) && lf.iter().zip(rf.iter()).all(|(a, b)| objects_equal(a, b))
+        },
+        (Object::Enum(l), Object::Enum(r)) => Rc::ptr_eq(l, r),
+        (Object::Error { .. }, Object::Error { .. }) => left == right,
+        (Object::Variant { enum_type: lt, variant: lv, values: lx }, Object::Variant { enum_type: rt, variant: rv, values: rx }) => {
+            Rc::ptr_eq(lt, rt) && lv == rv && lx.iter().zip(rx.iter()).all(|(a, b)| objects_equal(a, b))
+        },
         _ => false,
     }
 }
 
-fn evaluate_number_infix_op(op: &Token, l: f64, r: f64) -> Result<EvalResult, String> {
+// Integer arithmetic is exact. Division truncates toward zero and `%` takes the
+// sign of the dividend, matching Rust. Bitwise operators act on the two's
+// complement representation, so `~5` is -6.
+fn evaluate_integer_infix_op(op: &Token, l: &BigInt, r: &BigInt) -> Result<EvalResult, LangError> {
+    let result = match op {
+        Token::Plus => Object::Integer(l + r),
+        Token::Minus => Object::Integer(l - r),
+        Token::Multiply => Object::Integer(l * r),
+        Token::Divide => {
+            if r.is_zero() {
+                return Err(LangError::new(ErrorCode::DivisionByZero, "Division by zero"));
+            }
+            Object::Integer(l / r)
+        },
+        Token::Modulo => {
+            if r.is_zero() {
+                return Err(LangError::new(ErrorCode::DivisionByZero, "Modulo by zero"));
+            }
+            Object::Integer(l % r)
+        },
+        // A negative exponent cannot stay an integer
+        Token::Power if r.is_negative() => {
+            return evaluate_number_infix_op(op, l.to_f64().unwrap_or(f64::INFINITY), r.to_f64().unwrap_or(f64::NEG_INFINITY));
+        },
+        Token::Power => match r.to_u32() {
+            Some(exp) => Object::Integer(checked_pow(l, exp)?),
+            None => return Err(LangError::new(ErrorCode::ArithmeticError, "Exponent too large")),
+        },
+        Token::BitAnd => Object::Integer(l & r),
+        Token::BitOr => Object::Integer(l | r),
+        Token::BitXor => Object::Integer(l ^ r),
+        Token::ShiftLeft | Token::ShiftRight => {
+            if r.is_negative() {
+                return Err(LangError::new(ErrorCode::ArithmeticError, "Negative shift amount"));
+            }
+            let Some(shift) = r.to_usize() else {
+                return Err(LangError::new(ErrorCode::ArithmeticError, "Shift amount too large"));
+            };
+            match op {
+                Token::ShiftLeft => {
+                    if !l.is_zero() {
+                        reserve_memory(((l.bits() as usize).saturating_add(shift)) / 8)?;
+                    }
+                    Object::Integer(l << shift)
+                }
+                _ => Object::Integer(l >> shift),
+            }
+        },
+        Token::Equal => Object::Boolean(l == r),
+        Token::NotEqual => Object::Boolean(l != r),
+        Token::LessThan => Object::Boolean(l < r),
+        Token::GreaterThan => Object::Boolean(l > r),
+        Token::LessThanOrEqual => Object::Boolean(l <= r),
+        Token::GreaterThanOrEqual => Object::Boolean(l >= r),
+        _ => return Err(LangError::new(ErrorCode::UnknownOperator, format!("Unknown operator for integers: {:?}", op))),
+    };
+    Ok(EvalResult::Value(result))
+}
+
+fn evaluate_number_infix_op(op: &Token, l: f64, r: f64) -> Result<EvalResult, LangError> {
     // Check for invalid numbers
     if l.is_infinite() || l.is_nan() || r.is_infinite() || r.is_nan() {
-        return Err("Cannot perform operations with infinity or NaN".to_string());
+        return Err(LangError::new(ErrorCode::ArithmeticError, "Cannot perform operations with infinity or NaN"));
     }
     
     match op {
         Token::Plus => {
             let result = l + r;
             if result.is_infinite() {
-                return Err("Arithmetic overflow".to_string());
+                return Err(LangError::new(ErrorCode::ArithmeticError, "Arithmetic overflow"));
             }
             Ok(EvalResult::Value(Object::Number(result)))
         },
         Token::Minus => {
             let result = l - r;
             if result.is_infinite() {
-                return Err("Arithmetic overflow".to_string());
+                return Err(LangError::new(ErrorCode::ArithmeticError, "Arithmetic overflow"));
             }
             Ok(EvalResult::Value(Object::Number(result)))
         },
         Token::Multiply => {
             let result = l * r;
             if result.is_infinite() {
-                return Err("Arithmetic overflow".to_string());
+                return Err(LangError::new(ErrorCode::ArithmeticError, "Arithmetic overflow"));
             }
             Ok(EvalResult::Value(Object::Number(result)))
         },
         Token::Divide => {
             if r == 0.0 {
-                return Err("Division by zero".to_string());
+                return Err(LangError::new(ErrorCode::DivisionByZero, "Division by zero"));
             }
             let result = l / r;
             if result.is_infinite() || result.is_nan() {
-                return Err("Division resulted in infinity or NaN".to_string());
+                return Err(LangError::new(ErrorCode::ArithmeticError, "Division resulted in infinity or NaN"));
             }
             Ok(EvalResult::Value(Object::Number(result)))
         },
         Token::Modulo => {
             if r == 0.0 {
-                return Err("Modulo by zero".to_string());
+                return Err(LangError::new(ErrorCode::DivisionByZero, "Modulo by zero"));
             }
             let result = l % r;
             Ok(EvalResult::Value(Object::Number(result)))
         },
+        Token::Power => {
+            let result = l.powf(r);
+            if result.is_infinite() || result.is_nan() {
+                return Err(LangError::new(ErrorCode::ArithmeticError, "Power operation resulted in infinity or NaN"));
+            }
+            Ok(EvalResult::Value(Object::Number(result)))
+        },
         Token::Equal => Ok(EvalResult::Value(Object::Boolean((l - r).abs() < f64::EPSILON))),
         Token::NotEqual => Ok(EvalResult::Value(Object::Boolean((l - r).abs() >= f64::EPSILON))),
         Token::LessThan => Ok(EvalResult::Value(Object::Boolean(l < r))),
         Token::GreaterThan => Ok(EvalResult::Value(Object::Boolean(l > r))),
         Token::LessThanOrEqual => Ok(EvalResult::Value(Object::Boolean(l <= r))),
         Token::GreaterThanOrEqual => Ok(EvalResult::Value(Object::Boolean(l >= r))),
-        _ => Err(format!("Unknown operator for numbers: {:?}", op)),
+        Token::BitAnd | Token::BitOr | Token::BitXor | Token::ShiftLeft | Token::ShiftRight => {
+            Err(LangError::new(ErrorCode::TypeMismatch, format!("Bitwise operator {:?} requires integers", op)))
+        },
+        _ => Err(LangError::new(ErrorCode::UnknownOperator, format!("Unknown operator for numbers: {:?}", op))),
     }
 }
 
-fn evaluate_boolean_infix_op(op: &Token, l: bool, r: bool) -> Result<EvalResult, String> {
+fn evaluate_boolean_infix_op(op: &Token, l: bool, r: bool) -> Result<EvalResult, LangError> {
     match op {
         Token::Equal => Ok(EvalResult::Value(Object::Boolean(l == r))),
         Token::NotEqual => Ok(EvalResult::Value(Object::Boolean(l != r))),
-        Token::And => Ok(EvalResult::Value(Object::Boolean(l && r))),
-        Token::Or => Ok(EvalResult::Value(Object::Boolean(l || r))),
-        _ => Err(format!("Unknown operator for booleans: {:?}", op)),
+        _ => Err(LangError::new(ErrorCode::UnknownOperator, format!("Unknown operator for booleans: {:?}", op))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+    use crate::parser::Parser;
+
+    // Runs a program and displays its value, on a thread with the stack the default
+    // limits need, as the server does
+    fn run(source: &str) -> Result<String, LangError> {
+        run_with(source, Limits::default())
+    }
+
+    fn run_with(source: &str, limits: Limits) -> Result<String, LangError> {
+        let source = source.to_string();
+        let stack_size = limits.stack_needed();
+        let limits = Limits { stack_size: Some(stack_size), ..limits };
+        std::thread::Builder::new()
+            .stack_size(stack_size)
+            .spawn(move || {
+                let (program, mut errors) = Parser::new(tokenize(&source)?).parse_program();
+                if !errors.is_empty() {
+                    return Err(errors.remove(0));
+                }
+                evaluate(&program, &Environment::new(), limits).map(|value| value.to_string())
+            })
+            .unwrap()
+            .join()
+            .unwrap()
+    }
+
+    #[test]
+    fn runtime_errors_report_line_and_column() {
+        let err = run("let a = 1;\nlet b = a + missing;").unwrap_err().to_string();
+        assert_eq!(err, "Identifier not found: missing at line 2, column 13");
+    }
+
+    #[test]
+    fn errors_carry_a_stable_code_and_kind() {
+        let err = run("let a = 1;\nlet b = a / 0;").unwrap_err();
+        assert_eq!(err.code, ErrorCode::DivisionByZero);
+        assert_eq!(err.kind(), crate::error::ErrorKind::Runtime);
+        assert_eq!(err.span.map(|span| span.start.line), Some(2));
+
+        let err = run("let a = \"open;").unwrap_err();
+        assert_eq!(err.code, ErrorCode::UnterminatedString);
+        assert_eq!(err.kind(), crate::error::ErrorKind::Lexer);
+    }
+
+    #[test]
+    fn assignment_updates_the_nearest_binding() {
+        let value = run("let x = 0; while (x < 5) x = x + 1; x").unwrap();
+        assert_eq!(value.to_string(), "5");
+        // Previously `i = i + 1` was an unknown operator, so the loop never advanced
+        assert!(run("for (let i = 0; i < 3; i = i + 1) {}").is_ok());
+
+        let value = run("let x = 10; x -= 3; x *= 4; x /= 2; x %= 5; x").unwrap();
+        assert_eq!(value.to_string(), "4");
+
+        let value = run("let arr = [1, 2, 3]; arr[1] = 20; arr[2] += 5; arr").unwrap();
+        assert_eq!(value.to_string(), "[1, 20, 8]");
+    }
+
+    #[test]
+    fn assignment_requires_a_declared_target() {
+        let err = run("missing = 1;").unwrap_err();
+        assert_eq!(err.code, ErrorCode::UndefinedVariable);
+        let err = run("let arr = [1];\narr[3] = 2;").unwrap_err();
+        assert_eq!(err.code, ErrorCode::IndexOutOfBounds);
+    }
+
+    #[test]
+    fn blocks_update_outer_variables() {
+        let value = run("let total = 0;\nfor (let i = 0; i < 5; i = i + 1) { total += i; }\ntotal").unwrap();
+        assert_eq!(value.to_string(), "10");
+
+        let value = run("let x = 1; if (true) { let y = 2; x = x + y; } x").unwrap();
+        assert_eq!(value.to_string(), "3");
+        assert_eq!(run("if (true) { let y = 2; } y").unwrap_err().code, ErrorCode::UndefinedVariable);
+    }
+
+    #[test]
+    fn functions_can_recurse_and_call_later_siblings() {
+        let value = run("fn fact(n) { if (n < 2) { return 1; } return n * fact(n - 1); }\nfact(5)").unwrap();
+        assert_eq!(value.to_string(), "120");
+
+        let source = "fn is_even(n) { if (n == 0) { return true; } return is_odd(n - 1); }\n\
+                      fn is_odd(n) { if (n == 0) { return false; } return is_even(n - 1); }\n\
+                      is_even(10)";
+        assert_eq!(run(source).unwrap().to_string(), "true");
+    }
+
+    #[test]
+    fn function_values_can_be_called_from_any_expression() {
+        let source = "fn make_adder(n) { return fn(x) { return x + n; }; }\n\
+                      let handlers = [fn(x) { return x * 2; }];\n\
+                      make_adder(1)(2) + handlers[0](10)";
+        assert_eq!(run(source).unwrap().to_string(), "23");
+    }
+
+    #[test]
+    fn higher_order_builtins_call_back_into_user_code() {
+        let source = "let xs = [3, 1, 2];\n\
+                      let doubled = map(xs, fn(x) { return x * 2; });\n\
+                      let sorted = sort_by(doubled, fn(a, b) { return a - b; });\n\
+                      [sorted, filter(xs, fn(x) { return x > 1; }), reduce(xs, fn(a, x) { return a + x; }, 10),\n\
+                       any(xs, fn(x) { return x == 2; }), all(xs, fn(x) { return x > 1; })]";
+        assert_eq!(run(source).unwrap().to_string(), "[[2, 4, 6], [3, 2], 16, true, false]");
+    }
+
+    #[test]
+    fn maps_support_literals_indexing_and_builtins() {
+        let source = "let counts = {};\n\
+                      for (let i = 0; i < 3; i = i + 1) { let w = [\"a\", \"b\", \"a\"][i]; \
+                      if (has_key(counts, w)) { counts[w] += 1; } else { counts[w] = 1; } }\n\
+                      counts";
+        assert_eq!(run(source).unwrap().to_string(), "{\"a\": 2, \"b\": 1}");
+
+        let source = "let m = { \"x\": 1, \"y\": [2] };\n\
+                      m = delete(m, \"x\");\n\
+                      [keys(m), values(m), m == { \"y\": [2] }]";
+        assert_eq!(run(source).unwrap().to_string(), "[[\"y\"], [[2]], true]");
+
+        // A block still parses as a block, not as a map literal
+        assert_eq!(run("let a = 1; { a = 2; } a").unwrap().to_string(), "2");
+        assert_eq!(run("let m = {};\nm[\"missing\"]").unwrap_err().code, ErrorCode::KeyNotFound);
+    }
+
+    #[test]
+    fn integers_are_exact_and_never_overflow() {
+        let source = "let f = 1;\nfor (let i = 2; i <= 25; i = i + 1) { f *= i; }\nf";
+        assert_eq!(run(source).unwrap().to_string(), "15511210043330985984000000");
+        assert_eq!(run("pow(2, 100) - pow(2, 100) + 1").unwrap().to_string(), "1");
+        assert_eq!(run("9007199254740993 == 9007199254740992").unwrap().to_string(), "false");
+
+        let err = run("pow(10, 400) * 1.5").unwrap_err();
+        assert_eq!(err.code, ErrorCode::ArithmeticError);
+    }
+
+    #[test]
+    fn integer_division_truncates_toward_zero() {
+        assert_eq!(run("[7 / 2, -7 / 2, 7 % 3, -7 % 3, 7 % -3]").unwrap().to_string(), "[3, -3, 1, -1, 1]");
+        // A float operand promotes the whole operation
+        assert_eq!(run("[7 / 2.0, 7.5 % 2]").unwrap().to_string(), "[3.5, 1.5]");
+        assert_eq!(run("7 / 0").unwrap_err().code, ErrorCode::DivisionByZero);
+    }
+
+    #[test]
+    fn type_reports_number_for_both_numeric_kinds() {
+        let value = run("[type(1), type(1.5), is_integer(1), is_integer(1.0), is_integer(\"1\")]").unwrap();
+        assert_eq!(value.to_string(), "[\"number\", \"number\", true, false, false]");
+    }
+
+    #[test]
+    fn logical_operators_short_circuit_and_return_operands() {
+        assert_eq!(run("let a = []; len(a) && a[0]").unwrap().to_string(), "0");
+        assert_eq!(run("let x = 0; x != 0 && 10 / x > 1").unwrap().to_string(), "false");
+        assert_eq!(run("[0 || \"default\", 1 && 2, \"\" || false]").unwrap().to_string(), "[\"default\", 2, false]");
+    }
+
+    #[test]
+    fn interpolated_strings_format_embedded_expressions() {
+        let value = run("let n = 21; let xs = [1, \"a\"];\n\"total: ${n * 2}, items: ${xs}, nested: ${\"<${n}>\"}\"").unwrap();
+        assert_eq!(value.to_string(), "total: 42, items: [1, \"a\"], nested: <21>");
+    }
+
+    #[test]
+    fn errors_inside_placeholders_point_into_the_string() {
+        let err = run("let n = 1;\nlet s = \"value: ${n + missing}\";").unwrap_err();
+        assert_eq!(err.to_string(), "Identifier not found: missing at line 2, column 23");
+
+        let err = run("let s = \"${1 +}\";").unwrap_err();
+        assert_eq!(err.span.map(|span| span.start.line), Some(1));
+        assert_eq!(err.span.map(|span| span.start.column), Some(15));
+    }
+
+    #[test]
+    fn null_values_can_be_written_tested_and_defaulted() {
+        let value = run("let xs = [];\n[first(xs) == null, type(null), first(xs) ?? \"none\", 0 ?? 5]").unwrap();
+        assert_eq!(value.to_string(), "[true, \"null\", \"none\", 0]");
+
+        // `?[` yields null for a null target or a missing element instead of failing
+        let value = run("let m = { \"a\": [1] };\nlet none = null;\n[m?[\"b\"], none?[0], m?[\"a\"]?[0], m[\"a\"]?[3] ?? -1]").unwrap();
+        assert_eq!(value.to_string(), "[null, null, 1, -1]");
+
+        // `??` only evaluates its right side when needed
+        assert_eq!(run("1 ?? missing").unwrap().to_string(), "1");
+    }
+
+    #[test]
+    fn bitwise_exponent_and_ternary_operators() {
+        let value = run("[2 ** 3 ** 2, 2 + 3 ** 2 * 2, 1 | 2 & 3, 5 ^ 1, 1 << 4, -16 >> 2, ~5]").unwrap();
+        assert_eq!(value.to_string(), "[512, 20, 3, 4, 16, -4, -6]");
+
+        // The ternary is right-associative and only evaluates the chosen branch
+        let value = run("let n = 0;\n[n > 0 ? 1 : n < 0 ? -1 : 0, true ? \"yes\" : missing]").unwrap();
+        assert_eq!(value.to_string(), "[0, \"yes\"]");
+
+        assert_eq!(run("1.5 & 1").unwrap_err().code, ErrorCode::TypeMismatch);
+    }
+
+    #[test]
+    fn for_in_loops_over_collections_and_ranges() {
+        let source = "let out = [];\n\
+                      for x in [1, 2, 3, 4] { if (x == 2) { continue; } if (x == 4) { break; } out = push(out, x); }\n\
+                      for ch in \"hé\" { out = push(out, ch); }\n\
+                      for i in 0..3 { out = push(out, i); }\n\
+                      for i in 3..=4 { out = push(out, i); }\n\
+                      for k, v in { \"a\": 1, \"b\": 2 } { out = push(out, k + to_string(v)); }\n\
+                      out";
+        assert_eq!(run(source).unwrap().to_string(), "[1, 3, \"h\", \"é\", 0, 1, 2, 3, 4, \"a1\", \"b2\"]");
+
+        let limits = Limits { max_steps: 1000, ..Limits::default() };
+        let err = run_with("for x in 0..100000 { }", limits).unwrap_err();
+        assert_eq!(err.code, ErrorCode::StepLimit);
+    }
+
+    #[test]
+    fn slices_and_negative_indices() {
+        let value = run("let a = [0, 1, 2, 3, 4];\n[a[-1], a[1:3], a[:2], a[3:], a[::2], a[::-1], a[-2:]]").unwrap();
+        assert_eq!(value.to_string(), "[4, [1, 2], [0, 1], [3, 4], [0, 2, 4], [4, 3, 2, 1, 0], [3, 4]]");
+
+        // Strings index and slice by character, not byte
+        let value = run("let s = \"héllo\";\n[s[1], s[-1], s[1:3], s[::-1]]").unwrap();
+        assert_eq!(value.to_string(), "[\"é\", \"o\", \"él\", \"olléh\"]");
+
+        assert_eq!(run("[1, 2][-3]").unwrap_err().code, ErrorCode::IndexOutOfBounds);
+        assert_eq!(run("[1, 2][::0]").unwrap_err().code, ErrorCode::InvalidArgument);
+    }
+
+    #[test]
+    fn structs_with_fields_and_methods() {
+        let source = "struct Point { x, y }\n\
+                      impl Point {\n\
+                          fn origin() { return Point(0, 0); }\n\
+                          fn moved(self, dx) { return Point(self.x + dx, self.y); }\n\
+                          fn norm1(self) { return abs(self.x) + abs(self.y); }\n\
+                      }\n\
+                      let p = Point.origin().moved(-3);\n\
+                      p.y = 4;\n\
+                      [p.x, p.norm1(), type(p), \"abc\".upper(), [1, 2].len()]";
+        assert_eq!(run(source).unwrap().to_string(), "[-3, 7, \"Point\", \"ABC\", 2]");
+
+        assert_eq!(run("struct P { x }\nP(1).z").unwrap_err().code, ErrorCode::UnknownField);
+        assert_eq!(run("struct P { x }\nP(1, 2)").unwrap_err().code, ErrorCode::ArgumentCount);
+    }
+
+    #[test]
+    fn match_binds_patterns_and_honours_guards() {
+        let source = "enum Shape { Circle(r), Rect(w, h), Empty }\n\
+                      impl Shape { fn area(self) { return match self { Shape.Circle(r) => 3 * r * r, Shape.Rect(w, h) => w * h, _ => 0 }; } }\n\
+                      fn describe(x) {\n\
+                          return match x {\n\
+                              0 => \"zero\",\n\
+                              -1 => \"minus one\",\n\
+                              n if type(n) == \"number\" && n > 100 => \"big\",\n\
+                              [first, ..rest] => \"list of \" + to_string(len(rest) + 1) + \" from \" + to_string(first),\n\
+                              Shape.Rect(w, _) if w == 0 => \"flat\",\n\
+                              Shape.Circle(_) => \"round\",\n\
+                              _ => \"other\",\n\
+                          };\n\
+                      }\n\
+                      [describe(0), describe(-1), describe(500), describe([7, 8, 9]), describe(Shape.Rect(0, 3)),\n\
+                       describe(Shape.Circle(1)), describe(Shape.Empty), Shape.Rect(2, 3).area(), type(Shape.Empty)]";
+        assert_eq!(
+            run(source).unwrap(),
+            "[\"zero\", \"minus one\", \"big\", \"list of 3 from 7\", \"flat\", \"round\", \"other\", 6, \"Shape\"]"
+        );
+
+        let err = run("match 5 { 1 => 2 }").unwrap_err();
+        assert_eq!(err.code, ErrorCode::NoMatchingArm);
+    }
+
+    #[test]
+    fn non_exhaustive_matches_are_checker_warnings() {
+        let source = "enum Light { Red, Amber, Green }\nlet l = Light.Red;\nmatch l { Light.Red => 1, Light.Green => 2 }";
+        let (program, errors) = Parser::new(tokenize(source).unwrap()).parse_program();
+        assert!(errors.is_empty());
+        let warnings = crate::checker::check_program(&program);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].code, ErrorCode::NonExhaustiveMatch);
+        assert_eq!(warnings[0].kind(), crate::error::ErrorKind::Checker);
+        assert_eq!(warnings[0].code.severity(), crate::error::Severity::Warning);
+        assert_eq!(warnings[0].message, "Non-exhaustive match: missing Light.Amber");
+    }
+
+    #[test]
+    fn finally_runs_after_try_and_catch_whatever_way_they_exit() {
+        let source = "let log = [];\n\
+                      fn early() { try { log = push(log, \"try\"); return \"from try\"; } finally { log = push(log, \"finally\"); } }\n\
+                      let r = early();\n\
+                      try { to_number(\"abc\"); log = push(log, \"unreachable\"); }\n\
+                      catch (e) { log = push(log, e.kind); }\n\
+                      finally { log = push(log, \"after catch\"); }\n\
+                      for i in 0..3 {\n\
+                          try { if (i == 1) { continue; } if (i == 2) { break; } log = push(log, i); }\n\
+                          finally { log = push(log, \"f\" + to_string(i)); }\n\
+                      }\n\
+                      try { try { throw \"inner\"; } finally { log = push(log, \"inner finally\"); } }\n\
+                      catch (e) { log = push(log, e.message); }\n\
+                      [r, log]";
+        assert_eq!(
+            run(source).unwrap(),
+            "[\"from try\", [\"try\", \"finally\", \"InvalidArgument\", \"after catch\", 0, \"f0\", \"f1\", \"f2\", \
+             \"inner finally\", \"inner\"]]"
+        );
+    }
+
+    #[test]
+    fn errors_thrown_from_catch_still_run_finally() {
+        let source = "let log = [];\n\
+                      try {\n\
+                          try { throw error(\"First\", \"one\"); }\n\
+                          catch (e) { log = push(log, e.kind); throw error(\"Second\", \"two\"); }\n\
+                          finally { log = push(log, \"finally\"); }\n\
+                      } catch (e) { log = push(log, e.kind + \": \" + e.message); }\n\
+                      log";
+        assert_eq!(run(source).unwrap(), "[\"First\", \"finally\", \"Second: two\"]");
+
+        let err = run("fn f() { throw \"boom\"; }\nf();").unwrap_err();
+        assert_eq!(err.to_string(), "Uncaught Error: boom at line 1, column 10");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn runaway_recursion_is_a_clean_stack_overflow() {
+        let err = run("fn f(n) { return f(n + 1); }\nf(0)").unwrap_err();
+        assert_eq!(err.code, ErrorCode::StackOverflow);
+        assert_eq!(err.message, "Stack overflow: maximum call depth of 1000 exceeded");
+
+        // Chains the parser builds in a loop are still bounded when evaluated
+        let err = run(&format!("1{}", " + 1".repeat(6000))).unwrap_err();
+        assert_eq!(err.message, "Stack overflow: maximum nesting depth of 5000 exceeded");
+
+        let limits = Limits { max_call_depth: 10, ..Limits::default() };
+        assert!(run_with("fn f(n) { if (n == 0) { return 0; } return f(n - 1); }\nf(9)", limits).is_ok());
+        assert!(run_with("fn f(n) { if (n == 0) { return 0; } return f(n - 1); }\nf(10)", limits).is_err());
+    }
+
+    #[test]
+    fn one_step_budget_covers_loops_and_recursion() {
+        // Nested loops are no longer capped per loop
+        let source = "let n = 0;\nfor i in 0..200 { for j in 0..200 { n += 1; } }\nn";
+        assert_eq!(run(source).unwrap(), "40000");
+
+        let limits = Limits { max_steps: 500, ..Limits::default() };
+        let err = run_with("let i = 0;\nwhile (true) { i += 1; }", limits).unwrap_err();
+        assert_eq!(err.code, ErrorCode::StepLimit);
+        assert_eq!(err.kind(), crate::error::ErrorKind::LimitExceeded);
+        assert_eq!(err.message, "Execution exceeded the budget of 500 steps (possible infinite loop)");
+
+        // Recursion draws from the same budget, long before the call depth limit
+        let err = run_with("fn f(n) { if (n == 0) { return 0; } return f(n - 1); }\nf(400)", limits).unwrap_err();
+        assert_eq!(err.code, ErrorCode::StepLimit);
+    }
+
+    #[test]
+    fn memory_limit_covers_values_and_output() {
+        let limits = Limits { max_memory: 1024 * 1024, ..Limits::default() };
+        // Refused before the array is built
+        let err = run_with("let big = 0..10000000;", limits).unwrap_err();
+        assert_eq!(err.code, ErrorCode::MemoryLimit);
+        assert_eq!(err.kind(), crate::error::ErrorKind::LimitExceeded);
+        assert_eq!(err.message, "Memory limit of 1.0 MB exceeded");
+        assert_eq!(err.span.map(|span| (span.start.line, span.start.column)), Some((1, 11)));
+
+        // A string doubled in place
+        let err = run_with("let s = \"ab\";\nwhile (true) { s = s + s; }", limits).unwrap_err();
+        assert_eq!(err.code, ErrorCode::MemoryLimit);
+
+        // Printed text stays buffered until the run is over
+        let err = run_with("while (true) { print(\"0123456789\"); }", limits).unwrap_err();
+        assert_eq!(err.code, ErrorCode::MemoryLimit);
+
+        // Values that are freed again do not add up
+        assert_eq!(run_with("for i in 0..100 { let a = 0..1000; }\n1", limits).unwrap(), "1");
+    }
+}
@@ -1,33 +1,63 @@
+use super::error::{ErrorCode, LangError};
+use super::parser::MAX_NESTING;
+use num_bigint::BigInt;
+use std::fmt;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     // Literals
+    Integer(BigInt),
     Number(f64),
     String(String),
+    // String containing `${...}` placeholders
+    InterpolatedString(Vec<StringPart>),
     Boolean(bool),
     Identifier(String),
     
     // Keywords
     Let,
-    True,
-    False,
     If,
     Else,
     While,
     For,
+    In,
     Fn,
     Return,
     Break,
     Continue,
+    Null,
+    Struct,
+    Impl,
+    Enum,
+    Match,
+    Try,
+    Catch,
+    Finally,
+    Throw,
     
     // Operators
     Assign,        // =
+    PlusAssign,    // +=
+    MinusAssign,   // -=
+    MultiplyAssign,// *=
+    DivideAssign,  // /=
+    ModuloAssign,  // %=
     Plus,          // +
     Minus,         // -
     Multiply,      // *
     Divide,        // /
     Modulo,        // %
+    Power,         // **
     Not,           // !
     
+    // Bitwise
+    BitAnd,        // &
+    BitOr,         // |
+    BitXor,        // ^
+    BitNot,        // ~
+    ShiftLeft,     // <<
+    ShiftRight,    // >>
+    
     // Comparison
     Equal,             // ==
     NotEqual,          // !=
@@ -39,6 +69,8 @@ pub enum Token {
     // Logical
     And,           // &&
     Or,            // ||
+    NullCoalesce,  // ??
+    Question,      // ? (ternary)
     
     // Delimiters
     LeftParen,     // (
@@ -46,24 +78,63 @@ pub enum Token {
     LeftBrace,     // {
     RightBrace,    // }
     LeftBracket,   // [
+    SafeLeftBracket, // ?[
     RightBracket,  // ]
     Comma,         // ,
     Semicolon,     // ;
+    Colon,         // :
+    FatArrow,      // =>
+    Dot,           // .
+    DotDot,        // ..
+    DotDotEqual,   // ..=
     
     // Special
     Eof,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub struct TokenPosition {
     pub line: usize,
     pub column: usize,
 }
 
-#[derive(Debug, Clone)]
+impl fmt::Display for TokenPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+// Source range covered by a token or AST node; `end` points just past the last character
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub start: TokenPosition,
+    pub end: TokenPosition,
+}
+
+impl Span {
+    pub fn new(start: TokenPosition, end: TokenPosition) -> Self {
+        Span { start, end }
+    }
+
+    // Span running from the start of `self` to the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        Span { start: self.start, end: other.end }
+    }
+}
+
+// Piece of an interpolated string. Placeholders keep their tokens (with real
+// source positions) so the parser can handle them like any other expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StringPart {
+    Literal(String),
+    Placeholder(Vec<TokenWithPosition>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct TokenWithPosition {
     pub token: Token,
     pub position: TokenPosition,
+    pub end: TokenPosition,
 }
 
 pub struct Lexer {
@@ -71,6 +142,8 @@ pub struct Lexer {
     position: usize,
     line: usize,
     column: usize,
+    // Placeholders currently being read inside one another
+    depth: usize,
 }
 
 impl Lexer {
@@ -80,6 +153,7 @@ impl Lexer {
             position: 0,
             line: 1,
             column: 1,
+            depth: 0,
         }
     }
     
@@ -90,12 +164,21 @@ impl Lexer {
         }
     }
     
+    // Error covering the source from `start` up to the current position
+    fn error(&self, code: ErrorCode, message: impl Into<String>, start: TokenPosition) -> LangError {
+        LangError::new(code, message).at(Span::new(start, self.current_position()))
+    }
+    
     fn current_char(&self) -> Option<char> {
         self.input.get(self.position).copied()
     }
     
     fn peek_char(&self) -> Option<char> {
-        self.input.get(self.position + 1).copied()
+        self.char_at(1)
+    }
+    
+    fn char_at(&self, offset: usize) -> Option<char> {
+        self.input.get(self.position + offset).copied()
     }
     
     fn advance(&mut self) {
@@ -118,7 +201,8 @@ impl Lexer {
         }
     }
     
-    fn skip_comment(&mut self) -> Result<(), String> {
+    fn skip_comment(&mut self) -> Result<(), LangError> {
+        let start = self.current_position();
         if self.current_char() == Some('/') && self.peek_char() == Some('/') {
             // Single-line comment
             while let Some(ch) = self.current_char() {
@@ -140,41 +224,44 @@ impl Lexer {
                 }
                 self.advance();
             }
-            return Err(format!("Unterminated multi-line comment at line {}", self.line));
+            return Err(self.error(ErrorCode::UnterminatedComment, "Unterminated multi-line comment", start));
         }
         Ok(())
     }
     
-    fn read_string(&mut self) -> Result<String, String> {
+    // Reads "..." and """...""" strings. Both may span lines and contain escapes
+    // and `${...}` placeholders; triple-quoted ones may also hold bare quotes.
+    fn read_string(&mut self) -> Result<Token, LangError> {
         let mut value = String::new();
-        let start_line = self.line;
-        self.advance(); // skip opening quote
+        let mut parts = Vec::new();
+        let start = self.current_position();
+        let triple = self.peek_char() == Some('"') && self.char_at(2) == Some('"');
+        let quote_len = if triple { 3 } else { 1 };
+        for _ in 0..quote_len {
+            self.advance(); // skip opening quotes
+        }
         
         while let Some(ch) = self.current_char() {
             match ch {
-                '"' => {
-                    self.advance();
-                    return Ok(value);
+                '"' if !triple || (self.peek_char() == Some('"') && self.char_at(2) == Some('"')) => {
+                    for _ in 0..quote_len {
+                        self.advance();
+                    }
+                    if parts.is_empty() {
+                        return Ok(Token::String(value));
+                    }
+                    if !value.is_empty() {
+                        parts.push(StringPart::Literal(value));
+                    }
+                    return Ok(Token::InterpolatedString(parts));
                 }
-                '\\' => {
-                    self.advance();
-                    match self.current_char() {
-                        Some('n') => value.push('\n'),
-                        Some('t') => value.push('\t'),
-                        Some('r') => value.push('\r'),
-                        Some('\\') => value.push('\\'),
-                        Some('"') => value.push('"'),
-                        Some('0') => value.push('\0'),
-                        Some(c) => {
-                            return Err(format!("Invalid escape sequence '\\{}' at line {}, column {}", 
-                                c, self.line, self.column));
-                        }
-                        None => {
-                            return Err(format!("Unterminated string starting at line {}", start_line));
-                        }
+                '$' if self.peek_char() == Some('{') => {
+                    if !value.is_empty() {
+                        parts.push(StringPart::Literal(std::mem::take(&mut value)));
                     }
-                    self.advance();
+                    parts.push(StringPart::Placeholder(self.read_placeholder()?));
                 }
+                '\\' => value.push(self.read_escape(start)?),
                 c => {
                     value.push(c);
                     self.advance();
@@ -182,35 +269,251 @@ impl Lexer {
             }
         }
         
-        Err(format!("Unterminated string starting at line {}", start_line))
+        Err(self.error(ErrorCode::UnterminatedString, "Unterminated string", start))
     }
     
-    fn read_number(&mut self) -> Result<f64, String> {
-        let start_pos = self.position;
-        let mut has_dot = false;
+    // Decodes one backslash escape, leaving the lexer after it
+    fn read_escape(&mut self, string_start: TokenPosition) -> Result<char, LangError> {
+        let escape_start = self.current_position();
+        self.advance(); // skip '\'
         
-        while let Some(ch) = self.current_char() {
-            match ch {
-                '0'..='9' => self.advance(),
-                '.' if !has_dot => {
-                    has_dot = true;
+        let Some(c) = self.current_char() else {
+            return Err(self.error(ErrorCode::UnterminatedString, "Unterminated string", string_start));
+        };
+        self.advance();
+        
+        let decoded = match c {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '\\' => '\\',
+            '"' => '"',
+            '$' => '$',
+            '0' => '\0',
+            // `\x41`: exactly two hex digits, ASCII only
+            'x' => {
+                let digits = self.read_hex_digits(2);
+                match u32::from_str_radix(&digits, 16) {
+                    Ok(code) if digits.len() == 2 && code <= 0x7F => char::from_u32(code).unwrap_or_default(),
+                    _ => return Err(self.error(ErrorCode::InvalidEscape,
+                        "Invalid '\\x' escape: expected two hex digits up to 7F", escape_start)),
+                }
+            }
+            // `\u{1F600}`: one to six hex digits naming a Unicode scalar value
+            'u' => {
+                if self.current_char() != Some('{') {
+                    return Err(self.error(ErrorCode::InvalidEscape, "Invalid '\\u' escape: expected '{'", escape_start));
+                }
+                self.advance();
+                let digits = self.read_hex_digits(6);
+                if self.current_char() != Some('}') || digits.is_empty() {
+                    return Err(self.error(ErrorCode::InvalidEscape,
+                        "Invalid '\\u' escape: expected 1 to 6 hex digits and '}'", escape_start));
+                }
+                self.advance();
+                match u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32) {
+                    Some(ch) => ch,
+                    None => return Err(self.error(ErrorCode::InvalidEscape,
+                        format!("Invalid Unicode code point '\\u{{{}}}'", digits), escape_start)),
+                }
+            }
+            c => return Err(self.error(ErrorCode::InvalidEscape,
+                format!("Invalid escape sequence '\\{}'", c), escape_start)),
+        };
+        Ok(decoded)
+    }
+    
+    fn read_hex_digits(&mut self, max: usize) -> String {
+        let mut digits = String::new();
+        while digits.len() < max {
+            match self.current_char() {
+                Some(c) if c.is_ascii_hexdigit() => {
+                    digits.push(c);
                     self.advance();
                 }
                 _ => break,
             }
         }
+        digits
+    }
+    
+    // Reads r"..." or r#"..."#: no escapes or placeholders, and the closing quote
+    // must be followed by as many '#' as the opening one
+    fn read_raw_string(&mut self) -> Result<Token, LangError> {
+        let start = self.current_position();
+        self.advance(); // skip 'r'
+        let mut hashes = 0;
+        while self.current_char() == Some('#') {
+            hashes += 1;
+            self.advance();
+        }
+        self.advance(); // skip opening quote
         
-        let number_str: String = self.input[start_pos..self.position].iter().collect();
+        let mut value = String::new();
+        while let Some(ch) = self.current_char() {
+            if ch == '"' && (1..=hashes).all(|i| self.char_at(i) == Some('#')) {
+                for _ in 0..=hashes {
+                    self.advance();
+                }
+                return Ok(Token::String(value));
+            }
+            value.push(ch);
+            self.advance();
+        }
         
-        // Handle edge cases
-        if number_str == "." {
-            return Err(format!("Invalid number '.' at line {}, column {}", 
-                self.line, self.column - 1));
+        Err(self.error(ErrorCode::UnterminatedString, "Unterminated raw string", start))
+    }
+    
+    // True at `r"` or `r#...#"`
+    fn at_raw_string(&self) -> bool {
+        let mut offset = 1;
+        while self.char_at(offset) == Some('#') {
+            offset += 1;
         }
+        self.char_at(offset) == Some('"')
+    }
+    
+    // Lexes the tokens of a `${...}` placeholder up to its closing brace, which
+    // is replaced by Eof so the parser stops there
+    fn read_placeholder(&mut self) -> Result<Vec<TokenWithPosition>, LangError> {
+        let start = self.current_position();
+        if self.depth >= MAX_NESTING {
+            return Err(self.error(ErrorCode::StackOverflow,
+                format!("Program is nested too deeply (more than {} levels)", MAX_NESTING), start));
+        }
+        self.advance(); // skip '$'
+        self.advance(); // skip '{'
         
-        number_str.parse::<f64>()
-            .map_err(|_| format!("Invalid number '{}' at line {}, column {}", 
-                number_str, self.line, self.column - number_str.len()))
+        self.depth += 1;
+        let tokens = self.read_placeholder_tokens(start);
+        self.depth -= 1;
+        tokens
+    }
+
+    fn read_placeholder_tokens(&mut self, start: TokenPosition) -> Result<Vec<TokenWithPosition>, LangError> {
+        let mut tokens = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.next_token()?;
+            match token.token {
+                Token::LeftBrace => depth += 1,
+                Token::RightBrace if depth == 0 => {
+                    tokens.push(TokenWithPosition { token: Token::Eof, ..token });
+                    return Ok(tokens);
+                }
+                Token::RightBrace => depth -= 1,
+                Token::Eof => {
+                    return Err(self.error(ErrorCode::UnterminatedString, "Unterminated '${' in string", start));
+                }
+                _ => {}
+            }
+            tokens.push(token);
+        }
+    }
+    
+    // Decimal, hex (`0x`), binary (`0b`) and octal (`0o`) literals with optional
+    // `_` separators. Decimals may have a fraction and an exponent, which make them
+    // floats; everything else is an exact integer.
+    fn read_number(&mut self) -> Result<Token, LangError> {
+        let start = self.current_position();
+        
+        if self.current_char() == Some('0') {
+            let radix = match self.peek_char() {
+                Some('x' | 'X') => Some((16, "hexadecimal")),
+                Some('b' | 'B') => Some((2, "binary")),
+                Some('o' | 'O') => Some((8, "octal")),
+                _ => None,
+            };
+            if let Some((radix, name)) = radix {
+                self.advance(); // skip '0'
+                self.advance(); // skip radix letter
+                let digits = self.read_digits(radix)?;
+                self.check_number_end(name)?;
+                if digits.is_empty() {
+                    return Err(self.error(ErrorCode::InvalidNumber, format!("Missing digits in {} literal", name), start));
+                }
+                return BigInt::parse_bytes(digits.as_bytes(), radix)
+                    .map(Token::Integer)
+                    .ok_or_else(|| self.error(ErrorCode::InvalidNumber, format!("Invalid {} literal", name), start));
+            }
+        }
+        
+        let mut number_str = self.read_digits(10)?;
+        let mut is_float = false;
+        
+        // A dot only belongs to the number when a digit follows it
+        if self.current_char() == Some('.') && self.peek_char().is_some_and(|c| c.is_ascii_digit()) {
+            self.advance();
+            number_str.push('.');
+            number_str.push_str(&self.read_digits(10)?);
+            is_float = true;
+        }
+        
+        if matches!(self.current_char(), Some('e' | 'E')) {
+            let exponent_start = self.current_position();
+            self.advance();
+            number_str.push('e');
+            if let Some(sign @ ('+' | '-')) = self.current_char() {
+                number_str.push(sign);
+                self.advance();
+            }
+            let exponent = self.read_digits(10)?;
+            if exponent.is_empty() {
+                return Err(self.error(ErrorCode::InvalidNumber, "Missing digits in exponent", exponent_start));
+            }
+            number_str.push_str(&exponent);
+            is_float = true;
+        }
+        
+        self.check_number_end("number")?;
+        
+        if !is_float {
+            return number_str.parse::<BigInt>()
+                .map(Token::Integer)
+                .map_err(|_| self.error(ErrorCode::InvalidNumber, format!("Invalid number '{}'", number_str), start));
+        }
+        
+        match number_str.parse::<f64>() {
+            Ok(n) if n.is_finite() => Ok(Token::Number(n)),
+            Ok(_) => Err(self.error(ErrorCode::InvalidNumber, format!("Number '{}' is too large", number_str), start)),
+            Err(_) => Err(self.error(ErrorCode::InvalidNumber, format!("Invalid number '{}'", number_str), start)),
+        }
+    }
+    
+    // Reads digits valid in `radix`, dropping `_` separators that sit between two digits
+    fn read_digits(&mut self, radix: u32) -> Result<String, LangError> {
+        let mut digits = String::new();
+        
+        while let Some(ch) = self.current_char() {
+            if ch.is_digit(radix) {
+                digits.push(ch);
+                self.advance();
+            } else if ch == '_' {
+                let position = self.current_position();
+                let followed_by_digit = self.peek_char().is_some_and(|c| c.is_digit(radix));
+                self.advance();
+                if digits.is_empty() || !followed_by_digit {
+                    return Err(self.error(ErrorCode::InvalidNumber,
+                        "Digit separator '_' must be between two digits", position));
+                }
+            } else {
+                break;
+            }
+        }
+        
+        Ok(digits)
+    }
+    
+    // Rejects literals running straight into letters or out-of-range digits, like `0b102` or `12px`
+    fn check_number_end(&mut self, name: &str) -> Result<(), LangError> {
+        match self.current_char() {
+            Some(c) if c.is_alphanumeric() || c == '_' => {
+                let position = self.current_position();
+                self.advance();
+                Err(self.error(ErrorCode::InvalidNumber, format!("Invalid digit '{}' in {} literal", c, name), position))
+            }
+            _ => Ok(()),
+        }
     }
     
     fn read_identifier(&mut self) -> String {
@@ -227,6 +530,17 @@ impl Lexer {
         self.input[start_pos..self.position].iter().collect()
     }
     
+    // Consumes an arithmetic operator, folding a following '=' into its compound-assignment form
+    fn read_operator(&mut self, plain: Token, with_assign: Token) -> Token {
+        self.advance();
+        if self.current_char() == Some('=') {
+            self.advance();
+            with_assign
+        } else {
+            plain
+        }
+    }
+    
     fn identifier_to_token(&self, ident: &str) -> Token {
         match ident {
             "let" => Token::Let,
@@ -236,15 +550,25 @@ impl Lexer {
             "else" => Token::Else,
             "while" => Token::While,
             "for" => Token::For,
+            "in" => Token::In,
             "fn" => Token::Fn,
             "return" => Token::Return,
             "break" => Token::Break,
             "continue" => Token::Continue,
+            "null" => Token::Null,
+            "struct" => Token::Struct,
+            "impl" => Token::Impl,
+            "enum" => Token::Enum,
+            "match" => Token::Match,
+            "try" => Token::Try,
+            "catch" => Token::Catch,
+            "finally" => Token::Finally,
+            "throw" => Token::Throw,
             _ => Token::Identifier(ident.to_string()),
         }
     }
     
-    pub fn next_token(&mut self) -> Result<TokenWithPosition, String> {
+    pub fn next_token(&mut self) -> Result<TokenWithPosition, LangError> {
         self.skip_whitespace();
         
         // Handle comments
@@ -261,18 +585,19 @@ impl Lexer {
             
             Some(ch) => match ch {
                 // String literals
-                '"' => Token::String(self.read_string()?),
+                '"' => self.read_string()?,
                 
                 // Numbers
-                '0'..='9' => Token::Number(self.read_number()?),
+                '0'..='9' => self.read_number()?,
+                
+                // Raw strings
+                'r' if self.at_raw_string() => self.read_raw_string()?,
                 
-                // Identifiers and keywords
-                'a'..='z' | 'A'..='Z' | '_' => {
+                // Identifiers and keywords: any Unicode letter or '_' starts one,
+                // and read_identifier continues on letters, digits and '_'
+                c if c.is_alphabetic() || c == '_' => {
                     let ident = self.read_identifier();
-                    return Ok(TokenWithPosition {
-                        token: self.identifier_to_token(&ident),
-                        position,
-                    });
+                    self.identifier_to_token(&ident)
                 }
                 
                 // Two-character operators
@@ -281,6 +606,9 @@ impl Lexer {
                     if self.current_char() == Some('=') {
                         self.advance();
                         Token::Equal
+                    } else if self.current_char() == Some('>') {
+                        self.advance();
+                        Token::FatArrow
                     } else {
                         Token::Assign
                     }
@@ -298,21 +626,28 @@ impl Lexer {
                 
                 '<' => {
                     self.advance();
-                    if self.current_char() == Some('=') {
-                        self.advance();
-                        Token::LessThanOrEqual
-                    } else {
-                        Token::LessThan
+                    match self.current_char() {
+                        Some('=') => { self.advance(); Token::LessThanOrEqual }
+                        Some('<') => { self.advance(); Token::ShiftLeft }
+                        _ => Token::LessThan,
                     }
                 }
                 
                 '>' => {
                     self.advance();
-                    if self.current_char() == Some('=') {
-                        self.advance();
-                        Token::GreaterThanOrEqual
-                    } else {
-                        Token::GreaterThan
+                    match self.current_char() {
+                        Some('=') => { self.advance(); Token::GreaterThanOrEqual }
+                        Some('>') => { self.advance(); Token::ShiftRight }
+                        _ => Token::GreaterThan,
+                    }
+                }
+                
+                '?' => {
+                    self.advance();
+                    match self.current_char() {
+                        Some('?') => { self.advance(); Token::NullCoalesce }
+                        Some('[') => { self.advance(); Token::SafeLeftBracket }
+                        _ => Token::Question,
                     }
                 }
                 
@@ -322,8 +657,7 @@ impl Lexer {
                         self.advance();
                         Token::And
                     } else {
-                        return Err(format!("Unexpected character '&' at line {}, column {}. Did you mean '&&'?", 
-                            position.line, position.column));
+                        Token::BitAnd
                     }
                 }
                 
@@ -333,17 +667,34 @@ impl Lexer {
                         self.advance();
                         Token::Or
                     } else {
-                        return Err(format!("Unexpected character '|' at line {}, column {}. Did you mean '||'?", 
-                            position.line, position.column));
+                        Token::BitOr
                     }
                 }
                 
                 // Single-character tokens
-                '+' => { self.advance(); Token::Plus }
-                '-' => { self.advance(); Token::Minus }
-                '*' => { self.advance(); Token::Multiply }
-                '/' => { self.advance(); Token::Divide }
-                '%' => { self.advance(); Token::Modulo }
+                '+' => self.read_operator(Token::Plus, Token::PlusAssign),
+                '-' => self.read_operator(Token::Minus, Token::MinusAssign),
+                '*' if self.peek_char() == Some('*') => {
+                    self.advance();
+                    self.advance();
+                    Token::Power
+                }
+                '*' => self.read_operator(Token::Multiply, Token::MultiplyAssign),
+                '/' => self.read_operator(Token::Divide, Token::DivideAssign),
+                '%' => self.read_operator(Token::Modulo, Token::ModuloAssign),
+                '.' if self.peek_char() == Some('.') => {
+                    self.advance();
+                    self.advance();
+                    if self.current_char() == Some('=') {
+                        self.advance();
+                        Token::DotDotEqual
+                    } else {
+                        Token::DotDot
+                    }
+                }
+                '.' => { self.advance(); Token::Dot }
+                '^' => { self.advance(); Token::BitXor }
+                '~' => { self.advance(); Token::BitNot }
                 '(' => { self.advance(); Token::LeftParen }
                 ')' => { self.advance(); Token::RightParen }
                 '{' => { self.advance(); Token::LeftBrace }
@@ -352,19 +703,21 @@ impl Lexer {
                 ']' => { self.advance(); Token::RightBracket }
                 ',' => { self.advance(); Token::Comma }
                 ';' => { self.advance(); Token::Semicolon }
+                ':' => { self.advance(); Token::Colon }
                 
                 // Unexpected character
                 c => {
-                    return Err(format!("Unexpected character '{}' at line {}, column {}", 
-                        c, position.line, position.column));
+                    self.advance();
+                    return Err(self.error(ErrorCode::UnexpectedCharacter,
+                        format!("Unexpected character '{}'", c), position));
                 }
             }
         };
         
-        Ok(TokenWithPosition { token, position })
+        Ok(TokenWithPosition { token, position, end: self.current_position() })
     }
     
-    pub fn tokenize(mut self) -> Result<Vec<TokenWithPosition>, String> {
+    pub fn tokenize(mut self) -> Result<Vec<TokenWithPosition>, LangError> {
         let mut tokens = Vec::new();
         
         loop {
@@ -381,9 +734,84 @@ impl Lexer {
     }
 }
 
-// Convenience function for backward compatibility
-pub fn tokenize(input: &str) -> Result<Vec<Token>, String> {
-    let lexer = Lexer::new(input);
-    let tokens_with_pos = lexer.tokenize()?;
-    Ok(tokens_with_pos.into_iter().map(|twp| twp.token).collect())
-}
\ No newline at end of file
+// Convenience function: tokens keep their positions so the parser can report them
+pub fn tokenize(input: &str) -> Result<Vec<TokenWithPosition>, LangError> {
+    Lexer::new(input).tokenize()
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        tokenize(source).unwrap().into_iter().map(|t| t.token).collect()
+    }
+
+    fn error_at(source: &str) -> (String, usize) {
+        let err = tokenize(source).unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidNumber);
+        (err.message, err.span.unwrap().start.column)
+    }
+
+    #[test]
+    fn numeric_literal_forms() {
+        let int = |n: i64| Token::Integer(BigInt::from(n));
+        assert_eq!(
+            tokens("0xFF 0b1010 0o17 1_000_000 1e9 1.5e-3 2.5E+2"),
+            vec![int(255), int(10), int(15), int(1_000_000), Token::Number(1e9),
+                 Token::Number(1.5e-3), Token::Number(250.0), Token::Eof]
+        );
+    }
+
+    #[test]
+    fn malformed_numbers_point_at_the_problem() {
+        assert_eq!(error_at("let x = 0b102;"), ("Invalid digit '2' in binary literal".to_string(), 13));
+        assert_eq!(error_at("let x = 0x;"), ("Missing digits in hexadecimal literal".to_string(), 9));
+        assert_eq!(error_at("let x = 1__0;"), ("Digit separator '_' must be between two digits".to_string(), 10));
+        assert_eq!(error_at("let x = 1e+;"), ("Missing digits in exponent".to_string(), 10));
+        assert_eq!(error_at("let x = 12px;"), ("Invalid digit 'p' in number literal".to_string(), 11));
+    }
+
+    #[test]
+    fn escapes_raw_and_multi_line_strings() {
+        let string = |s: &str| Token::String(s.to_string());
+        assert_eq!(
+            tokens(r##""\u{1F600}\x41\t\$" r"C:\new" r#"say "hi""# """a "quoted"
+line""""##),
+            vec![string("😀A\t$"), string(r"C:\new"), string(r#"say "hi""#), string("a \"quoted\"\nline"), Token::Eof]
+        );
+        // Raw strings never interpolate
+        assert_eq!(tokens(r#"r"${x}""#), vec![string("${x}"), Token::Eof]);
+    }
+
+    #[test]
+    fn identifiers_may_start_with_any_letter() {
+        assert_eq!(
+            tokens("été x_é"),
+            vec![Token::Identifier("été".to_string()), Token::Identifier("x_é".to_string()), Token::Eof]
+        );
+    }
+
+    #[test]
+    fn bad_escapes_point_at_the_backslash() {
+        for (source, message) in [
+            (r#"let s = "a\q";"#, "Invalid escape sequence '\\q'"),
+            (r#"let s = "a\x80";"#, "Invalid '\\x' escape: expected two hex digits up to 7F"),
+            (r#"let s = "a\u{D800}";"#, "Invalid Unicode code point '\\u{D800}'"),
+        ] {
+            let err = tokenize(source).unwrap_err();
+            assert_eq!((err.code, err.message.as_str()), (ErrorCode::InvalidEscape, message));
+            assert_eq!(err.span.unwrap().start.column, 11);
+        }
+        assert_eq!(tokenize(r##"r#"open"##).unwrap_err().code, ErrorCode::UnterminatedString);
+    }
+
+    #[test]
+    fn placeholders_nest_no_deeper_than_the_parser_allows() {
+        let mut source = "1".to_string();
+        for _ in 0..=MAX_NESTING {
+            source = format!("\"${{{}}}\"", source);
+        }
+        let err = tokenize(&source).unwrap_err();
+        assert_eq!(err.code, ErrorCode::StackOverflow);
+    }
+}
@@ -2,13 +2,10 @@
 use actix_web::{web, App, HttpResponse, HttpServer, Responder, middleware::Logger};
 use serde::{Deserialize, Serialize};
 use actix_cors::Cors;
-use std::collections::HashMap;
 use sqlx::PgPool;
 use dotenv::dotenv;
 use std::env;
 use std::process::{Command, Stdio};
-use std::io::Write;
-use tempfile::NamedTempFile;
 use tokio::time::{timeout, Duration};
 use std::fs;
 
@@ -16,6 +13,11 @@ mod lexer;
 mod parser;
 mod evaluator;
 mod object;
+mod error;
+mod environment;
+mod checker;
+mod compiler;
+mod vm;
 
 #[derive(Debug, sqlx::Type, Clone)]
 #[sqlx(type_name = "ExecutionStatus", rename_all = "SCREAMING_SNAKE_CASE")]
@@ -27,10 +29,52 @@ enum ExecutionStatus {
     MemoryLimit,
 }
 
+// Mirrors the `ErrorType` enum used by the `error_logs` table, so the
+// variant names keep the schema's spelling
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, sqlx::Type, Clone)]
+#[sqlx(type_name = "ErrorType", rename_all = "SCREAMING_SNAKE_CASE")]
+enum ErrorType {
+    LexerError,
+    ParserError,
+    RuntimeError,
+    SystemError,
+    ApiError,
+}
+
+impl From<error::ErrorKind> for ErrorType {
+    fn from(kind: error::ErrorKind) -> Self {
+        match kind {
+            error::ErrorKind::Lexer => ErrorType::LexerError,
+            // The schema has no separate type for static checks, which run right after parsing
+            error::ErrorKind::Parser | error::ErrorKind::Checker => ErrorType::ParserError,
+            error::ErrorKind::Runtime | error::ErrorKind::LimitExceeded => ErrorType::RuntimeError,
+        }
+    }
+}
+
 #[derive(Deserialize)]
 struct CompileRequest {
     code: String,
     language: String, // "rust", "python", "c", or "custom"
+    // Evaluation steps the custom interpreter may take, up to MAX_STEP_BUDGET
+    #[serde(default)]
+    max_steps: Option<u64>,
+    // Approximate bytes the custom interpreter may hold, up to MAX_MEMORY_BUDGET
+    #[serde(default)]
+    max_memory: Option<usize>,
+    // How custom-language programs run
+    #[serde(default)]
+    backend: Backend,
+}
+
+// The bytecode VM is the default; the tree-walking evaluator is kept for comparison
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum Backend {
+    #[default]
+    Vm,
+    TreeWalker,
 }
 
 #[derive(Serialize)]
@@ -38,11 +82,88 @@ struct CompileResponse {
     result: Option<String>,
     error: Option<String>,
     execution_time_ms: Option<u64>,
+    // Peak memory of a custom-language run
+    memory_usage_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    diagnostics: Vec<Diagnostic>,
+}
+
+// What the custom interpreter reports besides its result, whether or not the run succeeds
+#[derive(Default)]
+struct RunReport {
+    // Compile-time warnings
+    warnings: Vec<error::LangError>,
+    // Set once evaluation has started
+    peak_memory: Option<u64>,
+}
+
+// Structured form of a custom-language error so the editor can classify and underline it
+#[derive(Serialize)]
+struct Diagnostic {
+    severity: error::Severity,
+    kind: error::ErrorKind,
+    code: error::ErrorCode,
+    message: String,
+    line: Option<usize>,
+    column: Option<usize>,
+    end_line: Option<usize>,
+    end_column: Option<usize>,
+}
+
+impl From<&error::LangError> for Diagnostic {
+    fn from(err: &error::LangError) -> Self {
+        Diagnostic {
+            severity: err.code.severity(),
+            kind: err.kind(),
+            code: err.code,
+            message: err.message.clone(),
+            line: err.span.map(|s| s.start.line),
+            column: err.span.map(|s| s.start.column),
+            end_line: err.span.map(|s| s.end.line),
+            end_column: err.span.map(|s| s.end.column),
+        }
+    }
+}
+
+// Failure from any backend; only the custom interpreter produces structured errors
+enum ExecutionError {
+    Language(Vec<error::LangError>),
+    Other(String),
+}
+
+impl std::fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecutionError::Language(errors) => {
+                let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", messages.join("\n"))
+            }
+            ExecutionError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl ExecutionError {
+    // Status recorded for the failed run; running out of steps counts as a timeout
+    fn status(&self) -> ExecutionStatus {
+        let ExecutionError::Language(errors) = self else {
+            return ExecutionStatus::Error;
+        };
+        if errors.iter().any(|e| e.code == error::ErrorCode::StepLimit) {
+            ExecutionStatus::Timeout
+        } else if errors.iter().any(|e| e.code == error::ErrorCode::MemoryLimit) {
+            ExecutionStatus::MemoryLimit
+        } else {
+            ExecutionStatus::Error
+        }
+    }
 }
 
 // Enhanced security for code execution
 const EXECUTION_TIMEOUT: Duration = Duration::from_secs(10);
 const MAX_OUTPUT_SIZE: usize = 10_000; // 10KB max output
+const MAX_STEP_BUDGET: u64 = 50_000_000; // about 4s of interpreter time
+const MAX_MEMORY_BUDGET: usize = 256 * 1024 * 1024;
 
 async fn compile_handler(req: web::Json<CompileRequest>, pool: web::Data<PgPool>) -> impl Responder {
     let start_time = std::time::Instant::now();
@@ -55,28 +176,40 @@ async fn compile_handler(req: web::Json<CompileRequest>, pool: web::Data<PgPool>
             result: None,
             error: Some("Code too large (max 50KB)".to_string()),
             execution_time_ms: Some(start_time.elapsed().as_millis() as u64),
+            memory_usage_bytes: None,
+            diagnostics: Vec::new(),
         });
     }
 
+    let mut report = RunReport::default();
     let result = match language.as_str() {
-        "custom" => execute_custom_language(code).await,
-        "rust" => execute_rust_code(code).await,
-        "python" => execute_python_code(code).await,
-        "c" => execute_c_code(code).await,
-        _ => Err("Unsupported language. Use: custom, rust, python, or c".to_string()),
+        "custom" => {
+            let limits = evaluator::Limits {
+                max_steps: req.max_steps.map_or(evaluator::Limits::default().max_steps, |steps| steps.min(MAX_STEP_BUDGET)),
+                max_memory: req.max_memory.map_or(evaluator::Limits::default().max_memory, |bytes| bytes.min(MAX_MEMORY_BUDGET)),
+                ..evaluator::Limits::default()
+            };
+            execute_custom_language(code, req.backend, limits, &mut report).await
+        }
+        "rust" => execute_rust_code(code).await.map_err(ExecutionError::Other),
+        "python" => execute_python_code(code).await.map_err(ExecutionError::Other),
+        "c" => execute_c_code(code).await.map_err(ExecutionError::Other),
+        _ => Err(ExecutionError::Other("Unsupported language. Use: custom, rust, python, or c".to_string())),
     };
 
     let execution_time = start_time.elapsed().as_millis() as u64;
+    let memory_usage = report.peak_memory.and_then(|bytes| i32::try_from(bytes).ok());
 
     let response = match result {
         Ok(output) => {
             // Log success to database - using Prisma column names
             let _ = sqlx::query!(
-                r#"INSERT INTO executions (code, result, status, execution_time_ms, language) VALUES ($1, $2, $3, $4, $5)"#,
+                r#"INSERT INTO executions (code, result, status, execution_time_ms, memory_usage, language) VALUES ($1, $2, $3, $4, $5, $6)"#,
                 code,
                 Some(output.clone()),
                 ExecutionStatus::Success as _,
                 execution_time as i32,
+                memory_usage,
                 language
             )
             .execute(pool.get_ref())
@@ -86,25 +219,51 @@ async fn compile_handler(req: web::Json<CompileRequest>, pool: web::Data<PgPool>
                 result: Some(output),
                 error: None,
                 execution_time_ms: Some(execution_time),
+                memory_usage_bytes: report.peak_memory,
+                diagnostics: report.warnings.iter().map(Diagnostic::from).collect(),
             }
         }
-        Err(error) => {
+        Err(failure) => {
+            let error = failure.to_string();
+
             // Log error to database - using Prisma column names
             let _ = sqlx::query!(
-                r#"INSERT INTO executions (code, error, status, execution_time_ms, language) VALUES ($1, $2, $3, $4, $5)"#,
+                r#"INSERT INTO executions (code, error, status, execution_time_ms, memory_usage, language) VALUES ($1, $2, $3, $4, $5, $6)"#,
                 code,
                 Some(error.clone()),
-                ExecutionStatus::Error as _,
+                failure.status() as _,
                 execution_time as i32,
+                memory_usage,
                 language
             )
             .execute(pool.get_ref())
             .await;
 
+            let mut diagnostics = Vec::new();
+            if let ExecutionError::Language(errors) = &failure {
+                // Classify custom-language failures in error_logs by pipeline stage;
+                // all diagnostics of one run come from the same stage
+                if let Some(first) = errors.first() {
+                    let _ = sqlx::query!(
+                        r#"INSERT INTO error_logs ("errorType", message, code) VALUES ($1, $2, $3)"#,
+                        ErrorType::from(first.kind()) as _,
+                        error.clone(),
+                        code
+                    )
+                    .execute(pool.get_ref())
+                    .await;
+                }
+
+                diagnostics.extend(errors.iter().map(Diagnostic::from));
+            }
+            diagnostics.extend(report.warnings.iter().map(Diagnostic::from));
+
             CompileResponse {
                 result: None,
                 error: Some(error),
                 execution_time_ms: Some(execution_time),
+                memory_usage_bytes: report.peak_memory,
+                diagnostics,
             }
         }
     };
@@ -113,14 +272,62 @@ async fn compile_handler(req: web::Json<CompileRequest>, pool: web::Data<PgPool>
 }
 
 // Execute custom language (your interpreter)
-async fn execute_custom_language(code: &str) -> Result<String, String> {
-    let tokens = lexer::tokenize(code)?;
+async fn execute_custom_language(
+    code: &str,
+    backend: Backend,
+    limits: evaluator::Limits,
+    report: &mut RunReport,
+) -> Result<String, ExecutionError> {
+    // The interpreter recurses on the native stack, so it runs on its own thread with a
+    // stack sized for the depth limits, which the evaluator also checks against
+    let stack_size = limits.stack_needed();
+    let limits = evaluator::Limits { stack_size: Some(stack_size), ..limits };
+    let code = code.to_string();
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    std::thread::Builder::new()
+        .name("interpreter".to_string())
+        .stack_size(stack_size)
+        .spawn(move || {
+            let mut report = RunReport::default();
+            let result = run_custom_language(&code, backend, limits, &mut report);
+            let _ = sender.send((result, report));
+        })
+        .map_err(|e| ExecutionError::Other(format!("Failed to start interpreter: {}", e)))?;
+
+    // The sender is only dropped without a value if the interpreter panicked
+    let (result, run_report) = receiver.await
+        .map_err(|_| ExecutionError::Other("Interpreter crashed".to_string()))?;
+    *report = run_report;
+    result.map_err(ExecutionError::Language)
+}
+
+fn run_custom_language(
+    code: &str,
+    backend: Backend,
+    limits: evaluator::Limits,
+    report: &mut RunReport,
+) -> Result<String, Vec<error::LangError>> {
+    let tokens = lexer::tokenize(code).map_err(|e| vec![e])?;
     let mut parser = parser::Parser::new(tokens);
-    let ast = parser.parse_program()?;
-    let mut env = HashMap::new();
+    let (ast, syntax_errors) = parser.parse_program();
+    if !syntax_errors.is_empty() {
+        return Err(syntax_errors);
+    }
+    report.warnings.extend(checker::check_program(&ast));
     
     // Execute the code
-    let result = evaluator::evaluate(&ast, &mut env)?;
+    let result = match backend {
+        Backend::Vm => vm::run(&compiler::compile(&ast, limits.max_nesting), limits),
+        Backend::TreeWalker => {
+            let env = environment::Environment::new();
+            let result = evaluator::evaluate(&ast, &env, limits);
+            env.clear();
+            environment::release_escaped();
+            result
+        }
+    };
+    report.peak_memory = Some(evaluator::peak_memory() as u64);
+    let result = result.map_err(|e| vec![e])?;
     
     // Get any output from print statements
     let output = evaluator::get_output();
@@ -356,4 +563,282 @@ async fn main() -> std::io::Result<()> {
     .bind(("0.0.0.0", port))?
     .run()
     .await
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Output or error message of a custom-language run, with its peak memory
+    async fn run_with(code: &str, backend: Backend, limits: evaluator::Limits) -> (Result<String, String>, u64) {
+        let mut report = RunReport::default();
+        let result = execute_custom_language(code, backend, limits, &mut report).await;
+        (result.map_err(|e| e.to_string()), report.peak_memory.unwrap_or_default())
+    }
+
+    async fn run(code: &str, backend: Backend) -> (Result<String, String>, u64) {
+        run_with(code, backend, evaluator::Limits::default()).await
+    }
+
+    // Runs `code` on the VM and the tree-walker and returns the result they agree on
+    async fn cross_check(code: &str) -> Result<String, String> {
+        cross_check_with(code, evaluator::Limits::default()).await
+    }
+
+    async fn cross_check_with(code: &str, limits: evaluator::Limits) -> Result<String, String> {
+        let (vm, _) = run_with(code, Backend::Vm, limits).await;
+        let (tree_walker, _) = run_with(code, Backend::TreeWalker, limits).await;
+        assert_eq!(vm, tree_walker, "backends disagree on:{}", code);
+        vm
+    }
+
+    #[tokio::test]
+    async fn closures_capture_loop_variables() {
+        let result = cross_check("
+            let fs = [];
+            for i in 0..3 { fs = push(fs, fn() { i * 10 }); }
+            let gs = [];
+            for (let j = 0; j < 3; j += 1) { gs = push(gs, fn() { j }); }
+            fn counter() { let count = 0; return fn() { count += 1; return count; }; }
+            let c = counter();
+            c();
+            println(map(fs, fn(f) { f() }), map(gs, fn(g) { g() }), c());
+        ").await;
+        assert_eq!(result, Ok("[0, 10, 20] [3, 3, 3] 2".to_string()));
+    }
+
+    #[tokio::test]
+    async fn finally_runs_on_break_continue_and_return() {
+        let result = cross_check("
+            fn early() { try { return \"try\"; } finally { println(\"finally\"); } }
+            fn loop_exits() {
+                let seen = [];
+                for i in 0..5 {
+                    try {
+                        if i == 1 { continue; }
+                        if i == 3 { break; }
+                        seen = push(seen, i);
+                    } finally { seen = push(seen, -i); }
+                }
+                return seen;
+            }
+            fn overridden() { try { throw \"lost\"; } finally { return \"finally wins\"; } }
+            println(early());
+            println(loop_exits());
+            println(overridden());
+        ").await;
+        assert_eq!(result, Ok("finally\ntry\n[0, 0, -1, 2, -2, -3]\nfinally wins".to_string()));
+    }
+
+    #[tokio::test]
+    async fn methods_write_self_back_through_paths() {
+        let result = cross_check("
+            struct Point { x, y }
+            impl Point { fn shift(self, dx) { self.x += dx; } }
+            struct Line { from, to }
+            let p = Point(1, 2);
+            p.shift(10);
+            let points = [Point(0, 0), Point(5, 5)];
+            points[1].shift(1);
+            let line = Line(Point(0, 0), Point(1, 1));
+            line.to.shift(2);
+            println(p.x, points[1].x, line.to.x);
+        ").await;
+        assert_eq!(result, Ok("11 6 3".to_string()));
+    }
+
+    #[tokio::test]
+    async fn match_arms_bind_variant_and_array_parts() {
+        let result = cross_check("
+            enum Shape { Circle(r), Rect(w, h), Empty }
+            fn area(s) {
+                match s {
+                    Shape.Circle(r) => 3 * r * r,
+                    Shape.Rect(w, h) if w == h => w * w,
+                    Shape.Rect(w, h) => w * h,
+                    Shape.Empty => 0,
+                }
+            }
+            fn describe(xs) {
+                match xs { [] => \"empty\", [only] => \"one ${only}\", [first, ..rest] => \"${first} of ${len(rest) + 1}\" }
+            }
+            println(map([Shape.Circle(2), Shape.Rect(3, 3), Shape.Rect(2, 5), Shape.Empty], area));
+            println(describe([]), describe([7]), describe([4, 5, 6]));
+        ").await;
+        assert_eq!(result, Ok("[12, 9, 10, 0]\nempty one 7 4 of 3".to_string()));
+    }
+
+    #[tokio::test]
+    async fn maps_and_compound_indexed_assignment() {
+        let result = cross_check("
+            let m = {\"a\": 1, \"b\": [1, 2]};
+            m[\"c\"] = 3;
+            m[\"a\"] += 10;
+            m[\"b\"][1] *= 5;
+            let grid = [[1, 2], [3, 4]];
+            grid[1][0] -= 1;
+            grid[-1][-1] %= 3;
+            println(m, keys(delete(m, \"c\")), has_key(m, \"c\"));
+            println(grid, grid[-2], m[\"b\"][-1]);
+        ").await;
+        assert_eq!(result, Ok("{\"a\": 11, \"b\": [1, 10], \"c\": 3} [\"a\", \"b\"] true\n[[1, 2], [2, 1]] [1, 2] 10".to_string()));
+        let result = cross_check("let m = {\"a\": 1};\nm[\"b\"] += 1;").await;
+        assert_eq!(result, Err("Type mismatch: cannot apply Plus to null and integer at line 2, column 1".to_string()));
+        let result = cross_check("let xs = [1, 2, 3];\nprintln(xs[-4]);").await;
+        assert_eq!(result, Err("Array index -4 out of bounds (length 3) at line 2, column 9".to_string()));
+    }
+
+    #[tokio::test]
+    async fn slices_strings_and_interpolation() {
+        let result = cross_check("
+            let xs = [0, 1, 2, 3, 4, 5];
+            let s = \"hello\";
+            println(xs[1:4], xs[::2], xs[::-1], xs[-2:], s[1:], s[::-1], s[-1]);
+            let name = \"world\";
+            let p = {\"x\": 1};
+            println(\"hi ${name}, ${len(xs) * 2} ${p[\"x\"] + 1} ${xs[1:3]}\");
+        ").await;
+        assert_eq!(result, Ok("[1, 2, 3] [0, 2, 4] [5, 4, 3, 2, 1, 0] [4, 5] ello olleh o\nhi world, 12 2 [1, 2]".to_string()));
+    }
+
+    #[tokio::test]
+    async fn null_safe_access_and_coalescing() {
+        let result = cross_check("
+            let missing = null;
+            let m = {\"a\": [1, 2]};
+            println(missing?[0], missing?[0] ?? \"none\", m?[\"a\"]?[1], null ?? false ?? 3, 0 ?? 1);
+        ").await;
+        assert_eq!(result, Ok("null none 2 false 0".to_string()));
+    }
+
+    #[tokio::test]
+    async fn for_in_over_strings_and_maps() {
+        let result = cross_check("
+            let out = [];
+            for c in \"abc\" { out = push(out, upper(c)); }
+            let m = {\"x\": 1, \"y\": 2};
+            let total = 0;
+            for k in m { out = push(out, k); total += m[k]; }
+            for k, v in m { out = push(out, \"${k}=${v}\"); }
+            for i, c in \"hi\" { out = push(out, \"${i}${c}\"); }
+            println(out, total);
+        ").await;
+        assert_eq!(result, Ok("[\"A\", \"B\", \"C\", \"x\", \"y\", \"x=1\", \"y=2\", \"0h\", \"1i\"] 3".to_string()));
+    }
+
+    #[tokio::test]
+    async fn uncaught_errors_point_at_the_same_place() {
+        for code in [
+            "let x = 1;\nlet y = x + \"a\";",
+            "fn f() {\n  throw \"boom\";\n}\nf();",
+            "let xs = [1];\nxs[0][1] = 2;",
+            "let p = {\"a\": 1};\np.a;",
+            "undefined_name + 1;",
+            "fn g(a) { a }\ng(1, 2);",
+            "let n = 10 / 0;",
+        ] {
+            let result = cross_check(code).await;
+            assert!(result.is_err(), "expected an error from:{}", code);
+        }
+        let result = cross_check("fn f() {\n  throw \"boom\";\n}\nf();").await;
+        assert_eq!(result, Err("Uncaught Error: boom at line 2, column 3".to_string()));
+    }
+
+    #[tokio::test]
+    async fn types_defined_in_functions_keep_their_methods() {
+        let result = cross_check("
+            fn make(k) {
+                struct S { v }
+                impl S { fn get(self) { self.v + k } }
+                return S(1);
+            }
+            let s = make(5);
+            fn kind() {
+                enum E { A, B }
+                impl E { fn name(self) { match self { E.A => \"a\", E.B => \"b\" } } }
+                return E;
+            }
+            let e = kind();
+            println(s.get(), make(10).get(), e.B.name());
+        ").await;
+        assert_eq!(result, Ok("6 11 b".to_string()));
+    }
+
+    #[tokio::test]
+    async fn depth_limits_fail_the_same_way() {
+        let chain = format!("let x = 1{};", "+1".repeat(20_000));
+        let result = cross_check(&chain).await;
+        assert!(result.is_err_and(|e| e.starts_with("Stack overflow: maximum nesting depth of 5000 exceeded")));
+        let result = cross_check("fn r(n) { r(n + 1) } r(0)").await;
+        assert!(result.is_err_and(|e| e.starts_with("Stack overflow: maximum call depth of 1000 exceeded")));
+
+        // Right-associative operators and access chains nest too. The parser stops
+        // at a depth of 256, so a lower limit is what these run into.
+        let limits = evaluator::Limits { max_nesting: 100, ..evaluator::Limits::default() };
+        for chain in [
+            format!("let x = 0;\nx = {}1;", "x = ".repeat(200)),
+            format!("let x = 1{};", " ** 1".repeat(200)),
+            format!("let x = {}0{};", "true ? ".repeat(200), " : 1".repeat(200)),
+            format!("let a = [1];\nlet x = a{};", "[0]".repeat(200)),
+            format!("let a = [1];\na{}.f();", "[0]".repeat(200)),
+        ] {
+            let result = cross_check_with(&chain, limits).await;
+            assert!(result.is_err_and(|e| e.starts_with("Stack overflow: maximum nesting depth of 100 exceeded")),
+                "no depth error from:{}", chain);
+        }
+        // Just under the limit both backends get through
+        let result = cross_check_with(&format!("let a = [[[1]]];\na[0][0][0]{}", " + 0".repeat(90)), limits).await;
+        assert_eq!(result, Ok("1".to_string()));
+    }
+
+    // Steps are counted per node on one backend and per instruction on the other, so
+    // only the kind of error is compared
+    #[tokio::test]
+    async fn step_and_memory_limits_stop_both_backends() {
+        let limits = evaluator::Limits { max_steps: 10_000, max_memory: 1024 * 1024, ..evaluator::Limits::default() };
+        for backend in [Backend::Vm, Backend::TreeWalker] {
+            let (result, _) = run_with("while true {}", backend, limits).await;
+            assert!(result.is_err_and(|e| e.starts_with("Execution exceeded the budget of 10000 steps")));
+            let (result, _) = run_with("let s = \"x\"; while true { s = s + s; }", backend, limits).await;
+            assert!(result.is_err_and(|e| e.starts_with("Memory limit of 1.0 MB exceeded")));
+        }
+    }
+
+    #[tokio::test]
+    async fn functions_defined_in_a_loop_keep_memory_bounded() {
+        let code = "
+            fn outer(i) { let big = 0..1000; fn inner() { return i; } return inner(); }
+            let n = 0;
+            while n < 2000 { outer(n); n += 1; }
+            n";
+        for backend in [Backend::Vm, Backend::TreeWalker] {
+            let (result, peak) = run(code, backend).await;
+            assert_eq!(result, Ok("2000".to_string()));
+            assert!(peak < 1024 * 1024, "peak memory grew to {} bytes", peak);
+        }
+    }
+
+    #[tokio::test]
+    async fn types_defined_in_a_loop_keep_memory_bounded() {
+        // The methods close over the scope that holds the type
+        let code = "
+            fn outer(i) { let big = 0..1000; struct S { v } impl S { fn make(self) { S(big) } } return i; }
+            let n = 0;
+            while n < 2000 { outer(n); n += 1; }
+            n";
+        for backend in [Backend::Vm, Backend::TreeWalker] {
+            let (result, peak) = run(code, backend).await;
+            assert_eq!(result, Ok("2000".to_string()));
+            assert!(peak < 1024 * 1024, "peak memory grew to {} bytes", peak);
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_do_not_share_printed_output() {
+        for backend in [Backend::Vm, Backend::TreeWalker] {
+            let (first, second) = tokio::join!(run("print(\"first\");", backend), run("print(\"second\");", backend));
+            assert_eq!(first.0, Ok("first".to_string()));
+            assert_eq!(second.0, Ok("second".to_string()));
+        }
+    }
+}
@@ -1,19 +1,84 @@
+use super::error::{ErrorCode, LangError};
+use super::environment::Environment;
+use super::evaluator::Interpreter;
+use super::vm::Closure;
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, Signed, ToPrimitive, Zero};
+use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::fmt;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+// Builtins receive the interpreter so they can call back into user functions
+pub type Builtin = fn(&mut Interpreter, &[Object]) -> Result<Object, LangError>;
+
+// A declared struct. Methods are added by `impl` blocks after the declaration,
+// so they sit behind a RefCell shared by every instance.
+#[derive(Debug)]
+pub struct StructType {
+    pub name: String,
+    pub fields: Vec<String>,
+    pub methods: RefCell<HashMap<String, Object>>,
+}
+
+impl StructType {
+    pub fn field_index(&self, field: &str) -> Option<usize> {
+        self.fields.iter().position(|f| f == field)
+    }
+}
+
+// A declared enum; each variant lists the names of the values it carries.
+// Like structs, enums can gain methods through `impl` blocks.
+#[derive(Debug)]
+pub struct EnumType {
+    pub name: String,
+    pub variants: Vec<(String, Vec<String>)>,
+    pub methods: RefCell<HashMap<String, Object>>,
+}
+
+impl EnumType {
+    pub fn variant_index(&self, variant: &str) -> Option<usize> {
+        self.variants.iter().position(|(name, _)| name == variant)
+    }
+}
 
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub enum Object {
+    Integer(BigInt),
     Number(f64),
     Boolean(bool),
     String(String),
     Array(Vec<Object>),
+    Map(BTreeMap<HashKey, Object>),
     Function {
         parameters: Vec<String>,
         body: super::parser::AstNode,
-        closure: HashMap<String, Object>,
+        closure: Environment,
+    },
+    BuiltinFunction(Builtin),
+    // Function compiled for the VM, with the variables it captured
+    Closure(Rc<Closure>),
+    Struct(Rc<StructType>),
+    // Field values are stored in declaration order
+    Instance {
+        struct_type: Rc<StructType>,
+        fields: Vec<Object>,
+    },
+    Enum(Rc<EnumType>),
+    // `variant` indexes into the enum's variant list
+    Variant {
+        enum_type: Rc<EnumType>,
+        variant: usize,
+        values: Vec<Object>,
+    },
+    // Value caught by `catch`; `kind` is the error code for runtime failures
+    // and "Error" unless given otherwise for thrown values
+    Error {
+        kind: String,
+        message: String,
     },
-    BuiltinFunction(fn(&[Object]) -> Result<Object, String>),
     Null,
 }
 
@@ -21,13 +86,26 @@ pub enum Object {
 impl PartialEq for Object {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
+            (Object::Integer(a), Object::Integer(b)) => a == b,
             (Object::Number(a), Object::Number(b)) => (a - b).abs() < f64::EPSILON,
             (Object::Boolean(a), Object::Boolean(b)) => a == b,
             (Object::String(a), Object::String(b)) => a == b,
             (Object::Array(a), Object::Array(b)) => a == b,
+            (Object::Map(a), Object::Map(b)) => a == b,
             (Object::Function { parameters: pa, body: ba, closure: ca },
              Object::Function { parameters: pb, body: bb, closure: cb }) =>
-                pa == pb && ba == bb && ca == cb,
+                pa == pb && ba == bb && ca.ptr_eq(cb),
+            (Object::Closure(a), Object::Closure(b)) => Rc::ptr_eq(a, b),
+            (Object::Struct(a), Object::Struct(b)) => Rc::ptr_eq(a, b),
+            (Object::Instance { struct_type: ta, fields: fa },
+             Object::Instance { struct_type: tb, fields: fb }) =>
+                Rc::ptr_eq(ta, tb) && fa == fb,
+            (Object::Enum(a), Object::Enum(b)) => Rc::ptr_eq(a, b),
+            (Object::Variant { enum_type: ta, variant: va, values: xa },
+             Object::Variant { enum_type: tb, variant: vb, values: xb }) =>
+                Rc::ptr_eq(ta, tb) && va == vb && xa == xb,
+            (Object::Error { kind: ka, message: ma }, Object::Error { kind: kb, message: mb }) =>
+                ka == kb && ma == mb,
             (Object::Null, Object::Null) => true,
             // Do not compare BuiltinFunction by pointer
             (Object::BuiltinFunction(_), Object::BuiltinFunction(_)) => false,
@@ -39,6 +117,7 @@ impl PartialEq for Object {
 impl fmt::Display for Object {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Object::Integer(n) => write!(f, "{}", n),
             Object::Number(n) => {
                 if n.fract() == 0.0 && n.abs() < 1e15 {
                     write!(f, "{}", *n as i64)
@@ -49,18 +128,43 @@ impl fmt::Display for Object {
             Object::Boolean(b) => write!(f, "{}", b),
             Object::String(s) => write!(f, "{}", s), // Don't add quotes for display
             Object::Array(elements) => {
-                let elements_str: Vec<String> = elements.iter()
-                    .map(|e| match e {
-                        Object::String(s) => format!("\"{}\"", s),
-                        other => other.to_string(),
-                    })
-                    .collect();
+                let elements_str: Vec<String> = elements.iter().map(display_element).collect();
                 write!(f, "[{}]", elements_str.join(", "))
             },
+            Object::Map(entries) => {
+                let entries_str: Vec<String> = entries.iter()
+                    .map(|(k, v)| format!("{}: {}", k, display_element(v)))
+                    .collect();
+                write!(f, "{{{}}}", entries_str.join(", "))
+            },
             Object::Function { parameters, .. } => {
                 write!(f, "function({})", parameters.join(", "))
             },
+            Object::Closure(closure) => {
+                write!(f, "function({})", closure.proto.parameters.join(", "))
+            },
             Object::BuiltinFunction(_) => write!(f, "builtin function"),
+            Object::Struct(struct_type) => write!(f, "struct {}", struct_type.name),
+            Object::Instance { struct_type, fields } => {
+                let fields_str: Vec<String> = struct_type.fields.iter().zip(fields)
+                    .map(|(name, value)| format!("{}: {}", name, display_element(value)))
+                    .collect();
+                if fields_str.is_empty() {
+                    write!(f, "{} {{}}", struct_type.name)
+                } else {
+                    write!(f, "{} {{ {} }}", struct_type.name, fields_str.join(", "))
+                }
+            },
+            Object::Enum(enum_type) => write!(f, "enum {}", enum_type.name),
+            Object::Variant { enum_type, variant, values } => {
+                write!(f, "{}.{}", enum_type.name, enum_type.variants[*variant].0)?;
+                if !values.is_empty() {
+                    let values_str: Vec<String> = values.iter().map(display_element).collect();
+                    write!(f, "({})", values_str.join(", "))?;
+                }
+                Ok(())
+            },
+            Object::Error { kind, message } => write!(f, "{}: {}", kind, message),
             Object::Null => write!(f, "null"),
         }
     }
@@ -70,26 +174,137 @@ impl Object {
     pub fn is_truthy(&self) -> bool {
         match self {
             Object::Boolean(b) => *b,
+            Object::Integer(n) => !n.is_zero(),
             Object::Number(n) => *n != 0.0,
             Object::String(s) => !s.is_empty(),
             Object::Array(arr) => !arr.is_empty(),
+            Object::Map(map) => !map.is_empty(),
             Object::Null => false,
-            Object::Function { .. } => true,
+            Object::Function { .. } | Object::Closure(_) => true,
             Object::BuiltinFunction(_) => true,
+            Object::Struct(_) | Object::Instance { .. } => true,
+            Object::Enum(_) | Object::Variant { .. } => true,
+            Object::Error { .. } => true,
         }
     }
 
     pub fn type_name(&self) -> &'static str {
         match self {
-            Object::Number(_) => "number",
+            Object::Integer(_) => "integer",
+            Object::Number(_) => "float",
             Object::Boolean(_) => "boolean", 
             Object::String(_) => "string",
             Object::Array(_) => "array",
-            Object::Function { .. } => "function",
+            Object::Map(_) => "map",
+            Object::Function { .. } | Object::Closure(_) => "function",
             Object::BuiltinFunction(_) => "builtin",
+            Object::Struct(_) => "struct",
+            Object::Instance { .. } => "instance",
+            Object::Enum(_) => "enum",
+            Object::Variant { .. } => "variant",
+            Object::Error { .. } => "error",
             Object::Null => "null",
         }
     }
+
+    // Numeric value as a float; integers are promoted
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Object::Integer(n) => n.to_f64(),
+            Object::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    // Non-negative whole number usable as a count or position
+    fn as_usize(&self) -> Option<usize> {
+        match self {
+            Object::Integer(n) => n.to_usize(),
+            Object::Number(n) if n.fract() == 0.0 && *n >= 0.0 => Some(*n as usize),
+            _ => None,
+        }
+    }
+
+    // Approximate heap bytes owned by this value, counted against the interpreter's
+    // memory limit. Functions and type definitions are shared, so they count as nothing.
+    pub fn heap_size(&self) -> usize {
+        const SLOT: usize = std::mem::size_of::<Object>();
+        match self {
+            Object::Integer(n) => (n.bits() / 8) as usize,
+            Object::String(s) => s.len(),
+            Object::Array(items) | Object::Instance { fields: items, .. } | Object::Variant { values: items, .. } => {
+                items.iter().map(|item| SLOT + item.heap_size()).sum()
+            }
+            Object::Map(map) => map.iter().map(|(key, value)| key.entry_size() + value.heap_size()).sum(),
+            Object::Error { kind, message } => kind.len() + message.len(),
+            _ => 0,
+        }
+    }
+}
+
+// Orders two numbers of either kind, comparing integers exactly
+pub fn compare_numbers(left: &Object, right: &Object) -> Option<Ordering> {
+    match (left, right) {
+        (Object::Integer(l), Object::Integer(r)) => Some(l.cmp(r)),
+        _ => left.as_float()?.partial_cmp(&right.as_float()?),
+    }
+}
+
+// Strings inside collections are quoted so `["1"]` and `[1]` print differently
+fn display_element(obj: &Object) -> String {
+    match obj {
+        Object::String(s) => format!("\"{}\"", s),
+        other => other.to_string(),
+    }
+}
+
+// Values usable as map keys. Floats must be whole and are stored as integers,
+// so `m[1]` and `m[1.0]` name the same entry.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HashKey {
+    Boolean(bool),
+    Integer(BigInt),
+    String(String),
+}
+
+impl HashKey {
+    pub fn from_object(obj: &Object) -> Result<HashKey, LangError> {
+        match obj {
+            Object::Boolean(b) => Ok(HashKey::Boolean(*b)),
+            Object::Integer(n) => Ok(HashKey::Integer(n.clone())),
+            Object::Number(n) if n.fract() == 0.0 => Ok(HashKey::Integer(BigInt::from_f64(*n).unwrap_or_default())),
+            Object::String(s) => Ok(HashKey::String(s.clone())),
+            other => Err(LangError::new(ErrorCode::TypeMismatch, format!("Cannot use {} {} as a map key", other.type_name(), other))),
+        }
+    }
+
+    // Bytes a map entry with this key costs before counting its value
+    pub fn entry_size(&self) -> usize {
+        let key_size = match self {
+            HashKey::Integer(n) => (n.bits() / 8) as usize,
+            HashKey::String(s) => s.len(),
+            HashKey::Boolean(_) => 0,
+        };
+        std::mem::size_of::<HashKey>() + std::mem::size_of::<Object>() + key_size
+    }
+
+    pub fn to_object(&self) -> Object {
+        match self {
+            HashKey::Boolean(b) => Object::Boolean(*b),
+            HashKey::Integer(n) => Object::Integer(n.clone()),
+            HashKey::String(s) => Object::String(s.clone()),
+        }
+    }
+}
+
+impl fmt::Display for HashKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashKey::Boolean(b) => write!(f, "{}", b),
+            HashKey::Integer(n) => write!(f, "{}", n),
+            HashKey::String(s) => write!(f, "\"{}\"", s),
+        }
+    }
 }
 
 // Built-in functions
@@ -108,6 +323,21 @@ pub fn get_builtins() -> HashMap<String, Object> {
     builtins.insert("last".to_string(), Object::BuiltinFunction(builtin_last));
     builtins.insert("rest".to_string(), Object::BuiltinFunction(builtin_rest));
     
+    // Map functions
+    builtins.insert("keys".to_string(), Object::BuiltinFunction(builtin_keys));
+    builtins.insert("values".to_string(), Object::BuiltinFunction(builtin_values));
+    builtins.insert("has_key".to_string(), Object::BuiltinFunction(builtin_has_key));
+    builtins.insert("delete".to_string(), Object::BuiltinFunction(builtin_delete));
+    
+    // Higher-order functions
+    builtins.insert("map".to_string(), Object::BuiltinFunction(builtin_map));
+    builtins.insert("filter".to_string(), Object::BuiltinFunction(builtin_filter));
+    builtins.insert("reduce".to_string(), Object::BuiltinFunction(builtin_reduce));
+    builtins.insert("sort_by".to_string(), Object::BuiltinFunction(builtin_sort_by));
+    builtins.insert("any".to_string(), Object::BuiltinFunction(builtin_any));
+    builtins.insert("all".to_string(), Object::BuiltinFunction(builtin_all));
+    builtins.insert("each".to_string(), Object::BuiltinFunction(builtin_each));
+    
     // Mathematical functions
     builtins.insert("abs".to_string(), Object::BuiltinFunction(builtin_abs));
     builtins.insert("sqrt".to_string(), Object::BuiltinFunction(builtin_sqrt));
@@ -131,14 +361,16 @@ pub fn get_builtins() -> HashMap<String, Object> {
     
     // Type checking and conversion functions
     builtins.insert("type".to_string(), Object::BuiltinFunction(builtin_type));
+    builtins.insert("is_integer".to_string(), Object::BuiltinFunction(builtin_is_integer));
     builtins.insert("to_string".to_string(), Object::BuiltinFunction(builtin_to_string));
     builtins.insert("to_number".to_string(), Object::BuiltinFunction(builtin_to_number));
+    builtins.insert("error".to_string(), Object::BuiltinFunction(builtin_error));
     
     builtins
 }
 
 // I/O Functions
-fn builtin_print(args: &[Object]) -> Result<Object, String> {
+fn builtin_print(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     for (i, arg) in args.iter().enumerate() {
         if i > 0 {
             super::evaluator::add_output(" ");
@@ -148,61 +380,64 @@ fn builtin_print(args: &[Object]) -> Result<Object, String> {
     Ok(Object::Null)
 }
 
-fn builtin_println(args: &[Object]) -> Result<Object, String> {
-    builtin_print(args)?;
+fn builtin_println(interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
+    builtin_print(interp, args)?;
     super::evaluator::add_output("\n");
     Ok(Object::Null)
 }
 
 // Collection Functions
-fn builtin_len(args: &[Object]) -> Result<Object, String> {
+fn builtin_len(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 1 {
-        return Err(format!("len() takes exactly 1 argument, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("len() takes exactly 1 argument, got {}", args.len())));
     }
     
     match &args[0] {
-        Object::String(s) => Ok(Object::Number(s.chars().count() as f64)),
-        Object::Array(arr) => Ok(Object::Number(arr.len() as f64)),
-        other => Err(format!("len() not supported for {}", other.type_name())),
+        Object::String(s) => Ok(Object::Integer(BigInt::from(s.chars().count()))),
+        Object::Array(arr) => Ok(Object::Integer(BigInt::from(arr.len()))),
+        Object::Map(map) => Ok(Object::Integer(BigInt::from(map.len()))),
+        other => Err(LangError::new(ErrorCode::TypeMismatch, format!("len() not supported for {}", other.type_name()))),
     }
 }
 
-fn builtin_push(args: &[Object]) -> Result<Object, String> {
+fn builtin_push(interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 2 {
-        return Err(format!("push() takes exactly 2 arguments, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("push() takes exactly 2 arguments, got {}", args.len())));
     }
     
     match &args[0] {
         Object::Array(arr) => {
+            // The whole array is copied
+            interp.reserve(args[0].heap_size() + std::mem::size_of::<Object>() + args[1].heap_size())?;
             let mut new_arr = arr.clone();
             new_arr.push(args[1].clone());
             Ok(Object::Array(new_arr))
         },
-        other => Err(format!("push() not supported for {}", other.type_name())),
+        other => Err(LangError::new(ErrorCode::TypeMismatch, format!("push() not supported for {}", other.type_name()))),
     }
 }
 
-fn builtin_pop(args: &[Object]) -> Result<Object, String> {
+fn builtin_pop(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 1 {
-        return Err(format!("pop() takes exactly 1 argument, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("pop() takes exactly 1 argument, got {}", args.len())));
     }
     
     match &args[0] {
         Object::Array(arr) => {
             if arr.is_empty() {
-                return Err("Cannot pop from empty array".to_string());
+                return Err(LangError::new(ErrorCode::InvalidArgument, "Cannot pop from empty array"));
             }
             let mut new_arr = arr.clone();
             let popped = new_arr.pop().unwrap();
             Ok(popped)
         },
-        other => Err(format!("pop() not supported for {}", other.type_name())),
+        other => Err(LangError::new(ErrorCode::TypeMismatch, format!("pop() not supported for {}", other.type_name()))),
     }
 }
 
-fn builtin_first(args: &[Object]) -> Result<Object, String> {
+fn builtin_first(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 1 {
-        return Err(format!("first() takes exactly 1 argument, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("first() takes exactly 1 argument, got {}", args.len())));
     }
     
     match &args[0] {
@@ -213,13 +448,13 @@ fn builtin_first(args: &[Object]) -> Result<Object, String> {
                 Ok(arr[0].clone())
             }
         },
-        other => Err(format!("first() not supported for {}", other.type_name())),
+        other => Err(LangError::new(ErrorCode::TypeMismatch, format!("first() not supported for {}", other.type_name()))),
     }
 }
 
-fn builtin_last(args: &[Object]) -> Result<Object, String> {
+fn builtin_last(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 1 {
-        return Err(format!("last() takes exactly 1 argument, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("last() takes exactly 1 argument, got {}", args.len())));
     }
     
     match &args[0] {
@@ -230,13 +465,13 @@ fn builtin_last(args: &[Object]) -> Result<Object, String> {
                 Ok(arr[arr.len() - 1].clone())
             }
         },
-        other => Err(format!("last() not supported for {}", other.type_name())),
+        other => Err(LangError::new(ErrorCode::TypeMismatch, format!("last() not supported for {}", other.type_name()))),
     }
 }
 
-fn builtin_rest(args: &[Object]) -> Result<Object, String> {
+fn builtin_rest(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 1 {
-        return Err(format!("rest() takes exactly 1 argument, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("rest() takes exactly 1 argument, got {}", args.len())));
     }
     
     match &args[0] {
@@ -247,250 +482,432 @@ fn builtin_rest(args: &[Object]) -> Result<Object, String> {
                 Ok(Object::Array(arr[1..].to_vec()))
             }
         },
-        other => Err(format!("rest() not supported for {}", other.type_name())),
+        other => Err(LangError::new(ErrorCode::TypeMismatch, format!("rest() not supported for {}", other.type_name()))),
+    }
+}
+
+// Map Functions
+fn builtin_keys(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
+    if args.len() != 1 {
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("keys() takes exactly 1 argument, got {}", args.len())));
+    }
+    
+    match &args[0] {
+        Object::Map(map) => Ok(Object::Array(map.keys().map(HashKey::to_object).collect())),
+        other => Err(LangError::new(ErrorCode::TypeMismatch, format!("keys() not supported for {}", other.type_name()))),
+    }
+}
+
+fn builtin_values(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
+    if args.len() != 1 {
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("values() takes exactly 1 argument, got {}", args.len())));
+    }
+    
+    match &args[0] {
+        Object::Map(map) => Ok(Object::Array(map.values().cloned().collect())),
+        other => Err(LangError::new(ErrorCode::TypeMismatch, format!("values() not supported for {}", other.type_name()))),
+    }
+}
+
+fn builtin_has_key(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
+    if args.len() != 2 {
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("has_key() takes exactly 2 arguments, got {}", args.len())));
+    }
+    
+    match &args[0] {
+        Object::Map(map) => Ok(Object::Boolean(map.contains_key(&HashKey::from_object(&args[1])?))),
+        other => Err(LangError::new(ErrorCode::TypeMismatch, format!("has_key() not supported for {}", other.type_name()))),
     }
 }
 
+// Like push(), returns a new map rather than modifying the argument
+fn builtin_delete(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
+    if args.len() != 2 {
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("delete() takes exactly 2 arguments, got {}", args.len())));
+    }
+    
+    match &args[0] {
+        Object::Map(map) => {
+            let mut new_map = map.clone();
+            new_map.remove(&HashKey::from_object(&args[1])?);
+            Ok(Object::Map(new_map))
+        },
+        other => Err(LangError::new(ErrorCode::TypeMismatch, format!("delete() not supported for {}", other.type_name()))),
+    }
+}
+
+// Higher-Order Functions
+fn array_and_callback<'a>(name: &str, args: &'a [Object]) -> Result<(&'a [Object], &'a Object), LangError> {
+    if args.len() != 2 {
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("{}() takes exactly 2 arguments, got {}", name, args.len())));
+    }
+    
+    match (&args[0], &args[1]) {
+        (Object::Array(arr), f @ (Object::Function { .. } | Object::Closure(_) | Object::BuiltinFunction(_))) => Ok((arr, f)),
+        _ => Err(LangError::new(ErrorCode::TypeMismatch, format!("{}() requires array and function", name))),
+    }
+}
+
+fn builtin_map(interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
+    let (arr, f) = array_and_callback("map", args)?;
+    let mapped: Result<Vec<Object>, LangError> = arr.iter()
+        .map(|item| interp.call(f, vec![item.clone()]))
+        .collect();
+    Ok(Object::Array(mapped?))
+}
+
+fn builtin_filter(interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
+    let (arr, f) = array_and_callback("filter", args)?;
+    let mut kept = Vec::new();
+    for item in arr {
+        if interp.call(f, vec![item.clone()])?.is_truthy() {
+            kept.push(item.clone());
+        }
+    }
+    Ok(Object::Array(kept))
+}
+
+fn builtin_reduce(interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
+    if args.len() != 2 && args.len() != 3 {
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("reduce() takes 2 or 3 arguments, got {}", args.len())));
+    }
+    let (arr, f) = array_and_callback("reduce", &args[..2])?;
+    
+    // Without an initial value the first element seeds the accumulator
+    let (mut acc, rest) = match args.get(2) {
+        Some(initial) => (initial.clone(), arr),
+        None => match arr.split_first() {
+            Some((first, rest)) => (first.clone(), rest),
+            None => return Err(LangError::new(ErrorCode::InvalidArgument, "reduce() of empty array with no initial value")),
+        },
+    };
+    for item in rest {
+        acc = interp.call(f, vec![acc, item.clone()])?;
+    }
+    Ok(acc)
+}
+
+fn builtin_sort_by(interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
+    let (arr, f) = array_and_callback("sort_by", args)?;
+    
+    // The comparator returns a negative number when a should come before b.
+    // std's sort can panic on inconsistent orderings, so use a merge sort that
+    // also lets comparator errors propagate.
+    let mut compare = |a: &Object, b: &Object| -> Result<bool, LangError> {
+        match interp.call(f, vec![a.clone(), b.clone()])? {
+            Object::Integer(n) => Ok(n.is_positive()),
+            Object::Number(n) => Ok(n > 0.0),
+            other => Err(LangError::new(ErrorCode::TypeMismatch, format!("sort_by() comparator must return a number, got {}", other.type_name()))),
+        }
+    };
+    
+    let mut sorted = arr.to_vec();
+    let mut width = 1;
+    while width < sorted.len() {
+        let mut merged = Vec::with_capacity(sorted.len());
+        for chunk in sorted.chunks(width * 2) {
+            let (left, right) = chunk.split_at(width.min(chunk.len()));
+            let (mut i, mut j) = (0, 0);
+            while i < left.len() && j < right.len() {
+                // Take from the left on ties to keep the sort stable
+                if compare(&left[i], &right[j])? {
+                    merged.push(right[j].clone());
+                    j += 1;
+                } else {
+                    merged.push(left[i].clone());
+                    i += 1;
+                }
+            }
+            merged.extend_from_slice(&left[i..]);
+            merged.extend_from_slice(&right[j..]);
+        }
+        sorted = merged;
+        width *= 2;
+    }
+    Ok(Object::Array(sorted))
+}
+
+fn builtin_any(interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
+    let (arr, f) = array_and_callback("any", args)?;
+    for item in arr {
+        if interp.call(f, vec![item.clone()])?.is_truthy() {
+            return Ok(Object::Boolean(true));
+        }
+    }
+    Ok(Object::Boolean(false))
+}
+
+fn builtin_all(interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
+    let (arr, f) = array_and_callback("all", args)?;
+    for item in arr {
+        if !interp.call(f, vec![item.clone()])?.is_truthy() {
+            return Ok(Object::Boolean(false));
+        }
+    }
+    Ok(Object::Boolean(true))
+}
+
+fn builtin_each(interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
+    let (arr, f) = array_and_callback("each", args)?;
+    for item in arr {
+        interp.call(f, vec![item.clone()])?;
+    }
+    Ok(Object::Null)
+}
+
 // Mathematical Functions
-fn builtin_abs(args: &[Object]) -> Result<Object, String> {
+fn builtin_abs(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 1 {
-        return Err(format!("abs() takes exactly 1 argument, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("abs() takes exactly 1 argument, got {}", args.len())));
     }
     
     match &args[0] {
+        Object::Integer(n) => Ok(Object::Integer(n.abs())),
         Object::Number(n) => Ok(Object::Number(n.abs())),
-        other => Err(format!("abs() not supported for {}", other.type_name())),
+        other => Err(LangError::new(ErrorCode::TypeMismatch, format!("abs() not supported for {}", other.type_name()))),
     }
 }
 
-fn builtin_sqrt(args: &[Object]) -> Result<Object, String> {
+fn builtin_sqrt(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 1 {
-        return Err(format!("sqrt() takes exactly 1 argument, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("sqrt() takes exactly 1 argument, got {}", args.len())));
     }
     
-    match &args[0] {
-        Object::Number(n) => {
-            if *n < 0.0 {
-                return Err("Cannot take square root of negative number".to_string());
+    match args[0].as_float() {
+        Some(n) => {
+            if n < 0.0 {
+                return Err(LangError::new(ErrorCode::InvalidArgument, "Cannot take square root of negative number"));
             }
             Ok(Object::Number(n.sqrt()))
         },
-        other => Err(format!("sqrt() not supported for {}", other.type_name())),
+        None => Err(LangError::new(ErrorCode::TypeMismatch, format!("sqrt() not supported for {}", args[0].type_name()))),
     }
 }
 
-fn builtin_pow(args: &[Object]) -> Result<Object, String> {
+fn builtin_pow(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 2 {
-        return Err(format!("pow() takes exactly 2 arguments, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("pow() takes exactly 2 arguments, got {}", args.len())));
     }
     
     match (&args[0], &args[1]) {
-        (Object::Number(base), Object::Number(exp)) => {
-            let result = base.powf(*exp);
-            if result.is_infinite() || result.is_nan() {
-                return Err("Power operation resulted in infinity or NaN".to_string());
-            }
-            Ok(Object::Number(result))
+        // Integer powers stay exact
+        (Object::Integer(base), Object::Integer(exp)) if !exp.is_negative() => match exp.to_u32() {
+            Some(exp) => Ok(Object::Integer(super::evaluator::checked_pow(base, exp)?)),
+            None => Err(LangError::new(ErrorCode::ArithmeticError, "Exponent too large")),
+        },
+        (base, exp) => match (base.as_float(), exp.as_float()) {
+            (Some(base), Some(exp)) => {
+                let result = base.powf(exp);
+                if result.is_infinite() || result.is_nan() {
+                    return Err(LangError::new(ErrorCode::ArithmeticError, "Power operation resulted in infinity or NaN"));
+                }
+                Ok(Object::Number(result))
+            },
+            _ => Err(LangError::new(ErrorCode::TypeMismatch, "pow() requires two numbers")),
         },
-        _ => Err("pow() requires two numbers".to_string()),
     }
 }
 
-fn builtin_floor(args: &[Object]) -> Result<Object, String> {
+fn builtin_floor(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 1 {
-        return Err(format!("floor() takes exactly 1 argument, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("floor() takes exactly 1 argument, got {}", args.len())));
     }
     
     match &args[0] {
-        Object::Number(n) => Ok(Object::Number(n.floor())),
-        other => Err(format!("floor() not supported for {}", other.type_name())),
+        Object::Integer(n) => Ok(Object::Integer(n.clone())),
+        Object::Number(n) => float_to_integer(n.floor()),
+        other => Err(LangError::new(ErrorCode::TypeMismatch, format!("floor() not supported for {}", other.type_name()))),
     }
 }
 
-fn builtin_ceil(args: &[Object]) -> Result<Object, String> {
+fn builtin_ceil(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 1 {
-        return Err(format!("ceil() takes exactly 1 argument, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("ceil() takes exactly 1 argument, got {}", args.len())));
     }
     
     match &args[0] {
-        Object::Number(n) => Ok(Object::Number(n.ceil())),
-        other => Err(format!("ceil() not supported for {}", other.type_name())),
+        Object::Integer(n) => Ok(Object::Integer(n.clone())),
+        Object::Number(n) => float_to_integer(n.ceil()),
+        other => Err(LangError::new(ErrorCode::TypeMismatch, format!("ceil() not supported for {}", other.type_name()))),
     }
 }
 
-fn builtin_round(args: &[Object]) -> Result<Object, String> {
+fn builtin_round(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 1 {
-        return Err(format!("round() takes exactly 1 argument, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("round() takes exactly 1 argument, got {}", args.len())));
     }
     
     match &args[0] {
-        Object::Number(n) => Ok(Object::Number(n.round())),
-        other => Err(format!("round() not supported for {}", other.type_name())),
+        Object::Integer(n) => Ok(Object::Integer(n.clone())),
+        Object::Number(n) => float_to_integer(n.round()),
+        other => Err(LangError::new(ErrorCode::TypeMismatch, format!("round() not supported for {}", other.type_name()))),
     }
 }
 
-fn builtin_min(args: &[Object]) -> Result<Object, String> {
+fn builtin_min(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.is_empty() {
-        return Err("min() requires at least 1 argument".to_string());
+        return Err(LangError::new(ErrorCode::ArgumentCount, "min() requires at least 1 argument"));
     }
     
-    let mut min_val = match &args[0] {
-        Object::Number(n) => *n,
-        other => return Err(format!("min() not supported for {}", other.type_name())),
-    };
-    
-    for arg in &args[1..] {
-        match arg {
-            Object::Number(n) => {
-                if *n < min_val {
-                    min_val = *n;
-                }
-            },
-            other => return Err(format!("min() not supported for {}", other.type_name())),
+    let mut min_val = &args[0];
+    for arg in args {
+        match compare_numbers(arg, min_val) {
+            Some(Ordering::Less) => min_val = arg,
+            Some(_) => {},
+            None => {
+                let bad = if arg.as_float().is_none() { arg } else { min_val };
+                return Err(LangError::new(ErrorCode::TypeMismatch, format!("min() not supported for {}", bad.type_name())));
+            }
         }
     }
     
-    Ok(Object::Number(min_val))
+    Ok(min_val.clone())
 }
 
-fn builtin_max(args: &[Object]) -> Result<Object, String> {
+fn builtin_max(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.is_empty() {
-        return Err("max() requires at least 1 argument".to_string());
+        return Err(LangError::new(ErrorCode::ArgumentCount, "max() requires at least 1 argument"));
     }
     
-    let mut max_val = match &args[0] {
-        Object::Number(n) => *n,
-        other => return Err(format!("max() not supported for {}", other.type_name())),
-    };
-    
-    for arg in &args[1..] {
-        match arg {
-            Object::Number(n) => {
-                if *n > max_val {
-                    max_val = *n;
-                }
-            },
-            other => return Err(format!("max() not supported for {}", other.type_name())),
+    let mut max_val = &args[0];
+    for arg in args {
+        match compare_numbers(arg, max_val) {
+            Some(Ordering::Greater) => max_val = arg,
+            Some(_) => {},
+            None => {
+                let bad = if arg.as_float().is_none() { arg } else { max_val };
+                return Err(LangError::new(ErrorCode::TypeMismatch, format!("max() not supported for {}", bad.type_name())));
+            }
         }
     }
     
-    Ok(Object::Number(max_val))
+    Ok(max_val.clone())
 }
 
-fn builtin_sin(args: &[Object]) -> Result<Object, String> {
+fn builtin_sin(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 1 {
-        return Err(format!("sin() takes exactly 1 argument, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("sin() takes exactly 1 argument, got {}", args.len())));
     }
     
-    match &args[0] {
-        Object::Number(n) => Ok(Object::Number(n.sin())),
-        other => Err(format!("sin() not supported for {}", other.type_name())),
+    match args[0].as_float() {
+        Some(n) => Ok(Object::Number(n.sin())),
+        None => Err(LangError::new(ErrorCode::TypeMismatch, format!("sin() not supported for {}", args[0].type_name()))),
     }
 }
 
-fn builtin_cos(args: &[Object]) -> Result<Object, String> {
+fn builtin_cos(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 1 {
-        return Err(format!("cos() takes exactly 1 argument, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("cos() takes exactly 1 argument, got {}", args.len())));
     }
     
-    match &args[0] {
-        Object::Number(n) => Ok(Object::Number(n.cos())),
-        other => Err(format!("cos() not supported for {}", other.type_name())),
+    match args[0].as_float() {
+        Some(n) => Ok(Object::Number(n.cos())),
+        None => Err(LangError::new(ErrorCode::TypeMismatch, format!("cos() not supported for {}", args[0].type_name()))),
     }
 }
 
-fn builtin_tan(args: &[Object]) -> Result<Object, String> {
+fn builtin_tan(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 1 {
-        return Err(format!("tan() takes exactly 1 argument, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("tan() takes exactly 1 argument, got {}", args.len())));
     }
     
-    match &args[0] {
-        Object::Number(n) => Ok(Object::Number(n.tan())),
-        other => Err(format!("tan() not supported for {}", other.type_name())),
+    match args[0].as_float() {
+        Some(n) => Ok(Object::Number(n.tan())),
+        None => Err(LangError::new(ErrorCode::TypeMismatch, format!("tan() not supported for {}", args[0].type_name()))),
+    }
+}
+
+// Whole floats from floor/ceil/round become integers
+fn float_to_integer(n: f64) -> Result<Object, LangError> {
+    match BigInt::from_f64(n) {
+        Some(i) => Ok(Object::Integer(i)),
+        None => Err(LangError::new(ErrorCode::ArithmeticError, "Cannot convert infinity or NaN to an integer")),
     }
 }
 
 // String Functions
-fn builtin_substr(args: &[Object]) -> Result<Object, String> {
+fn builtin_substr(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 3 {
-        return Err(format!("substr() takes exactly 3 arguments, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("substr() takes exactly 3 arguments, got {}", args.len())));
     }
     
-    match (&args[0], &args[1], &args[2]) {
-        (Object::String(s), Object::Number(start), Object::Number(len)) => {
-            let start = *start as usize;
-            let len = *len as usize;
+    match (&args[0], args[1].as_usize(), args[2].as_usize()) {
+        (Object::String(s), Some(start), Some(len)) => {
             let chars: Vec<char> = s.chars().collect();
             
             if start >= chars.len() {
                 return Ok(Object::String(String::new()));
             }
             
-            let end = std::cmp::min(start + len, chars.len());
+            let end = std::cmp::min(start.saturating_add(len), chars.len());
             let substr: String = chars[start..end].iter().collect();
             Ok(Object::String(substr))
         },
-        _ => Err("substr() requires string, number, number".to_string()),
+        _ => Err(LangError::new(ErrorCode::TypeMismatch, "substr() requires string, number, number")),
     }
 }
 
-fn builtin_upper(args: &[Object]) -> Result<Object, String> {
+fn builtin_upper(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 1 {
-        return Err(format!("upper() takes exactly 1 argument, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("upper() takes exactly 1 argument, got {}", args.len())));
     }
     
     match &args[0] {
         Object::String(s) => Ok(Object::String(s.to_uppercase())),
-        other => Err(format!("upper() not supported for {}", other.type_name())),
+        other => Err(LangError::new(ErrorCode::TypeMismatch, format!("upper() not supported for {}", other.type_name()))),
     }
 }
 
-fn builtin_lower(args: &[Object]) -> Result<Object, String> {
+fn builtin_lower(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 1 {
-        return Err(format!("lower() takes exactly 1 argument, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("lower() takes exactly 1 argument, got {}", args.len())));
     }
     
     match &args[0] {
         Object::String(s) => Ok(Object::String(s.to_lowercase())),
-        other => Err(format!("lower() not supported for {}", other.type_name())),
+        other => Err(LangError::new(ErrorCode::TypeMismatch, format!("lower() not supported for {}", other.type_name()))),
     }
 }
 
-fn builtin_trim(args: &[Object]) -> Result<Object, String> {
+fn builtin_trim(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 1 {
-        return Err(format!("trim() takes exactly 1 argument, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("trim() takes exactly 1 argument, got {}", args.len())));
     }
     
     match &args[0] {
         Object::String(s) => Ok(Object::String(s.trim().to_string())),
-        other => Err(format!("trim() not supported for {}", other.type_name())),
+        other => Err(LangError::new(ErrorCode::TypeMismatch, format!("trim() not supported for {}", other.type_name()))),
     }
 }
 
-fn builtin_split(args: &[Object]) -> Result<Object, String> {
+fn builtin_split(interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 2 {
-        return Err(format!("split() takes exactly 2 arguments, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("split() takes exactly 2 arguments, got {}", args.len())));
     }
     
     match (&args[0], &args[1]) {
         (Object::String(s), Object::String(delimiter)) => {
+            // Each part costs a whole value, so splitting can take far more memory than the string
+            let count = if delimiter.is_empty() { s.chars().count() + 2 } else { s.matches(delimiter.as_str()).count() + 1 };
+            interp.reserve(count * std::mem::size_of::<Object>() + s.len())?;
             let parts: Vec<Object> = s.split(delimiter)
                 .map(|part| Object::String(part.to_string()))
                 .collect();
             Ok(Object::Array(parts))
         },
-        _ => Err("split() requires two strings".to_string()),
+        _ => Err(LangError::new(ErrorCode::TypeMismatch, "split() requires two strings")),
     }
 }
 
-fn builtin_join(args: &[Object]) -> Result<Object, String> {
+fn builtin_join(interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 2 {
-        return Err(format!("join() takes exactly 2 arguments, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("join() takes exactly 2 arguments, got {}", args.len())));
     }
     
     match (&args[0], &args[1]) {
         (Object::Array(arr), Object::String(separator)) => {
-            let strings: Result<Vec<String>, String> = arr.iter()
+            interp.reserve(args[0].heap_size() + separator.len() * arr.len())?;
+            let strings: Result<Vec<String>, LangError> = arr.iter()
                 .map(|obj| match obj {
                     Object::String(s) => Ok(s.clone()),
                     other => Ok(other.to_string()),
@@ -502,41 +919,74 @@ fn builtin_join(args: &[Object]) -> Result<Object, String> {
                 Err(e) => Err(e),
             }
         },
-        _ => Err("join() requires array and string".to_string()),
+        _ => Err(LangError::new(ErrorCode::TypeMismatch, "join() requires array and string")),
     }
 }
 
 // Type Functions
-fn builtin_type(args: &[Object]) -> Result<Object, String> {
+fn builtin_type(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 1 {
-        return Err(format!("type() takes exactly 1 argument, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("type() takes exactly 1 argument, got {}", args.len())));
     }
     
-    Ok(Object::String(args[0].type_name().to_string()))
+    // Integers and floats both report "number", as they did before integers
+    // existed; `is_integer` tells them apart. Instances and variants report
+    // their declared type, so `type(p)` is "Point".
+    let name = match &args[0] {
+        Object::Integer(_) | Object::Number(_) => "number",
+        Object::Instance { struct_type, .. } => return Ok(Object::String(struct_type.name.clone())),
+        Object::Variant { enum_type, .. } => return Ok(Object::String(enum_type.name.clone())),
+        other => other.type_name(),
+    };
+    Ok(Object::String(name.to_string()))
+}
+
+fn builtin_is_integer(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
+    if args.len() != 1 {
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("is_integer() takes exactly 1 argument, got {}", args.len())));
+    }
+    
+    Ok(Object::Boolean(matches!(args[0], Object::Integer(_))))
 }
 
-fn builtin_to_string(args: &[Object]) -> Result<Object, String> {
+// `error(message)` or `error(kind, message)`, for use with `throw`
+fn builtin_error(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
+    let (kind, message) = match args {
+        [message] => ("Error".to_string(), message),
+        [Object::String(kind), message] => (kind.clone(), message),
+        [other, _] => return Err(LangError::new(ErrorCode::TypeMismatch, format!("error() kind must be a string, got {}", other.type_name()))),
+        _ => return Err(LangError::new(ErrorCode::ArgumentCount, format!("error() takes 1 or 2 arguments, got {}", args.len()))),
+    };
+    Ok(Object::Error { kind, message: message.to_string() })
+}
+
+fn builtin_to_string(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 1 {
-        return Err(format!("to_string() takes exactly 1 argument, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("to_string() takes exactly 1 argument, got {}", args.len())));
     }
     
     Ok(Object::String(args[0].to_string()))
 }
 
-fn builtin_to_number(args: &[Object]) -> Result<Object, String> {
+fn builtin_to_number(_interp: &mut Interpreter, args: &[Object]) -> Result<Object, LangError> {
     if args.len() != 1 {
-        return Err(format!("to_number() takes exactly 1 argument, got {}", args.len()));
+        return Err(LangError::new(ErrorCode::ArgumentCount, format!("to_number() takes exactly 1 argument, got {}", args.len())));
     }
     
     match &args[0] {
+        Object::Integer(n) => Ok(Object::Integer(n.clone())),
         Object::Number(n) => Ok(Object::Number(*n)),
         Object::String(s) => {
+            let s = s.trim();
+            if let Ok(n) = s.parse::<BigInt>() {
+                return Ok(Object::Integer(n));
+            }
             match s.parse::<f64>() {
                 Ok(n) => Ok(Object::Number(n)),
-                Err(_) => Err(format!("Cannot convert '{}' to number", s)),
+                Err(_) => Err(LangError::new(ErrorCode::InvalidArgument, format!("Cannot convert '{}' to number", s))),
             }
         },
-        Object::Boolean(b) => Ok(Object::Number(if *b { 1.0 } else { 0.0 })),
-        other => Err(format!("Cannot convert {} to number", other.type_name())),
+        Object::Boolean(b) => Ok(Object::Integer(BigInt::from(*b as u8))),
+        other => Err(LangError::new(ErrorCode::TypeMismatch, format!("Cannot convert {} to number", other.type_name()))),
     }
 }
\ No newline at end of file
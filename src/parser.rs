@@ -1,406 +1,1174 @@
-use super::lexer::Token;
-use std::iter::Peekable;
-use std::vec::IntoIter;
+use super::error::{ErrorCode, LangError};
+use super::lexer::{Span, StringPart, Token, TokenPosition, TokenWithPosition};
+use num_bigint::BigInt;
 
 #[derive(Debug, PartialEq, Clone)]
-pub enum AstNode {
+pub struct AstNode {
+    pub kind: NodeKind,
+    pub span: Span,
+}
+
+impl AstNode {
+    pub fn new(kind: NodeKind, span: Span) -> Self {
+        AstNode { kind, span }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum NodeKind {
     // Existing nodes
+    Integer(BigInt),
     Number(f64),
     Boolean(bool),
     String(String),
+    // `"a ${b} c"`, evaluated by concatenating the displayed parts
+    InterpolatedString(Vec<TemplatePart>),
     Identifier(String),
-    
+
     // Array support
     Array(Vec<AstNode>),
+    MapLiteral(Vec<(AstNode, AstNode)>),
     ArrayAccess { array: Box<AstNode>, index: Box<AstNode> },
-    
+    // `object.field`; calling it (`object.method()`) is a method call
+    FieldAccess { object: Box<AstNode>, field: String },
+    // `a?[i]`: null instead of an error when `a` is null or has nothing at `i`
+    SafeArrayAccess { array: Box<AstNode>, index: Box<AstNode> },
+    // `a[start:end:step]` with every part optional
+    Slice {
+        array: Box<AstNode>,
+        start: Option<Box<AstNode>>,
+        end: Option<Box<AstNode>>,
+        step: Option<Box<AstNode>>
+    },
+    Null,
+
     // Existing statements
     LetStatement { name: String, value: Box<AstNode> },
-    
+
     // Control flow
-    IfStatement { 
-        condition: Box<AstNode>, 
-        then_branch: Box<AstNode>, 
-        else_branch: Option<Box<AstNode>> 
+    IfStatement {
+        condition: Box<AstNode>,
+        then_branch: Box<AstNode>,
+        else_branch: Option<Box<AstNode>>
     },
     WhileStatement { condition: Box<AstNode>, body: Box<AstNode> },
-    ForStatement { 
-        init: Box<AstNode>, 
-        condition: Box<AstNode>, 
-        increment: Box<AstNode>, 
-        body: Box<AstNode> 
+    ForStatement {
+        init: Box<AstNode>,
+        condition: Box<AstNode>,
+        increment: Box<AstNode>,
+        body: Box<AstNode>
+    },
+    // `for x in items` or `for k, v in items`; `variables` holds one or two names
+    ForInStatement {
+        variables: Vec<String>,
+        iterable: Box<AstNode>,
+        body: Box<AstNode>
     },
-    
+
     // Functions
-    FunctionDefinition { 
-        name: String, 
-        parameters: Vec<String>, 
-        body: Box<AstNode> 
+    FunctionDefinition {
+        name: String,
+        parameters: Vec<String>,
+        body: Box<AstNode>
     },
-    FunctionCall { name: String, arguments: Vec<AstNode> },
+    // `struct Point { x, y }`; instances are built by calling `Point(1, 2)`
+    StructDefinition { name: String, fields: Vec<String> },
+    // `enum Shape { Circle(r), Empty }`; each variant lists names for the values it carries
+    EnumDefinition { name: String, variants: Vec<(String, Vec<String>)> },
+    // `impl Point { fn ... }`, holding FunctionDefinition nodes
+    ImplBlock { name: String, methods: Vec<AstNode> },
+    // Anonymous function used as a value: `fn(x) { x * 2 }`
+    FunctionLiteral {
+        parameters: Vec<String>,
+        body: Box<AstNode>
+    },
+    // Call of any expression that evaluates to a function: `f(1)`, `make_adder(1)(2)`
+    FunctionCall { function: Box<AstNode>, arguments: Vec<AstNode> },
     ReturnStatement { value: Option<Box<AstNode>> },
-    
+    // `throw value`; an expression so it also works in `x ?? throw "missing"`
+    ThrowExpression { value: Box<AstNode> },
+    // `try { } catch (e) { } finally { }` with at least one of catch and finally;
+    // the catch variable is optional
+    TryStatement {
+        body: Box<AstNode>,
+        catch_variable: Option<String>,
+        catch_body: Option<Box<AstNode>>,
+        finally_body: Option<Box<AstNode>>
+    },
+
     // Control statements
     BreakStatement,
     ContinueStatement,
-    
+
+    // Assignment to a variable or element; `op` holds the arithmetic operator
+    // of a compound assignment such as `+=`
+    Assignment { target: Box<AstNode>, op: Option<Token>, value: Box<AstNode> },
+
     // Existing expressions
     InfixExpression { op: Token, left: Box<AstNode>, right: Box<AstNode> },
+    // `&&` and `||`, kept apart from other infix operators because the right
+    // side is only evaluated when needed
+    LogicalExpression { op: Token, left: Box<AstNode>, right: Box<AstNode> },
     PrefixExpression { op: Token, right: Box<AstNode> },
+    // `start..end` or `start..=end`
+    Range { start: Box<AstNode>, end: Box<AstNode>, inclusive: bool },
+    Conditional { condition: Box<AstNode>, consequence: Box<AstNode>, alternative: Box<AstNode> },
+    // `match value { pattern if guard => body, ... }`; the first matching arm wins
+    Match { subject: Box<AstNode>, arms: Vec<MatchArm> },
     BlockStatement(Vec<AstNode>),
     Program(Vec<AstNode>),
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub enum TemplatePart {
+    Literal(String),
+    Expression(AstNode),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<AstNode>,
+    pub body: AstNode,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Pattern {
+    // `_`
+    Wildcard,
+    // A bare name matches anything and binds it
+    Binding(String),
+    // Number, string, boolean or null literal, compared by value
+    Literal(AstNode),
+    // `[a, b]`, or `[first, ..rest]` / `[.., last]` with at most one rest pattern,
+    // which is a Wildcard or Binding collecting the remaining elements
+    Array { prefix: Vec<Pattern>, rest: Option<Box<Pattern>>, suffix: Vec<Pattern> },
+    // `Shape.Circle(r)`, or `Shape.Empty` for a variant without values
+    Variant { enum_name: String, variant: String, fields: Vec<Pattern> },
+}
+
+// Deeper statement or expression nesting is rejected, so that the recursive descent
+// cannot overflow the stack. Left-associative and postfix chains like `a + b + c`
+// or `a[0][0]` are built in a loop, so the tree itself can be deeper; the evaluator
+// limits its own depth. The lexer applies the same cap to placeholders inside strings.
+pub const MAX_NESTING: usize = 256;
+
 pub struct Parser {
-    tokens: Peekable<IntoIter<Token>>,
+    tokens: Vec<TokenWithPosition>,
+    position: usize,
+    // Statements and prefix expressions currently being parsed inside one another
+    depth: usize,
+    // End of the most recently consumed token, used to close node spans
+    last_end: TokenPosition,
+    // Diagnostics collected while recovering from syntax errors
+    errors: Vec<LangError>,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens: tokens.into_iter().peekable() }
+    pub fn new(tokens: Vec<TokenWithPosition>) -> Self {
+        Parser { tokens, position: 0, depth: 0, last_end: TokenPosition::default(), errors: Vec::new() }
     }
 
-    pub fn parse_program(&mut self) -> Result<AstNode, String> {
-        let mut statements = Vec::new();
-        while self.tokens.peek().is_some() && self.tokens.peek() != Some(&Token::Eof) {
-            statements.push(self.parse_statement()?);
-        }
-        Ok(AstNode::Program(statements))
-    }
-
-    fn parse_statement(&mut self) -> Result<AstNode, String> {
-        match self.tokens.peek() {
-            Some(Token::Let) => self.parse_let_statement(),
-            Some(Token::If) => self.parse_if_statement(),
-            Some(Token::While) => self.parse_while_statement(),
-            Some(Token::For) => self.parse_for_statement(),
-            Some(Token::Fn) => self.parse_function_definition(),
-            Some(Token::Return) => self.parse_return_statement(),
-            Some(Token::Break) => {
-                self.tokens.next();
-                if self.tokens.peek() == Some(&Token::Semicolon) {
-                    self.tokens.next();
+    fn peek(&self) -> &Token {
+        self.tokens.get(self.position).map(|t| &t.token).unwrap_or(&Token::Eof)
+    }
+
+    fn peek_next(&self) -> &Token {
+        self.peek_at(1)
+    }
+
+    fn peek_at(&self, offset: usize) -> &Token {
+        self.tokens.get(self.position + offset).map(|t| &t.token).unwrap_or(&Token::Eof)
+    }
+
+    fn current_position(&self) -> TokenPosition {
+        self.current_span().start
+    }
+
+    fn current_span(&self) -> Span {
+        match self.tokens.get(self.position).or(self.tokens.last()) {
+            Some(t) => Span::new(t.position, t.end),
+            None => Span::new(self.last_end, self.last_end),
+        }
+    }
+
+    // Moves the current token out of the list rather than cloning it, since an
+    // interpolated string carries the tokens of all its placeholders
+    fn next_token(&mut self) -> Token {
+        match self.tokens.get_mut(self.position) {
+            Some(t) => {
+                self.last_end = t.end;
+                if t.token == Token::Eof {
+                    return Token::Eof;
                 }
-                Ok(AstNode::BreakStatement)
+                self.position += 1;
+                std::mem::replace(&mut t.token, Token::Eof)
+            }
+            None => Token::Eof,
+        }
+    }
+
+    fn span_from(&self, start: TokenPosition) -> Span {
+        Span::new(start, self.last_end)
+    }
+
+    // Error pointing at the current token; running out of input gets its own code
+    fn error<T>(&self, message: &str) -> Result<T, LangError> {
+        let code = match self.peek() {
+            Token::Eof => ErrorCode::UnexpectedEof,
+            _ => ErrorCode::UnexpectedToken,
+        };
+        Err(LangError::new(code, message).at(self.current_span()))
+    }
+
+    fn expect(&mut self, expected: Token, message: &str) -> Result<(), LangError> {
+        if *self.peek() == expected {
+            self.next_token();
+            Ok(())
+        } else {
+            self.error(message)
+        }
+    }
+
+    fn expect_identifier(&mut self, message: &str) -> Result<String, LangError> {
+        match self.peek() {
+            Token::Identifier(_) => match self.next_token() {
+                Token::Identifier(name) => Ok(name),
+                _ => unreachable!(),
             },
-            Some(Token::Continue) => {
-                self.tokens.next();
-                if self.tokens.peek() == Some(&Token::Semicolon) {
-                    self.tokens.next();
+            _ => self.error(message),
+        }
+    }
+
+    fn skip_semicolon(&mut self) {
+        if *self.peek() == Token::Semicolon {
+            self.next_token();
+        }
+    }
+
+    // Parses the whole input, returning every syntax error found alongside the
+    // statements that did parse
+    pub fn parse_program(&mut self) -> (AstNode, Vec<LangError>) {
+        let start = self.current_position();
+        let mut statements = Vec::new();
+        while *self.peek() != Token::Eof {
+            if let Some(statement) = self.parse_statement_or_recover() {
+                statements.push(statement);
+            }
+        }
+        let program = AstNode::new(NodeKind::Program(statements), self.span_from(start));
+        (program, std::mem::take(&mut self.errors))
+    }
+
+    fn parse_statement_or_recover(&mut self) -> Option<AstNode> {
+        let before = self.position;
+        match self.parse_statement() {
+            Ok(statement) => Some(statement),
+            Err(err) => {
+                self.errors.push(err);
+                self.synchronize();
+                // Always make progress, e.g. past a stray '}' at top level
+                if self.position == before {
+                    self.next_token();
+                }
+                None
+            }
+        }
+    }
+
+    // Panic-mode recovery: skip tokens until a ';' (consumed), a '}' closing the
+    // current block, or a keyword that starts a new statement
+    fn synchronize(&mut self) {
+        let mut depth = 0usize;
+        loop {
+            match self.peek() {
+                Token::Eof => return,
+                Token::Semicolon if depth == 0 => {
+                    self.next_token();
+                    return;
+                }
+                Token::LeftBrace => depth += 1,
+                Token::RightBrace => {
+                    if depth == 0 {
+                        return;
+                    }
+                    depth -= 1;
+                    if depth == 0 {
+                        self.next_token();
+                        return;
+                    }
                 }
-                Ok(AstNode::ContinueStatement)
+                Token::Let | Token::If | Token::While | Token::For | Token::Fn | Token::Struct |
+                Token::Enum | Token::Impl | Token::Try | Token::Return | Token::Break |
+                Token::Continue if depth == 0 => return,
+                _ => {}
+            }
+            self.next_token();
+        }
+    }
+
+    fn parse_statement(&mut self) -> Result<AstNode, LangError> {
+        self.nested(Self::parse_statement_inner)
+    }
+
+    // Runs `parse` one nesting level deeper
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, LangError>) -> Result<T, LangError> {
+        if self.depth >= MAX_NESTING {
+            return Err(LangError::new(ErrorCode::StackOverflow,
+                format!("Program is nested too deeply (more than {} levels)", MAX_NESTING)).at(self.current_span()));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn parse_statement_inner(&mut self) -> Result<AstNode, LangError> {
+        match self.peek() {
+            Token::Let => self.parse_let_statement(),
+            Token::If => self.parse_if_statement(),
+            Token::While => self.parse_while_statement(),
+            Token::For => self.parse_for_statement(),
+            // `fn name(...)` declares a function; a bare `fn(...)` is a function literal
+            Token::Fn if matches!(self.peek_next(), Token::Identifier(_)) => self.parse_function_definition(),
+            Token::Struct => self.parse_struct_definition(),
+            Token::Enum => self.parse_enum_definition(),
+            Token::Impl => self.parse_impl_block(),
+            Token::Return => self.parse_return_statement(),
+            Token::Try => self.parse_try_statement(),
+            Token::Break => {
+                let start = self.current_position();
+                self.next_token();
+                let span = self.span_from(start);
+                self.skip_semicolon();
+                Ok(AstNode::new(NodeKind::BreakStatement, span))
             },
-            Some(Token::LeftBrace) => self.parse_block_statement(),
+            Token::Continue => {
+                let start = self.current_position();
+                self.next_token();
+                let span = self.span_from(start);
+                self.skip_semicolon();
+                Ok(AstNode::new(NodeKind::ContinueStatement, span))
+            },
+            Token::LeftBrace => self.parse_block_statement(),
             _ => self.parse_expression_statement(),
         }
     }
 
-    fn parse_if_statement(&mut self) -> Result<AstNode, String> {
-        self.tokens.next(); // consume 'if'
-        
+    fn parse_if_statement(&mut self) -> Result<AstNode, LangError> {
+        let start = self.current_position();
+        self.next_token(); // consume 'if'
+
         let condition = self.parse_expression(0)?;
         let then_branch = self.parse_statement()?;
-        
-        let else_branch = if self.tokens.peek() == Some(&Token::Else) {
-            self.tokens.next(); // consume 'else'
+
+        let else_branch = if *self.peek() == Token::Else {
+            self.next_token(); // consume 'else'
             Some(Box::new(self.parse_statement()?))
         } else {
             None
         };
-        
-        Ok(AstNode::IfStatement {
+
+        Ok(AstNode::new(NodeKind::IfStatement {
             condition: Box::new(condition),
             then_branch: Box::new(then_branch),
             else_branch,
-        })
+        }, self.span_from(start)))
     }
 
-    fn parse_while_statement(&mut self) -> Result<AstNode, String> {
-        self.tokens.next(); // consume 'while'
-        
+    fn parse_while_statement(&mut self) -> Result<AstNode, LangError> {
+        let start = self.current_position();
+        self.next_token(); // consume 'while'
+
         let condition = self.parse_expression(0)?;
         let body = self.parse_statement()?;
-        
-        Ok(AstNode::WhileStatement {
+
+        Ok(AstNode::new(NodeKind::WhileStatement {
             condition: Box::new(condition),
             body: Box::new(body),
-        })
+        }, self.span_from(start)))
     }
 
-    fn parse_for_statement(&mut self) -> Result<AstNode, String> {
-        self.tokens.next(); // consume 'for'
-        
-        match self.tokens.next() {
-            Some(Token::LeftParen) => (),
-            _ => return Err("Expected '(' after 'for'".to_string()),
-        };
-        
+    fn parse_for_statement(&mut self) -> Result<AstNode, LangError> {
+        let start = self.current_position();
+        self.next_token(); // consume 'for'
+
+        // `for x in ...` and `for k, v in ...`, optionally wrapped in parentheses
+        let parenthesized = *self.peek() == Token::LeftParen;
+        let offset = if parenthesized { 1 } else { 0 };
+        if matches!(self.peek_at(offset), Token::Identifier(_))
+            && matches!(self.peek_at(offset + 1), Token::In | Token::Comma)
+        {
+            return self.parse_for_in(start, parenthesized);
+        }
+
+        self.expect(Token::LeftParen, "Expected '(' after 'for'")?;
+
         let init = self.parse_statement()?;
         let condition = self.parse_expression(0)?;
-        
-        match self.tokens.next() {
-            Some(Token::Semicolon) => (),
-            _ => return Err("Expected ';' after condition in for loop".to_string()),
-        };
-        
+
+        self.expect(Token::Semicolon, "Expected ';' after condition in for loop")?;
+
         let increment = self.parse_expression(0)?;
-        
-        match self.tokens.next() {
-            Some(Token::RightParen) => (),
-            _ => return Err("Expected ')' in for loop".to_string()),
-        };
-        
+
+        self.expect(Token::RightParen, "Expected ')' in for loop")?;
+
         let body = self.parse_statement()?;
-        
-        Ok(AstNode::ForStatement {
+
+        Ok(AstNode::new(NodeKind::ForStatement {
             init: Box::new(init),
             condition: Box::new(condition),
             increment: Box::new(increment),
             body: Box::new(body),
-        })
+        }, self.span_from(start)))
     }
 
-    fn parse_function_definition(&mut self) -> Result<AstNode, String> {
-        self.tokens.next(); // consume 'fn'
-        
-        let name = match self.tokens.next() {
-            Some(Token::Identifier(name)) => name,
-            _ => return Err("Expected function name".to_string()),
-        };
-        
-        match self.tokens.next() {
-            Some(Token::LeftParen) => (),
-            _ => return Err("Expected '(' after function name".to_string()),
-        };
-        
-        let mut parameters = Vec::new();
-        while self.tokens.peek() != Some(&Token::RightParen) {
-            match self.tokens.next() {
-                Some(Token::Identifier(param)) => parameters.push(param),
-                _ => return Err("Expected parameter name".to_string()),
+    fn parse_for_in(&mut self, start: TokenPosition, parenthesized: bool) -> Result<AstNode, LangError> {
+        if parenthesized {
+            self.next_token(); // consume '('
+        }
+
+        let mut variables = Vec::new();
+        loop {
+            variables.push(self.expect_identifier("Expected loop variable name")?);
+            if variables.len() == 2 || *self.peek() != Token::Comma {
+                break;
+            }
+            self.next_token(); // consume ','
+        }
+
+        self.expect(Token::In, "Expected 'in' after loop variables")?;
+        let iterable = self.parse_expression(0)?;
+        if parenthesized {
+            self.expect(Token::RightParen, "Expected ')' in for loop")?;
+        }
+        let body = self.parse_statement()?;
+
+        Ok(AstNode::new(NodeKind::ForInStatement {
+            variables,
+            iterable: Box::new(iterable),
+            body: Box::new(body),
+        }, self.span_from(start)))
+    }
+
+    fn parse_struct_definition(&mut self) -> Result<AstNode, LangError> {
+        let start = self.current_position();
+        self.next_token(); // consume 'struct'
+
+        let name = self.expect_identifier("Expected struct name")?;
+        self.expect(Token::LeftBrace, "Expected '{' after struct name")?;
+
+        let mut fields: Vec<String> = Vec::new();
+        while !matches!(self.peek(), Token::RightBrace | Token::Eof) {
+            let field_span = self.current_span();
+            let field = self.expect_identifier("Expected field name")?;
+            if fields.contains(&field) {
+                return Err(LangError::new(ErrorCode::UnexpectedToken, format!("Duplicate field '{}' in struct {}", field, name))
+                    .at(field_span));
+            }
+            fields.push(field);
+            if *self.peek() == Token::Comma {
+                self.next_token(); // consume comma
+            } else if *self.peek() != Token::RightBrace {
+                return self.error("Expected ',' or '}' in struct fields");
+            }
+        }
+        self.expect(Token::RightBrace, "Expected '}' to close struct")?;
+
+        Ok(AstNode::new(NodeKind::StructDefinition { name, fields }, self.span_from(start)))
+    }
+
+    fn parse_enum_definition(&mut self) -> Result<AstNode, LangError> {
+        let start = self.current_position();
+        self.next_token(); // consume 'enum'
+
+        let name = self.expect_identifier("Expected enum name")?;
+        self.expect(Token::LeftBrace, "Expected '{' after enum name")?;
+
+        let mut variants: Vec<(String, Vec<String>)> = Vec::new();
+        while !matches!(self.peek(), Token::RightBrace | Token::Eof) {
+            let variant_span = self.current_span();
+            let variant = self.expect_identifier("Expected variant name")?;
+            if variants.iter().any(|(existing, _)| *existing == variant) {
+                return Err(LangError::new(ErrorCode::UnexpectedToken, format!("Duplicate variant '{}' in enum {}", variant, name))
+                    .at(variant_span));
+            }
+            let fields = if *self.peek() == Token::LeftParen {
+                self.next_token(); // consume '('
+                self.parse_parameters()?
+            } else {
+                Vec::new()
             };
-            
-            if self.tokens.peek() == Some(&Token::Comma) {
-                self.tokens.next(); // consume comma
-            } else if self.tokens.peek() != Some(&Token::RightParen) {
-                return Err("Expected ',' or ')' in parameter list".to_string());
+            variants.push((variant, fields));
+            if *self.peek() == Token::Comma {
+                self.next_token(); // consume comma
+            } else if *self.peek() != Token::RightBrace {
+                return self.error("Expected ',' or '}' in enum variants");
             }
         }
-        
-        match self.tokens.next() {
-            Some(Token::RightParen) => (),
-            _ => return Err("Expected ')' after parameters".to_string()),
-        };
-        
+        self.expect(Token::RightBrace, "Expected '}' to close enum")?;
+
+        Ok(AstNode::new(NodeKind::EnumDefinition { name, variants }, self.span_from(start)))
+    }
+
+    fn parse_impl_block(&mut self) -> Result<AstNode, LangError> {
+        let start = self.current_position();
+        self.next_token(); // consume 'impl'
+
+        let name = self.expect_identifier("Expected struct name after 'impl'")?;
+        self.expect(Token::LeftBrace, "Expected '{' after impl name")?;
+
+        let mut methods = Vec::new();
+        while !matches!(self.peek(), Token::RightBrace | Token::Eof) {
+            if *self.peek() != Token::Fn {
+                return self.error("Expected method definition in impl block");
+            }
+            methods.push(self.parse_function_definition()?);
+        }
+        self.expect(Token::RightBrace, "Expected '}' to close impl block")?;
+
+        Ok(AstNode::new(NodeKind::ImplBlock { name, methods }, self.span_from(start)))
+    }
+
+    fn parse_function_definition(&mut self) -> Result<AstNode, LangError> {
+        let start = self.current_position();
+        self.next_token(); // consume 'fn'
+
+        let name = self.expect_identifier("Expected function name")?;
+
+        self.expect(Token::LeftParen, "Expected '(' after function name")?;
+        let parameters = self.parse_parameters()?;
         let body = self.parse_statement()?;
-        
-        Ok(AstNode::FunctionDefinition {
+
+        Ok(AstNode::new(NodeKind::FunctionDefinition {
             name,
             parameters,
             body: Box::new(body),
-        })
+        }, self.span_from(start)))
     }
 
-    fn parse_return_statement(&mut self) -> Result<AstNode, String> {
-        self.tokens.next(); // consume 'return'
-        
-        let value = if self.tokens.peek() == Some(&Token::Semicolon) || 
-                       self.tokens.peek() == Some(&Token::RightBrace) ||
-                       self.tokens.peek() == Some(&Token::Eof) ||
-                       self.tokens.peek().is_none() {
-            None
+    // Parses a parameter list after its opening '(' up to and including the ')'
+    fn parse_parameters(&mut self) -> Result<Vec<String>, LangError> {
+        let mut parameters = Vec::new();
+        while *self.peek() != Token::RightParen {
+            parameters.push(self.expect_identifier("Expected parameter name")?);
+
+            if *self.peek() == Token::Comma {
+                self.next_token(); // consume comma
+            } else if *self.peek() != Token::RightParen {
+                return self.error("Expected ',' or ')' in parameter list");
+            }
+        }
+
+        self.expect(Token::RightParen, "Expected ')' after parameters")?;
+        Ok(parameters)
+    }
+
+    // Parses call arguments after the opening '(' up to and including the ')'
+    fn parse_arguments(&mut self) -> Result<Vec<AstNode>, LangError> {
+        let mut arguments = Vec::new();
+
+        while !matches!(self.peek(), Token::RightParen | Token::Eof) {
+            arguments.push(self.parse_expression(0)?);
+            if *self.peek() == Token::Comma {
+                self.next_token(); // consume comma
+            } else if *self.peek() != Token::RightParen {
+                return self.error("Expected ',' or ')' in function call");
+            }
+        }
+
+        self.expect(Token::RightParen, "Expected ')' after arguments")?;
+        Ok(arguments)
+    }
+
+    fn parse_try_statement(&mut self) -> Result<AstNode, LangError> {
+        let start = self.current_position();
+        self.next_token(); // consume 'try'
+
+        if *self.peek() != Token::LeftBrace {
+            return self.error("Expected '{' after 'try'");
+        }
+        let body = self.parse_block_statement()?;
+
+        let (mut catch_variable, mut catch_body) = (None, None);
+        if *self.peek() == Token::Catch {
+            self.next_token(); // consume 'catch'
+            if *self.peek() == Token::LeftParen {
+                self.next_token(); // consume '('
+                catch_variable = Some(self.expect_identifier("Expected error variable name in catch")?);
+                self.expect(Token::RightParen, "Expected ')' after catch variable")?;
+            }
+            if *self.peek() != Token::LeftBrace {
+                return self.error("Expected '{' after 'catch'");
+            }
+            catch_body = Some(Box::new(self.parse_block_statement()?));
+        }
+
+        let finally_body = if *self.peek() == Token::Finally {
+            self.next_token(); // consume 'finally'
+            if *self.peek() != Token::LeftBrace {
+                return self.error("Expected '{' after 'finally'");
+            }
+            Some(Box::new(self.parse_block_statement()?))
         } else {
-            Some(Box::new(self.parse_expression(0)?))
+            None
         };
-        
-        if self.tokens.peek() == Some(&Token::Semicolon) {
-            self.tokens.next();
+
+        if catch_body.is_none() && finally_body.is_none() {
+            return self.error("Expected 'catch' or 'finally' after try block");
         }
-        
-        Ok(AstNode::ReturnStatement { value })
+
+        Ok(AstNode::new(NodeKind::TryStatement {
+            body: Box::new(body),
+            catch_variable,
+            catch_body,
+            finally_body,
+        }, self.span_from(start)))
     }
 
-    fn parse_let_statement(&mut self) -> Result<AstNode, String> {
-        self.tokens.next(); 
-        let name = match self.tokens.next() {
-            Some(Token::Identifier(name)) => name,
-            _ => return Err("Expected identifier after 'let'".to_string()),
-        };
-        match self.tokens.next() {
-            Some(Token::Assign) => (),
-            _ => return Err("Expected '=' after identifier".to_string()),
+    fn parse_return_statement(&mut self) -> Result<AstNode, LangError> {
+        let start = self.current_position();
+        self.next_token(); // consume 'return'
+
+        let value = if matches!(self.peek(), Token::Semicolon | Token::RightBrace | Token::Eof) {
+            None
+        } else {
+            Some(Box::new(self.parse_expression(0)?))
         };
+
+        let span = self.span_from(start);
+        self.skip_semicolon();
+
+        Ok(AstNode::new(NodeKind::ReturnStatement { value }, span))
+    }
+
+    fn parse_let_statement(&mut self) -> Result<AstNode, LangError> {
+        let start = self.current_position();
+        self.next_token();
+        let name = self.expect_identifier("Expected identifier after 'let'")?;
+        self.expect(Token::Assign, "Expected '=' after identifier")?;
         let value = self.parse_expression(0)?;
-        if self.tokens.peek() == Some(&Token::Semicolon) {
-            self.tokens.next();
-        }
-        Ok(AstNode::LetStatement { name, value: Box::new(value) })
+        let span = self.span_from(start);
+        self.skip_semicolon();
+        Ok(AstNode::new(NodeKind::LetStatement { name, value: Box::new(value) }, span))
+    }
+
+    fn parse_block_statement(&mut self) -> Result<AstNode, LangError> {
+        let start = self.current_position();
+        self.next_token(); // consume '{'
+        self.parse_block_body(start)
     }
-    
-    fn parse_block_statement(&mut self) -> Result<AstNode, String> {
-        self.tokens.next(); // consume '{'
+
+    // Parses statements up to and including the closing '}' of a block whose '{' is already consumed
+    fn parse_block_body(&mut self, start: TokenPosition) -> Result<AstNode, LangError> {
         let mut statements = Vec::new();
-        
-        while self.tokens.peek().is_some() && 
-              self.tokens.peek() != Some(&Token::RightBrace) &&
-              self.tokens.peek() != Some(&Token::Eof) {
-            statements.push(self.parse_statement()?);
-        }
-        
-        match self.tokens.next() {
-            Some(Token::RightBrace) => Ok(AstNode::BlockStatement(statements)),
-            _ => Err("Expected '}' to close block".to_string()),
+
+        while !matches!(self.peek(), Token::RightBrace | Token::Eof) {
+            if let Some(statement) = self.parse_statement_or_recover() {
+                statements.push(statement);
+            }
         }
+
+        self.expect(Token::RightBrace, "Expected '}' to close block")?;
+        Ok(AstNode::new(NodeKind::BlockStatement(statements), self.span_from(start)))
     }
 
-    fn parse_expression_statement(&mut self) -> Result<AstNode, String> {
+    fn parse_expression_statement(&mut self) -> Result<AstNode, LangError> {
         let expr = self.parse_expression(0)?;
-        if self.tokens.peek() == Some(&Token::Semicolon) {
-            self.tokens.next();
-        }
+        self.skip_semicolon();
         Ok(expr)
     }
 
-    fn parse_expression(&mut self, min_precedence: u8) -> Result<AstNode, String> {
+    fn parse_expression(&mut self, min_precedence: u8) -> Result<AstNode, LangError> {
         let mut left = self.parse_prefix()?;
-        
-        loop {
-            let op = match self.tokens.peek() {
-                Some(token) => token.clone(),
-                None => break,
-            };
 
+        loop {
             // Stop parsing if we hit certain tokens
-            if matches!(op, Token::RightBrace | Token::RightParen | Token::RightBracket | 
+            if matches!(self.peek(), Token::RightBrace | Token::RightParen | Token::RightBracket |
                            Token::Semicolon | Token::Comma | Token::Eof) {
                 break;
             }
 
-            let precedence = self.get_infix_precedence(&op);
-            if precedence < min_precedence {
+            // Precedence 0 means the token is not an infix operator at all
+            let precedence = self.get_infix_precedence(self.peek());
+            if precedence == 0 || precedence < min_precedence {
                 break;
             }
+            // Operators carry no data, so this clone is cheap
+            let op = self.peek().clone();
+
+            // Assignment is right-associative: `a = b = c` assigns to b first
+            if let Some(compound_op) = Self::assignment_operator(&op) {
+                if !matches!(left.kind, NodeKind::Identifier(_) | NodeKind::ArrayAccess { .. } | NodeKind::FieldAccess { .. }) {
+                    return Err(LangError::new(ErrorCode::InvalidAssignmentTarget, "Invalid assignment target")
+                        .at(left.span));
+                }
+                self.next_token(); // consume assignment operator
+                let value = self.parse_operand(precedence)?;
+                let span = left.span.to(value.span);
+                left = AstNode::new(NodeKind::Assignment {
+                    target: Box::new(left),
+                    op: compound_op,
+                    value: Box::new(value),
+                }, span);
+                continue;
+            }
+
+            // Calls are postfix, so any expression producing a function can be called
+            if op == Token::LeftParen {
+                self.next_token(); // consume '('
+                let arguments = self.parse_arguments()?;
+                let span = self.span_from(left.span.start);
+                left = AstNode::new(NodeKind::FunctionCall {
+                    function: Box::new(left),
+                    arguments,
+                }, span);
+                continue;
+            }
 
             // Handle array access
-            if op == Token::LeftBracket {
-                self.tokens.next(); // consume '['
-                let index = self.parse_expression(0)?;
-                match self.tokens.next() {
-                    Some(Token::RightBracket) => (),
-                    _ => return Err("Expected ']'".to_string()),
+            if matches!(op, Token::LeftBracket | Token::SafeLeftBracket) {
+                self.next_token(); // consume '[' or '?['
+                let first = self.parse_slice_bound()?;
+                if *self.peek() == Token::Colon {
+                    if op == Token::SafeLeftBracket {
+                        return self.error("Slices cannot be used with '?['");
+                    }
+                    self.next_token(); // consume ':'
+                    let end = self.parse_slice_bound()?;
+                    let step = if *self.peek() == Token::Colon {
+                        self.next_token(); // consume second ':'
+                        self.parse_slice_bound()?
+                    } else {
+                        None
+                    };
+                    self.expect(Token::RightBracket, "Expected ']' after slice")?;
+                    let span = self.span_from(left.span.start);
+                    left = AstNode::new(NodeKind::Slice { array: Box::new(left), start: first, end, step }, span);
+                    continue;
+                }
+                let Some(index) = first else {
+                    return self.error("Expected index expression");
                 };
-                left = AstNode::ArrayAccess { 
-                    array: Box::new(left), 
-                    index: Box::new(index) 
+                self.expect(Token::RightBracket, "Expected ']'")?;
+                let span = self.span_from(left.span.start);
+                let array = Box::new(left);
+                left = match op {
+                    Token::SafeLeftBracket => AstNode::new(NodeKind::SafeArrayAccess { array, index }, span),
+                    _ => AstNode::new(NodeKind::ArrayAccess { array, index }, span),
                 };
                 continue;
             }
 
-            let op_token = self.tokens.next().unwrap();
-            let right = self.parse_expression(precedence + 1)?;
-            left = AstNode::InfixExpression { op: op_token, left: Box::new(left), right: Box::new(right) };
+            if op == Token::Dot {
+                self.next_token(); // consume '.'
+                let field = self.expect_identifier("Expected field or method name after '.'")?;
+                let span = self.span_from(left.span.start);
+                left = AstNode::new(NodeKind::FieldAccess { object: Box::new(left), field }, span);
+                continue;
+            }
+
+            // `cond ? a : b` nests to the right: `a ? b : c ? d : e`
+            if op == Token::Question {
+                self.next_token(); // consume '?'
+                let consequence = self.parse_operand(0)?;
+                self.expect(Token::Colon, "Expected ':' in conditional expression")?;
+                let alternative = self.parse_operand(precedence)?;
+                let span = left.span.to(alternative.span);
+                left = AstNode::new(NodeKind::Conditional {
+                    condition: Box::new(left),
+                    consequence: Box::new(consequence),
+                    alternative: Box::new(alternative),
+                }, span);
+                continue;
+            }
+
+            // Ranges do not chain: `a..b..c` is an error rather than a range of ranges
+            if matches!(op, Token::DotDot | Token::DotDotEqual) {
+                self.next_token(); // consume '..' or '..='
+                let end = self.parse_operand(precedence + 1)?;
+                if matches!(self.peek(), Token::DotDot | Token::DotDotEqual) {
+                    return self.error("Ranges cannot be chained");
+                }
+                let span = left.span.to(end.span);
+                left = AstNode::new(NodeKind::Range {
+                    start: Box::new(left),
+                    end: Box::new(end),
+                    inclusive: op == Token::DotDotEqual,
+                }, span);
+                continue;
+            }
+
+            // `**` is right-associative, every other binary operator is left-associative
+            let op_token = self.next_token();
+            let right_precedence = if op_token == Token::Power { precedence } else { precedence + 1 };
+            let right = self.parse_operand(right_precedence)?;
+            let span = left.span.to(right.span);
+            let (left_box, right_box) = (Box::new(left), Box::new(right));
+            left = match op_token {
+                Token::And | Token::Or | Token::NullCoalesce => AstNode::new(NodeKind::LogicalExpression { op: op_token, left: left_box, right: right_box }, span),
+                _ => AstNode::new(NodeKind::InfixExpression { op: op_token, left: left_box, right: right_box }, span),
+            };
         }
         Ok(left)
     }
 
-    fn parse_prefix(&mut self) -> Result<AstNode, String> {
-        let token = match self.tokens.next() {
-            Some(t) => t,
-            None => return Err("Unexpected end of input while parsing prefix".to_string()),
+    // Operands after an operator count as one level deeper, so right-associative
+    // chains like `a = b = c` or `2 ** 2 ** 2` are bounded like any other nesting
+    fn parse_operand(&mut self, min_precedence: u8) -> Result<AstNode, LangError> {
+        self.nested(|parser| parser.parse_expression(min_precedence))
+    }
+
+    fn parse_prefix(&mut self) -> Result<AstNode, LangError> {
+        self.nested(Self::parse_prefix_inner)
+    }
+
+    fn parse_prefix_inner(&mut self) -> Result<AstNode, LangError> {
+        let start = self.current_position();
+        let token = match self.peek() {
+            Token::Eof => return self.error("Unexpected end of input while parsing prefix"),
+            _ => self.next_token(),
         };
 
         match token {
-            Token::Number(n) => Ok(AstNode::Number(n)),
-            Token::Boolean(b) => Ok(AstNode::Boolean(b)),
-            Token::True => Ok(AstNode::Boolean(true)),
-            Token::False => Ok(AstNode::Boolean(false)),
-            Token::String(s) => Ok(AstNode::String(s)),
-            Token::Identifier(name) => {
-                // Check for function call
-                if self.tokens.peek() == Some(&Token::LeftParen) {
-                    self.tokens.next(); // consume '('
-                    let mut arguments = Vec::new();
-                    
-                    while self.tokens.peek() != Some(&Token::RightParen) && 
-                          self.tokens.peek() != Some(&Token::Eof) &&
-                          self.tokens.peek().is_some() {
-                        arguments.push(self.parse_expression(0)?);
-                        if self.tokens.peek() == Some(&Token::Comma) {
-                            self.tokens.next(); // consume comma
-                        } else if self.tokens.peek() != Some(&Token::RightParen) {
-                            return Err("Expected ',' or ')' in function call".to_string());
-                        }
-                    }
-                    
-                    match self.tokens.next() {
-                        Some(Token::RightParen) => (),
-                        _ => return Err("Expected ')' after arguments".to_string()),
-                    };
-                    
-                    Ok(AstNode::FunctionCall { name, arguments })
-                } else {
-                    Ok(AstNode::Identifier(name))
-                }
+            Token::Integer(n) => Ok(AstNode::new(NodeKind::Integer(n), self.span_from(start))),
+            Token::Number(n) => Ok(AstNode::new(NodeKind::Number(n), self.span_from(start))),
+            Token::Boolean(b) => Ok(AstNode::new(NodeKind::Boolean(b), self.span_from(start))),
+            Token::String(s) => Ok(AstNode::new(NodeKind::String(s), self.span_from(start))),
+            Token::InterpolatedString(parts) => {
+                let parts = parts.into_iter()
+                    .map(|part| match part {
+                        StringPart::Literal(s) => Ok(TemplatePart::Literal(s)),
+                        StringPart::Placeholder(tokens) => self.parse_placeholder(tokens).map(TemplatePart::Expression),
+                    })
+                    .collect::<Result<Vec<_>, LangError>>()?;
+                Ok(AstNode::new(NodeKind::InterpolatedString(parts), self.span_from(start)))
+            }
+            Token::Identifier(name) => Ok(AstNode::new(NodeKind::Identifier(name), self.span_from(start))),
+            Token::Null => Ok(AstNode::new(NodeKind::Null, self.span_from(start))),
+            Token::Fn => {
+                self.expect(Token::LeftParen, "Expected '(' after 'fn'")?;
+                let parameters = self.parse_parameters()?;
+                let body = self.parse_statement()?;
+                Ok(AstNode::new(NodeKind::FunctionLiteral {
+                    parameters,
+                    body: Box::new(body),
+                }, self.span_from(start)))
             },
             Token::LeftBracket => {
                 let mut elements = Vec::new();
-                
-                while self.tokens.peek() != Some(&Token::RightBracket) && 
-                      self.tokens.peek() != Some(&Token::Eof) &&
-                      self.tokens.peek().is_some() {
+
+                while !matches!(self.peek(), Token::RightBracket | Token::Eof) {
                     elements.push(self.parse_expression(0)?);
-                    if self.tokens.peek() == Some(&Token::Comma) {
-                        self.tokens.next(); // consume comma
-                    } else if self.tokens.peek() != Some(&Token::RightBracket) {
-                        return Err("Expected ',' or ']' in array".to_string());
+                    if *self.peek() == Token::Comma {
+                        self.next_token(); // consume comma
+                    } else if *self.peek() != Token::RightBracket {
+                        return self.error("Expected ',' or ']' in array");
                     }
                 }
-                
-                match self.tokens.next() {
-                    Some(Token::RightBracket) => Ok(AstNode::Array(elements)),
-                    _ => return Err("Expected ']' to close array".to_string()),
-                }
+
+                self.expect(Token::RightBracket, "Expected ']' to close array")?;
+                Ok(AstNode::new(NodeKind::Array(elements), self.span_from(start)))
             },
-            op @ (Token::Minus | Token::Not) => {
-                let right = self.parse_expression(6)?;
-                Ok(AstNode::PrefixExpression { op, right: Box::new(right) })
+            // Binds tighter than binary operators except `**`, so `-2 ** 2` is -4
+            op @ (Token::Minus | Token::Not | Token::BitNot) => {
+                let right = self.parse_expression(13)?;
+                Ok(AstNode::new(NodeKind::PrefixExpression { op, right: Box::new(right) }, self.span_from(start)))
             }
             Token::LeftParen => {
                 let expr = self.parse_expression(0)?;
-                match self.tokens.next() {
-                    Some(Token::RightParen) => Ok(expr),
-                    _ => Err("Expected ')'".to_string()),
-                }
+                self.expect(Token::RightParen, "Expected ')'")?;
+                Ok(expr)
+            }
+            Token::Match => self.parse_match(start),
+            Token::Throw => {
+                let value = self.parse_expression(0)?;
+                Ok(AstNode::new(NodeKind::ThrowExpression { value: Box::new(value) }, self.span_from(start)))
+            }
+            Token::LeftBrace if self.at_map_literal() => self.parse_map_literal(start),
+            Token::LeftBrace => self.parse_block_body(start),
+            t => Err(LangError::new(ErrorCode::UnexpectedToken,
+                format!("Unexpected token for prefix expression: {:?}", t)).at(self.span_from(start))),
+        }
+    }
+
+    // `match` has already been consumed
+    fn parse_match(&mut self, start: TokenPosition) -> Result<AstNode, LangError> {
+        let subject = self.parse_expression(0)?;
+        self.expect(Token::LeftBrace, "Expected '{' after match value")?;
+
+        let mut arms = Vec::new();
+        while !matches!(self.peek(), Token::RightBrace | Token::Eof) {
+            let pattern = self.parse_pattern()?;
+            let guard = if *self.peek() == Token::If {
+                self.next_token(); // consume 'if'
+                Some(self.parse_expression(0)?)
+            } else {
+                None
+            };
+            self.expect(Token::FatArrow, "Expected '=>' after match pattern")?;
+            let body = self.parse_expression(0)?;
+
+            // The comma is optional after a block body
+            let is_block = matches!(body.kind, NodeKind::BlockStatement(_));
+            arms.push(MatchArm { pattern, guard, body });
+            if *self.peek() == Token::Comma {
+                self.next_token(); // consume comma
+            } else if !is_block && *self.peek() != Token::RightBrace {
+                return self.error("Expected ',' or '}' after match arm");
+            }
+        }
+        self.expect(Token::RightBrace, "Expected '}' to close match")?;
+
+        Ok(AstNode::new(NodeKind::Match { subject: Box::new(subject), arms }, self.span_from(start)))
+    }
+
+    fn parse_pattern(&mut self) -> Result<Pattern, LangError> {
+        let start = self.current_position();
+        match self.peek() {
+            Token::Identifier(name) if name == "_" => {
+                self.next_token();
+                Ok(Pattern::Wildcard)
             }
-            Token::LeftBrace => {
-                // Put the token back and parse as block statement
-                // This is a bit hacky but works for this simple parser
-                let mut statements = Vec::new();
-                
-                while self.tokens.peek().is_some() && 
-                      self.tokens.peek() != Some(&Token::RightBrace) &&
-                      self.tokens.peek() != Some(&Token::Eof) {
-                    statements.push(self.parse_statement()?);
+            Token::Identifier(_) if *self.peek_next() == Token::Dot => {
+                let enum_name = self.expect_identifier("Expected enum name")?;
+                self.next_token(); // consume '.'
+                let variant = self.expect_identifier("Expected variant name after '.'")?;
+                let mut fields = Vec::new();
+                if *self.peek() == Token::LeftParen {
+                    self.next_token(); // consume '('
+                    while !matches!(self.peek(), Token::RightParen | Token::Eof) {
+                        fields.push(self.parse_pattern()?);
+                        if *self.peek() == Token::Comma {
+                            self.next_token(); // consume comma
+                        } else if *self.peek() != Token::RightParen {
+                            return self.error("Expected ',' or ')' in variant pattern");
+                        }
+                    }
+                    self.expect(Token::RightParen, "Expected ')' after variant pattern")?;
                 }
-                
-                match self.tokens.next() {
-                    Some(Token::RightBrace) => Ok(AstNode::BlockStatement(statements)),
-                    _ => Err("Expected '}' to close block".to_string()),
+                Ok(Pattern::Variant { enum_name, variant, fields })
+            }
+            Token::Identifier(_) => Ok(Pattern::Binding(self.expect_identifier("Expected pattern")?)),
+            Token::LeftBracket => {
+                self.next_token(); // consume '['
+                let (mut prefix, mut suffix, mut rest) = (Vec::new(), Vec::new(), None);
+                while !matches!(self.peek(), Token::RightBracket | Token::Eof) {
+                    if *self.peek() == Token::DotDot {
+                        if rest.is_some() {
+                            return self.error("Only one '..' is allowed in an array pattern");
+                        }
+                        self.next_token(); // consume '..'
+                        rest = Some(Box::new(match self.peek() {
+                            Token::Identifier(name) if name != "_" => {
+                                Pattern::Binding(self.expect_identifier("Expected rest pattern name")?)
+                            }
+                            Token::Identifier(_) => {
+                                self.next_token();
+                                Pattern::Wildcard
+                            }
+                            _ => Pattern::Wildcard,
+                        }));
+                    } else if rest.is_some() {
+                        suffix.push(self.parse_pattern()?);
+                    } else {
+                        prefix.push(self.parse_pattern()?);
+                    }
+                    if *self.peek() == Token::Comma {
+                        self.next_token(); // consume comma
+                    } else if *self.peek() != Token::RightBracket {
+                        return self.error("Expected ',' or ']' in array pattern");
+                    }
                 }
+                self.expect(Token::RightBracket, "Expected ']' to close array pattern")?;
+                Ok(Pattern::Array { prefix, rest, suffix })
+            }
+            Token::Integer(_) | Token::Number(_) | Token::String(_) | Token::Boolean(_) | Token::Null => {
+                Ok(Pattern::Literal(self.parse_prefix()?))
             }
-            t => Err(format!("Unexpected token for prefix expression: {:?}", t)),
+            // Negative number literal
+            Token::Minus if matches!(self.peek_next(), Token::Integer(_) | Token::Number(_)) => {
+                self.next_token(); // consume '-'
+                let kind = match self.next_token() {
+                    Token::Integer(n) => NodeKind::Integer(-n),
+                    Token::Number(n) => NodeKind::Number(-n),
+                    _ => unreachable!(),
+                };
+                Ok(Pattern::Literal(AstNode::new(kind, self.span_from(start))))
+            }
+            _ => self.error("Expected pattern"),
+        }
+    }
+
+    // A missing slice part leaves the bound to its default
+    fn parse_slice_bound(&mut self) -> Result<Option<Box<AstNode>>, LangError> {
+        match self.peek() {
+            Token::Colon | Token::RightBracket => Ok(None),
+            _ => Ok(Some(Box::new(self.parse_expression(0)?))),
+        }
+    }
+
+    // Placeholder tokens keep their source positions, so errors inside `${...}`
+    // point into the string. The placeholder counts as nested in the string.
+    fn parse_placeholder(&self, tokens: Vec<TokenWithPosition>) -> Result<AstNode, LangError> {
+        let mut parser = Parser { depth: self.depth, ..Parser::new(tokens) };
+        let expr = parser.parse_expression(0)?;
+        if *parser.peek() != Token::Eof {
+            return parser.error("Expected '}' to close placeholder");
+        }
+        Ok(expr)
+    }
+
+    // In expression position `{` opens a map when it is empty or starts with
+    // `literal :`; anything else is a block
+    fn at_map_literal(&self) -> bool {
+        match self.peek() {
+            Token::RightBrace => true,
+            Token::String(_) | Token::InterpolatedString(_) | Token::Integer(_) | Token::Number(_) | Token::Boolean(_) => {
+                *self.peek_next() == Token::Colon
+            }
+            _ => false,
+        }
+    }
+
+    // Parses `key: value` pairs after the opening '{'
+    fn parse_map_literal(&mut self, start: TokenPosition) -> Result<AstNode, LangError> {
+        let mut entries = Vec::new();
+
+        while !matches!(self.peek(), Token::RightBrace | Token::Eof) {
+            let key = self.parse_expression(0)?;
+            self.expect(Token::Colon, "Expected ':' after map key")?;
+            let value = self.parse_expression(0)?;
+            entries.push((key, value));
+            if *self.peek() == Token::Comma {
+                self.next_token(); // consume comma
+            } else if *self.peek() != Token::RightBrace {
+                return self.error("Expected ',' or '}' in map");
+            }
+        }
+
+        self.expect(Token::RightBrace, "Expected '}' to close map")?;
+        Ok(AstNode::new(NodeKind::MapLiteral(entries), self.span_from(start)))
+    }
+
+    // Maps an assignment token to the arithmetic operator it applies, if any
+    fn assignment_operator(token: &Token) -> Option<Option<Token>> {
+        match token {
+            Token::Assign => Some(None),
+            Token::PlusAssign => Some(Some(Token::Plus)),
+            Token::MinusAssign => Some(Some(Token::Minus)),
+            Token::MultiplyAssign => Some(Some(Token::Multiply)),
+            Token::DivideAssign => Some(Some(Token::Divide)),
+            Token::ModuloAssign => Some(Some(Token::Modulo)),
+            _ => None,
         }
     }
 
     fn get_infix_precedence(&self, token: &Token) -> u8 {
         match token {
-            Token::Or => 1,
-            Token::And => 2,
-            Token::Equal | Token::NotEqual | Token::LessThan | Token::GreaterThan | Token::LessThanOrEqual | Token::GreaterThanOrEqual => 3,
-            Token::Plus | Token::Minus => 4,
-            Token::Multiply | Token::Divide | Token::Modulo => 5,
-            Token::LeftBracket => 7,
+            Token::Assign | Token::PlusAssign | Token::MinusAssign |
+            Token::MultiplyAssign | Token::DivideAssign | Token::ModuloAssign => 1,
+            Token::Question => 2,
+            Token::Or | Token::NullCoalesce => 3,
+            Token::And => 4,
+            Token::Equal | Token::NotEqual | Token::LessThan | Token::GreaterThan | Token::LessThanOrEqual | Token::GreaterThanOrEqual => 5,
+            Token::DotDot | Token::DotDotEqual => 6,
+            // Bitwise operators bind tighter than comparisons, so `x & 1 == 0` needs no parentheses
+            Token::BitOr => 7,
+            Token::BitXor => 8,
+            Token::BitAnd => 9,
+            Token::ShiftLeft | Token::ShiftRight => 10,
+            Token::Plus | Token::Minus => 11,
+            Token::Multiply | Token::Divide | Token::Modulo => 12,
+            Token::Power => 14,
+            Token::LeftBracket | Token::SafeLeftBracket | Token::LeftParen | Token::Dot => 15,
             _ => 0,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+
+    fn parse(source: &str) -> Result<AstNode, LangError> {
+        let (program, errors) = Parser::new(tokenize(source)?).parse_program();
+        match errors.into_iter().next() {
+            Some(err) => Err(err),
+            None => Ok(program),
+        }
+    }
+
+    fn position(line: usize, column: usize) -> TokenPosition {
+        TokenPosition { line, column }
+    }
+
+    #[test]
+    fn nodes_span_their_source_text() {
+        let program = parse("let a = 1;\nlet b = a +\n  2;").unwrap();
+        let NodeKind::Program(statements) = &program.kind else { panic!("expected a program") };
+        assert_eq!(statements[1].span, Span::new(position(2, 1), position(3, 4)));
+        let NodeKind::LetStatement { value, .. } = &statements[1].kind else { panic!("expected let") };
+        assert_eq!(value.span, Span::new(position(2, 9), position(3, 4)));
+    }
+
+    #[test]
+    fn errors_report_line_and_column() {
+        let err = parse("let a = 1;\nlet b = (a + 2;").unwrap_err().to_string();
+        assert_eq!(err, "Expected ')' at line 2, column 15");
+    }
+
+    #[test]
+    fn every_syntax_error_is_reported_in_one_pass() {
+        let source = "let a = ;\nlet b = 2;\nfn f( { return 1; }\nlet c = (1 + ;\nlet d = 4;";
+        let (program, errors) = Parser::new(tokenize(source).unwrap()).parse_program();
+        let lines: Vec<usize> = errors.iter().map(|e| e.span.unwrap().start.line).collect();
+        assert_eq!(lines, vec![1, 3, 4]);
+
+        // Statements around the broken ones still make it into the program
+        let NodeKind::Program(statements) = &program.kind else { panic!("expected a program") };
+        let names: Vec<&str> = statements
+            .iter()
+            .filter_map(|s| match &s.kind {
+                NodeKind::LetStatement { name, .. } => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names, vec!["b", "d"]);
+    }
+
+    // Deep programs are parsed on the interpreter's thread in the server; unoptimized
+    // builds need more than a test thread's default stack to reach the limit
+    fn on_large_stack(test: impl FnOnce() + Send + 'static) {
+        std::thread::Builder::new()
+            .stack_size(64 * 1024 * 1024)
+            .spawn(test)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn nesting_beyond_the_limit_is_rejected() {
+        on_large_stack(|| {
+            let deep = MAX_NESTING + 1;
+            let sources = [
+                format!("{}1{}", "(".repeat(deep), ")".repeat(deep)),
+                format!("let x = 0;\n{}1;", "x = ".repeat(deep)),
+                format!("2{}", " ** 2".repeat(deep)),
+                format!("{}1", "false ? 0 : ".repeat(deep)),
+                format!("{}1{}", "true ? ".repeat(deep), " : 0".repeat(deep)),
+                format!("{}1;{}", "if (true) { ".repeat(deep), " }".repeat(deep)),
+            ];
+            for source in sources {
+                let err = parse(&source).unwrap_err();
+                assert_eq!(err.code, ErrorCode::StackOverflow, "{}", &source[..40]);
+                assert_eq!(err.message, format!("Program is nested too deeply (more than {} levels)", MAX_NESTING));
+            }
+
+            // Just under the limit still parses
+            assert!(parse(&format!("let x = 0;\n{}1;", "x = ".repeat(MAX_NESTING - 10))).is_ok());
+        });
+    }
+}
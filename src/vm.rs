@@ -0,0 +1,1032 @@
+use super::compiler::{CompiledPattern, HandlerKind, Op, Path, Program, Proto, Root, Step, TypeDef, Var};
+use super::environment::record_resize;
+use super::error::{ErrorCode, LangError};
+use super::evaluator::{
+    begin_run, caught_value, charge_step, element, element_mut, evaluate_infix, evaluate_prefix, field_mut,
+    index_value, instantiate, make_variant, objects_equal, program_result, range_array, read_field,
+    resolve_method, slice_value, store, type_label, Depth, DepthGuard, Interpreter, Limits, MethodTarget,
+};
+use super::lexer::Span;
+use super::object::{get_builtins, EnumType, HashKey, Object, StructType};
+use num_bigint::BigInt;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+// Variable shared between the scope that declares it and the closures that capture
+// it. Empty until the declaration runs.
+#[derive(Default)]
+pub struct Captured(RefCell<Option<Object>>);
+
+impl Drop for Captured {
+    fn drop(&mut self) {
+        if let Some(value) = self.0.get_mut() {
+            record_resize(value.heap_size(), 0);
+        }
+    }
+}
+
+pub struct Closure {
+    pub proto: Rc<Proto>,
+    pub captures: Vec<Rc<Captured>>,
+}
+
+// Captured cells can hold the closure itself, so they are left out
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Closure({})", self.proto.parameters.join(", "))
+    }
+}
+
+// Method call that passed a variable, element or field as `self`, to update with
+// the new `self` when the method returns
+struct WriteBack {
+    path: u32,
+    keys: Vec<Object>,
+    receiver: Object,
+}
+
+// Struct or enum type given methods by an `impl`. The methods capture cells of the
+// frame that ran it, and a cell may hold the type again, so the table closes a cycle.
+#[derive(Clone)]
+enum Implemented {
+    Struct(Weak<StructType>),
+    Enum(Weak<EnumType>),
+}
+
+impl Implemented {
+    fn strong_count(&self) -> usize {
+        match self {
+            Implemented::Struct(t) => t.strong_count(),
+            Implemented::Enum(t) => t.strong_count(),
+        }
+    }
+
+    fn with_methods<R>(&self, f: impl FnOnce(&RefCell<HashMap<String, Object>>) -> R) -> Option<R> {
+        match self {
+            Implemented::Struct(t) => t.upgrade().map(|t| f(&t.methods)),
+            Implemented::Enum(t) => t.upgrade().map(|t| f(&t.methods)),
+        }
+    }
+
+    // Handles to this type held by `value`, looking inside containers
+    fn held_by(&self, value: &Object) -> usize {
+        let is_struct = |struct_type: &Rc<StructType>| matches!(self, Implemented::Struct(t) if t.as_ptr() == Rc::as_ptr(struct_type));
+        let is_enum = |enum_type: &Rc<EnumType>| matches!(self, Implemented::Enum(t) if t.as_ptr() == Rc::as_ptr(enum_type));
+        match value {
+            Object::Struct(struct_type) => usize::from(is_struct(struct_type)),
+            Object::Enum(enum_type) => usize::from(is_enum(enum_type)),
+            Object::Instance { struct_type, fields } => {
+                usize::from(is_struct(struct_type)) + fields.iter().map(|item| self.held_by(item)).sum::<usize>()
+            }
+            Object::Variant { enum_type, values, .. } => {
+                usize::from(is_enum(enum_type)) + values.iter().map(|item| self.held_by(item)).sum::<usize>()
+            }
+            Object::Array(items) => items.iter().map(|item| self.held_by(item)).sum(),
+            Object::Map(entries) => entries.values().map(|item| self.held_by(item)).sum(),
+            _ => 0,
+        }
+    }
+
+    fn clear_methods(&self) {
+        self.with_methods(|methods| methods.take());
+    }
+}
+
+struct Frame {
+    proto: Rc<Proto>,
+    closure: Option<Rc<Closure>>,
+    ip: usize,
+    // Stack index of the first slot
+    base: usize,
+    // Stack length to go back to once the call returns
+    return_height: usize,
+    cells: Vec<Rc<Captured>>,
+    call_span: Span,
+    write_back: Option<Box<WriteBack>>,
+    // Types given methods while this frame ran
+    implemented: Vec<Implemented>,
+    _depth: Option<DepthGuard>,
+}
+
+struct Handler {
+    kind: HandlerKind,
+    frame: usize,
+    height: usize,
+    pending: usize,
+    target: usize,
+}
+
+// Stack machine running compiled programs. Each frame's slots sit on the value
+// stack below its temporaries.
+pub struct Vm {
+    stack: Vec<Object>,
+    frames: Vec<Frame>,
+    globals: Vec<Option<Object>>,
+    global_names: Vec<String>,
+    handlers: Vec<Handler>,
+    // Errors waiting for their `finally` blocks to finish
+    pending: Vec<LangError>,
+    // Every type given methods during the run, whose tables are cleared at the end
+    implemented: Vec<Implemented>,
+}
+
+pub fn run(program: &Program, limits: Limits) -> Result<Object, LangError> {
+    begin_run(limits);
+    let builtins = get_builtins();
+    let mut vm = Vm {
+        stack: Vec::new(),
+        frames: Vec::new(),
+        globals: program.globals.iter().map(|name| builtins.get(name).cloned()).collect(),
+        global_names: program.globals.clone(),
+        handlers: Vec::new(),
+        pending: Vec::new(),
+        implemented: Vec::new(),
+    };
+    vm.push_frame(program.main.clone(), None, 0, Span::default(), None, None);
+    vm.execute(0)
+}
+
+impl Drop for Vm {
+    fn drop(&mut self) {
+        while !self.frames.is_empty() {
+            self.pop_frame();
+        }
+        // Types that outlived the frame of their `impl` may still be in a cycle
+        for implemented in self.implemented.drain(..) {
+            implemented.clear_methods();
+        }
+        record_resize(self.globals.iter().flatten().map(Object::heap_size).sum(), 0);
+    }
+}
+
+// Replaces a variable held in a cell or global, keeping the count of bound memory
+fn set(slot: &mut Option<Object>, value: Object) {
+    record_resize(slot.as_ref().map_or(0, Object::heap_size), value.heap_size());
+    *slot = Some(value);
+}
+
+fn runtime_error(code: ErrorCode, message: impl Into<String>, span: Span) -> LangError {
+    LangError::new(code, message).at(span)
+}
+
+impl Vm {
+    fn frame(&self) -> &Frame {
+        self.frames.last().expect("a frame is running")
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("a frame is running")
+    }
+
+    fn pop(&mut self) -> Object {
+        self.stack.pop().expect("a value on the stack")
+    }
+
+    fn top(&self) -> &Object {
+        self.stack.last().expect("a value on the stack")
+    }
+
+    fn temp_base(&self) -> usize {
+        let frame = self.frame();
+        frame.base + frame.proto.slot_count
+    }
+
+    fn push_frame(
+        &mut self,
+        proto: Rc<Proto>,
+        closure: Option<Rc<Closure>>,
+        return_height: usize,
+        call_span: Span,
+        write_back: Option<Box<WriteBack>>,
+        depth: Option<DepthGuard>,
+    ) {
+        let base = self.stack.len() - proto.parameters.len();
+        record_resize(0, self.stack[base..].iter().map(Object::heap_size).sum());
+        self.stack.resize(base + proto.slot_count, Object::Null);
+        let cells = (0..proto.cell_count).map(|_| Rc::default()).collect();
+        self.frames.push(Frame {
+            proto,
+            closure,
+            ip: 0,
+            base,
+            return_height,
+            cells,
+            call_span,
+            write_back,
+            implemented: Vec::new(),
+            _depth: depth,
+        });
+    }
+
+    fn pop_frame(&mut self) -> Frame {
+        let frame = self.frames.pop().expect("a frame is running");
+        self.release_implemented(&frame);
+        let slots = &self.stack[frame.base..frame.base + frame.proto.slot_count];
+        record_resize(slots.iter().map(Object::heap_size).sum(), 0);
+        self.stack.truncate(frame.return_height);
+        frame
+    }
+
+    // Clears the methods of types implemented in `frame` once nothing but the frame
+    // and those methods can reach them, to break the cycle through its cells. Types
+    // that are still reachable keep their methods until the end of the run.
+    fn release_implemented(&self, frame: &Frame) {
+        if frame.implemented.is_empty() {
+            return;
+        }
+        // Cells the methods capture, once per capture; a method held elsewhere escapes
+        let mut captured: Vec<*const Captured> = Vec::new();
+        for implemented in &frame.implemented {
+            let escaped = implemented.with_methods(|methods| {
+                methods.borrow().values().any(|method| match method {
+                    Object::Closure(closure) if Rc::strong_count(closure) == 1 => {
+                        captured.extend(closure.captures.iter().map(Rc::as_ptr));
+                        false
+                    }
+                    Object::Closure(_) => true,
+                    _ => false,
+                })
+            });
+            if escaped == Some(true) {
+                return;
+            }
+        }
+        // Cells only the frame and the methods hold, and the values going with the frame
+        let internal: Vec<_> = frame.cells.iter()
+            .filter(|cell| Rc::strong_count(cell) == 1 + captured.iter().filter(|&&ptr| ptr == Rc::as_ptr(cell)).count())
+            .map(|cell| cell.0.borrow())
+            .collect();
+        let reachable = frame.implemented.iter().any(|implemented| {
+            let held: usize = self.stack[frame.return_height..].iter()
+                .chain(internal.iter().filter_map(|value| value.as_ref()))
+                .map(|value| implemented.held_by(value))
+                .sum();
+            implemented.strong_count() > held
+        });
+        drop(internal);
+        if !reachable {
+            for implemented in &frame.implemented {
+                implemented.clear_methods();
+            }
+        }
+    }
+
+    // Runs until the frame at `base_frame` returns
+    fn execute(&mut self, base_frame: usize) -> Result<Object, LangError> {
+        loop {
+            let frame = self.frame_mut();
+            let op = frame.proto.chunk.code[frame.ip];
+            let span = frame.proto.chunk.spans[frame.ip];
+            frame.ip += 1;
+            match charge_step(span).and_then(|()| self.step(op, span, base_frame)) {
+                Ok(None) => {}
+                Ok(Some(value)) => return Ok(value),
+                Err(err) => self.fail(err, base_frame)?,
+            }
+        }
+    }
+
+    // Hands a catchable error to the innermost handler of this run, or unwinds
+    // every frame down to `base_frame` and returns it
+    fn fail(&mut self, err: LangError, base_frame: usize) -> Result<(), LangError> {
+        let handled = err.is_catchable() && self.handlers.last().is_some_and(|h| h.frame >= base_frame);
+        if !handled {
+            while self.frames.len() > base_frame {
+                self.pop_frame();
+            }
+            while self.handlers.last().is_some_and(|h| h.frame >= base_frame) {
+                self.handlers.pop();
+            }
+            return Err(err);
+        }
+        let handler = self.handlers.pop().expect("a handler was found");
+        while self.frames.len() > handler.frame + 1 {
+            self.pop_frame();
+        }
+        self.stack.truncate(handler.height);
+        self.pending.truncate(handler.pending);
+        self.frame_mut().ip = handler.target;
+        match handler.kind {
+            HandlerKind::Catch => self.stack.push(caught_value(err)),
+            HandlerKind::Finally => self.pending.push(err),
+        }
+        Ok(())
+    }
+
+    fn jump(&mut self, target: u32) {
+        self.frame_mut().ip = target as usize;
+    }
+
+    // Variables
+
+    fn cell(&self, var: Var) -> &Rc<Captured> {
+        let frame = self.frame();
+        match var {
+            Var::Cell(i) => &frame.cells[i as usize],
+            Var::Capture(i) => &frame.closure.as_ref().expect("captures belong to a closure").captures[i as usize],
+            _ => unreachable!("only cells are shared"),
+        }
+    }
+
+    fn is_declared(&self, var: Var) -> bool {
+        match var {
+            Var::Local(_) => true,
+            Var::Cell(_) | Var::Capture(_) => self.cell(var).0.borrow().is_some(),
+            Var::Global(i) => self.globals[i as usize].is_some(),
+            Var::Chain(_) => self.declared_link(var).is_some(),
+        }
+    }
+
+    // First declared candidate of a chain
+    fn declared_link(&self, var: Var) -> Option<Var> {
+        let Var::Chain(i) = var else { return Some(var) };
+        self.frame().proto.chunk.chains[i as usize].links.iter().copied().find(|&link| self.is_declared(link))
+    }
+
+    fn with_ref<R>(&self, var: Var, f: impl FnOnce(&Object) -> R) -> Option<R> {
+        match self.declared_link(var)? {
+            Var::Local(i) => Some(f(&self.stack[self.frame().base + i as usize])),
+            Var::Global(i) => self.globals[i as usize].as_ref().map(f),
+            cell => {
+                let value = self.cell(cell).0.borrow();
+                value.as_ref().map(f)
+            }
+        }
+    }
+
+    fn with_mut<R>(&mut self, var: Var, f: impl FnOnce(&mut Object) -> R) -> Option<R> {
+        match self.declared_link(var)? {
+            Var::Local(i) => {
+                let index = self.frame().base + i as usize;
+                Some(f(&mut self.stack[index]))
+            }
+            Var::Global(i) => self.globals[i as usize].as_mut().map(f),
+            cell => {
+                let mut value = self.cell(cell).0.borrow_mut();
+                value.as_mut().map(f)
+            }
+        }
+    }
+
+    fn load(&self, var: Var) -> Option<Object> {
+        self.with_ref(var, Object::clone)
+    }
+
+    fn var_name(&self, var: Var) -> &str {
+        match var {
+            Var::Global(i) => &self.global_names[i as usize],
+            Var::Chain(i) => &self.frame().proto.chunk.chains[i as usize].name,
+            _ => "",
+        }
+    }
+
+    fn define(&mut self, var: Var, value: Object) {
+        match var {
+            Var::Local(i) => {
+                let index = self.frame().base + i as usize;
+                store(&mut self.stack[index], value);
+            }
+            Var::Global(i) => set(&mut self.globals[i as usize], value),
+            cell => set(&mut self.cell(cell).0.borrow_mut(), value),
+        }
+    }
+
+    // Reads through the fields and indexes of a path, borrowing the containers on the way
+    fn read_path(&self, path: &Path, keys: &[Object]) -> Result<Object, LangError> {
+        let Root::Var { var, name, span } = &path.root else {
+            unreachable!("reads start from a variable");
+        };
+        let read = |root: &Object| -> Result<Object, LangError> {
+            let mut value = Cow::Borrowed(root);
+            let mut keys = keys.iter();
+            for step in &path.steps {
+                value = match step {
+                    Step::Index(span) => {
+                        let key = keys.next().expect("a key for each index");
+                        // Characters are not stored as values, so strings are indexed by copy
+                        let result = match value {
+                            Cow::Borrowed(container) if !matches!(container, Object::String(_)) => {
+                                element(container, key).map(Cow::Borrowed)
+                            }
+                            container => index_value(&container, key).map(Cow::Owned),
+                        };
+                        result.map_err(|e| e.at(*span))?
+                    }
+                    Step::Field(field, span) => {
+                        let borrowed = match value {
+                            Cow::Borrowed(Object::Instance { struct_type, fields }) => {
+                                struct_type.field_index(field).map(|idx| &fields[idx])
+                            }
+                            _ => None,
+                        };
+                        match borrowed {
+                            Some(field_value) => Cow::Borrowed(field_value),
+                            None => Cow::Owned(read_field(value.into_owned(), field).map_err(|e| e.at(*span))?),
+                        }
+                    }
+                };
+            }
+            Ok(value.into_owned())
+        };
+        match self.with_ref(*var, read) {
+            Some(result) => result,
+            None => Err(runtime_error(ErrorCode::UndefinedVariable, format!("Identifier not found: {}", name), *span)),
+        }
+    }
+
+    // Finds the slot a path names and lets `update` modify it in place
+    fn update_path(
+        &mut self,
+        path: &Path,
+        keys: &[Object],
+        update: impl FnOnce(&mut Object) -> Result<Object, LangError>,
+    ) -> Result<Object, LangError> {
+        let (var, name, span) = match &path.root {
+            Root::Var { var, name, span } => (*var, name, *span),
+            Root::Invalid(span) => {
+                return Err(runtime_error(ErrorCode::InvalidAssignmentTarget, "Invalid assignment target", *span));
+            }
+        };
+        let result = self.with_mut(var, |root| {
+            let mut slot = root;
+            let mut keys = keys.iter();
+            for step in &path.steps {
+                slot = match step {
+                    Step::Index(span) => {
+                        let key = keys.next().expect("a key for each index");
+                        element_mut(slot, key).map_err(|e| e.at(*span))?
+                    }
+                    Step::Field(field, span) => field_mut(slot, field).map_err(|e| e.at(*span))?,
+                };
+            }
+            update(slot)
+        });
+        match result {
+            Some(result) => result,
+            None => Err(runtime_error(ErrorCode::UndefinedVariable,
+                format!("Cannot assign to undeclared variable: {}", name), span)),
+        }
+    }
+
+    // Calls
+
+    // Calls a function for a builtin such as `map`, running closures to completion
+    pub fn call_value(&mut self, function: Object, args: Vec<Object>, span: Span) -> Result<Object, LangError> {
+        match function {
+            Object::Closure(closure) => {
+                let height = self.stack.len();
+                let argc = args.len();
+                self.stack.extend(args);
+                if let Err(err) = self.call_closure(closure, argc, "callback", span, height, None) {
+                    self.stack.truncate(height);
+                    return Err(err);
+                }
+                self.execute(self.frames.len() - 1)
+            }
+            other => self.call_native(other, args, "callback", span),
+        }
+    }
+
+    // Starts a closure whose arguments are the top `argc` values
+    fn call_closure(
+        &mut self,
+        closure: Rc<Closure>,
+        argc: usize,
+        name: &str,
+        span: Span,
+        return_height: usize,
+        write_back: Option<Box<WriteBack>>,
+    ) -> Result<(), LangError> {
+        let proto = closure.proto.clone();
+        if proto.parameters.len() != argc {
+            return Err(runtime_error(ErrorCode::ArgumentCount, format!("Function {} expects {} arguments, got {}",
+                name, proto.parameters.len(), argc), span));
+        }
+        let depth = DepthGuard::enter(Depth::Call, span)?;
+        self.push_frame(proto, Some(closure), return_height, span, write_back, Some(depth));
+        Ok(())
+    }
+
+    fn call_native(&mut self, function: Object, args: Vec<Object>, name: &str, span: Span) -> Result<Object, LangError> {
+        match function {
+            Object::BuiltinFunction(func) => func(&mut Interpreter::for_vm(span, self), &args).map_err(|e| e.at(span)),
+            Object::Closure(_) => self.call_value(function, args, span),
+            Object::Struct(struct_type) => instantiate(struct_type, args).map_err(|e| e.at(span)),
+            _ => Err(runtime_error(ErrorCode::NotCallable, format!("{} is not a function", name), span)),
+        }
+    }
+
+    // Calls `function` with `args`, as a new frame for closures
+    fn invoke(&mut self, function: Object, args: Vec<Object>, name: &str, span: Span) -> Result<(), LangError> {
+        match function {
+            Object::Closure(closure) => {
+                let height = self.stack.len();
+                let argc = args.len();
+                self.stack.extend(args);
+                self.call_closure(closure, argc, name, span, height, None)
+            }
+            other => {
+                let result = self.call_native(other, args, name, span)?;
+                self.stack.push(result);
+                Ok(())
+            }
+        }
+    }
+
+    fn method_call(&mut self, index: u32, span: Span) -> Result<(), LangError> {
+        let proto = self.frame().proto.clone();
+        let method = &proto.chunk.methods[index as usize];
+        let args = self.stack.split_off(self.stack.len() - method.argc);
+        let receiver = self.pop();
+        let key_count = method.path.map_or(0, |p| proto.chunk.paths[p as usize].keys());
+        let keys = self.stack.split_off(self.stack.len() - key_count);
+        let name = method.name.as_str();
+        match resolve_method(&receiver, name) {
+            Some(MethodTarget::SelfMethod(Object::Closure(closure))) => {
+                let height = self.stack.len();
+                self.stack.push(receiver.clone());
+                self.stack.extend(args);
+                let write_back = method.path.map(|path| Box::new(WriteBack { path, keys, receiver }));
+                self.call_closure(closure, method.argc + 1, name, span, height, write_back)
+            }
+            Some(MethodTarget::SelfMethod(function) | MethodTarget::Function(function)) => {
+                self.invoke(function, args, name, span)
+            }
+            Some(MethodTarget::Builtin(builtin)) => {
+                let mut full_args = vec![receiver];
+                full_args.extend(args);
+                self.invoke(builtin, full_args, name, span)
+            }
+            Some(MethodTarget::Variant(enum_type, variant)) => {
+                let value = make_variant(enum_type, variant, args).map_err(|e| e.at(span))?;
+                self.stack.push(value);
+                Ok(())
+            }
+            None => Err(runtime_error(ErrorCode::NotCallable,
+                format!("{} has no method '{}'", type_label(&receiver), name), span)),
+        }
+    }
+
+    // Ends the running call with `value`, writing a changed `self` back to the receiver
+    fn return_from(&mut self, value: Object, base_frame: usize) -> Result<Option<Object>, LangError> {
+        let updated = self.frame().write_back.as_ref().map(|_| {
+            let frame = self.frame();
+            match frame.proto.self_var {
+                Some(Var::Local(i)) => self.stack[frame.base + i as usize].clone(),
+                Some(var) => self.load(var).unwrap_or(Object::Null),
+                None => Object::Null,
+            }
+        });
+        let frame = self.pop_frame();
+        if self.frames.len() == base_frame {
+            return Ok(Some(value));
+        }
+        if let (Some(write_back), Some(updated)) = (frame.write_back, updated) {
+            if updated != write_back.receiver {
+                let proto = self.frame().proto.clone();
+                let path = &proto.chunk.paths[write_back.path as usize];
+                self.update_path(path, &write_back.keys, |slot| {
+                    store(slot, updated);
+                    Ok(Object::Null)
+                }).map_err(|e| e.at(frame.call_span))?;
+            }
+        }
+        self.stack.push(value);
+        Ok(None)
+    }
+
+    fn match_pattern(&self, pattern: &CompiledPattern, value: &Object, bindings: &mut Vec<Object>) -> Result<bool, LangError> {
+        match pattern {
+            CompiledPattern::Wildcard => Ok(true),
+            CompiledPattern::Binding => {
+                bindings.push(value.clone());
+                Ok(true)
+            }
+            CompiledPattern::Literal(expected) => Ok(objects_equal(expected, value)),
+            CompiledPattern::Invalid(err) => Err(err.clone()),
+            CompiledPattern::Array { prefix, rest, suffix } => {
+                let Object::Array(items) = value else {
+                    return Ok(false);
+                };
+                let fixed = prefix.len() + suffix.len();
+                let length_ok = match rest {
+                    Some(_) => items.len() >= fixed,
+                    None => items.len() == fixed,
+                };
+                if !length_ok {
+                    return Ok(false);
+                }
+                let suffix_start = items.len() - suffix.len();
+                for (pattern, item) in prefix.iter().zip(items).chain(suffix.iter().zip(&items[suffix_start..])) {
+                    if !self.match_pattern(pattern, item, bindings)? {
+                        return Ok(false);
+                    }
+                }
+                match rest {
+                    Some(rest) => self.match_pattern(rest, &Object::Array(items[prefix.len()..suffix_start].to_vec()), bindings),
+                    None => Ok(true),
+                }
+            }
+            CompiledPattern::Variant { enum_var, enum_name, variant, fields } => {
+                let enum_type = match self.load(*enum_var) {
+                    Some(Object::Enum(enum_type)) => enum_type,
+                    Some(other) => return Err(LangError::new(ErrorCode::TypeMismatch,
+                        format!("'{}' is {}, not an enum", enum_name, other.type_name()))),
+                    None => return Err(LangError::new(ErrorCode::UndefinedVariable, format!("Enum not found: {}", enum_name))),
+                };
+                let Some(index) = enum_type.variant_index(variant) else {
+                    return Err(LangError::new(ErrorCode::UnknownField,
+                        format!("Enum {} has no variant '{}'", enum_name, variant)));
+                };
+                let expected = enum_type.variants[index].1.len();
+                if fields.len() != expected {
+                    return Err(LangError::new(ErrorCode::ArgumentCount,
+                        format!("Pattern {}.{} expects {} values, got {}", enum_name, variant, expected, fields.len())));
+                }
+                match value {
+                    Object::Variant { enum_type: actual, variant: actual_index, values }
+                        if Rc::ptr_eq(actual, &enum_type) && *actual_index == index =>
+                    {
+                        for (pattern, item) in fields.iter().zip(values) {
+                            if !self.match_pattern(pattern, item, bindings)? {
+                                return Ok(false);
+                            }
+                        }
+                        Ok(true)
+                    }
+                    _ => Ok(false),
+                }
+            }
+        }
+    }
+
+    // Runs one instruction; Some once the frame at `base_frame` has finished
+    fn step(&mut self, op: Op, span: Span, base_frame: usize) -> Result<Option<Object>, LangError> {
+        match op {
+            Op::Constant(i) => {
+                let value = self.frame().proto.chunk.constants[i as usize].clone();
+                self.stack.push(value);
+            }
+            Op::Null => self.stack.push(Object::Null),
+            Op::Pop => {
+                self.pop();
+            }
+            Op::Dup => {
+                let value = self.top().clone();
+                self.stack.push(value);
+            }
+            Op::Load(var) => match self.load(var) {
+                Some(value) => self.stack.push(value),
+                None => return Err(runtime_error(ErrorCode::UndefinedVariable,
+                    format!("Identifier not found: {}", self.var_name(var)), span)),
+            },
+            Op::LoadCallee(var) => match self.load(var) {
+                Some(value) => self.stack.push(value),
+                None => return Err(runtime_error(ErrorCode::UndefinedVariable,
+                    format!("Function not found: {}", self.var_name(var)), span)),
+            },
+            Op::Define(var) => {
+                let value = self.pop();
+                self.define(var, value);
+            }
+            Op::NewCell(i) => self.frame_mut().cells[i as usize] = Rc::default(),
+            Op::ParamToCell(slot, cell) => {
+                let index = self.frame().base + slot as usize;
+                let value = std::mem::replace(&mut self.stack[index], Object::Null);
+                *self.frame().cells[cell as usize].0.borrow_mut() = Some(value);
+            }
+            Op::Replace(depth) => {
+                let value = self.pop();
+                let index = self.temp_base() + depth as usize;
+                self.stack[index] = value;
+            }
+            Op::Truncate(depth) => {
+                let height = self.temp_base() + depth as usize;
+                self.stack.truncate(height);
+            }
+            Op::Unwind(depth) => {
+                let value = self.pop();
+                let height = self.temp_base() + depth as usize;
+                self.stack.truncate(height);
+                self.stack.push(value);
+            }
+            Op::Closure(i) => {
+                let frame = self.frame();
+                let proto = frame.proto.chunk.functions[i as usize].clone();
+                let captures = proto.captures.iter().map(|&var| self.cell(var).clone()).collect();
+                self.stack.push(Object::Closure(Rc::new(Closure { proto, captures })));
+            }
+            Op::MakeType(i) => {
+                let value = match &self.frame().proto.chunk.types[i as usize] {
+                    TypeDef::Struct { name, fields } => Object::Struct(Rc::new(StructType {
+                        name: name.clone(),
+                        fields: fields.clone(),
+                        methods: RefCell::new(HashMap::new()),
+                    })),
+                    TypeDef::Enum { name, variants } => Object::Enum(Rc::new(EnumType {
+                        name: name.clone(),
+                        variants: variants.clone(),
+                        methods: RefCell::new(HashMap::new()),
+                    })),
+                };
+                self.stack.push(value);
+            }
+            // Methods close over the scope of the impl block, like any other function
+            Op::Impl(var, i) => {
+                let proto = self.frame().proto.clone();
+                let definition = &proto.chunk.impls[i as usize];
+                let methods = self.stack.split_off(self.stack.len() - definition.methods.len());
+                let target = self.load(var);
+                let (method_table, implemented) = match &target {
+                    Some(Object::Struct(struct_type)) => (&struct_type.methods, Implemented::Struct(Rc::downgrade(struct_type))),
+                    Some(Object::Enum(enum_type)) => (&enum_type.methods, Implemented::Enum(Rc::downgrade(enum_type))),
+                    Some(other) => return Err(runtime_error(ErrorCode::TypeMismatch,
+                        format!("Cannot implement methods for {} '{}'", other.type_name(), definition.name), span)),
+                    None => return Err(runtime_error(ErrorCode::UndefinedVariable,
+                        format!("Struct or enum not found: {}", definition.name), span)),
+                };
+                for (name, method) in definition.methods.iter().zip(methods) {
+                    method_table.borrow_mut().insert(name.clone(), method);
+                }
+                self.frame_mut().implemented.push(implemented.clone());
+                // Forget types that have been freed since, before the list grows
+                if self.implemented.len() == self.implemented.capacity() {
+                    self.implemented.retain(|implemented| implemented.strong_count() > 0);
+                }
+                self.implemented.push(implemented);
+                self.stack.push(Object::Null);
+            }
+
+            Op::Array(n) => {
+                let items = self.stack.split_off(self.stack.len() - n as usize);
+                self.stack.push(Object::Array(items));
+            }
+            Op::CheckKey => {
+                HashKey::from_object(self.top()).map_err(|e| e.at(span))?;
+            }
+            Op::Map(n) => {
+                let entries = self.stack.split_off(self.stack.len() - 2 * n as usize);
+                let mut map = BTreeMap::new();
+                let mut entries = entries.into_iter();
+                while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+                    map.insert(HashKey::from_object(&key).map_err(|e| e.at(span))?, value);
+                }
+                self.stack.push(Object::Map(map));
+            }
+            Op::RangeBound => {
+                if !matches!(self.top(), Object::Integer(_)) {
+                    return Err(runtime_error(ErrorCode::TypeMismatch,
+                        format!("Range bounds must be integers, got {}", self.top().type_name()), span));
+                }
+            }
+            Op::MakeRange(inclusive) => {
+                let (Object::Integer(mut end), Object::Integer(start)) = (self.pop(), self.pop()) else {
+                    unreachable!("range bounds are checked");
+                };
+                if inclusive {
+                    end += 1;
+                }
+                let range = range_array(start, end).map_err(|e| e.at(span))?;
+                self.stack.push(range);
+            }
+            Op::RangeStart(inclusive) => {
+                if let (true, Some(Object::Integer(end))) = (inclusive, self.stack.last_mut()) {
+                    *end += 1;
+                }
+            }
+            Op::RangeNext(exit) => {
+                let len = self.stack.len();
+                let (cursor, end) = self.stack.split_at_mut(len - 1);
+                let (Some(Object::Integer(cursor)), Object::Integer(end)) = (cursor.last_mut(), &end[0]) else {
+                    unreachable!("range bounds are checked");
+                };
+                if *cursor < *end {
+                    let value = cursor.clone();
+                    *cursor += 1;
+                    self.stack.push(Object::Integer(value));
+                } else {
+                    self.jump(exit);
+                }
+            }
+            Op::IterPrepare(pair) => {
+                let mut items: Vec<Object> = match self.pop() {
+                    Object::Array(arr) if pair => arr.into_iter().enumerate()
+                        .map(|(i, item)| Object::Array(vec![Object::Integer(BigInt::from(i)), item]))
+                        .collect(),
+                    Object::Array(arr) => arr,
+                    Object::String(s) if pair => s.chars().enumerate()
+                        .map(|(i, c)| Object::Array(vec![Object::Integer(BigInt::from(i)), Object::String(c.to_string())]))
+                        .collect(),
+                    Object::String(s) => s.chars().map(|c| Object::String(c.to_string())).collect(),
+                    Object::Map(map) if pair => map.into_iter().map(|(k, v)| Object::Array(vec![k.to_object(), v])).collect(),
+                    Object::Map(map) => map.into_keys().map(|k| k.to_object()).collect(),
+                    other => return Err(runtime_error(ErrorCode::TypeMismatch,
+                        format!("Cannot iterate over {}", other.type_name()), span)),
+                };
+                items.reverse();
+                self.stack.push(Object::Array(items));
+            }
+            Op::IterNext(exit, pair) => {
+                let Some(Object::Array(items)) = self.stack.last_mut() else {
+                    unreachable!("for-in items are prepared");
+                };
+                match items.pop() {
+                    Some(Object::Array(mut entry)) if pair => {
+                        let (value, key) = (entry.pop(), entry.pop());
+                        self.stack.extend([value.unwrap_or(Object::Null), key.unwrap_or(Object::Null)]);
+                    }
+                    Some(item) => self.stack.push(item),
+                    None => self.jump(exit),
+                }
+            }
+
+            Op::Index => {
+                let index = self.pop();
+                let container = self.pop();
+                let value = index_value(&container, &index).map_err(|e| e.at(span))?;
+                self.stack.push(value);
+            }
+            Op::SafeIndex => {
+                let index = self.pop();
+                let container = self.pop();
+                let value = match index_value(&container, &index) {
+                    Ok(value) => value,
+                    Err(e) if matches!(e.code, ErrorCode::IndexOutOfBounds | ErrorCode::KeyNotFound) => Object::Null,
+                    Err(e) => return Err(e.at(span)),
+                };
+                self.stack.push(value);
+            }
+            Op::JumpIfNullKeep(target) => {
+                if matches!(self.top(), Object::Null) {
+                    self.jump(target);
+                }
+            }
+            Op::Slice(flags) => {
+                let mut parts = [None, None, None];
+                for bit in (0..3).rev() {
+                    if flags & (1 << bit) != 0 {
+                        parts[bit] = Some(self.pop());
+                    }
+                }
+                let container = self.pop();
+                let [start, end, step] = &parts;
+                let slice = slice_value(container, start.as_ref(), end.as_ref(), step.as_ref()).map_err(|e| e.at(span))?;
+                self.stack.push(slice);
+            }
+            Op::Field(i) => {
+                let value = self.pop();
+                let value = read_field(value, &self.frame().proto.chunk.names[i as usize]).map_err(|e| e.at(span))?;
+                self.stack.push(value);
+            }
+            Op::LoadPath(i) | Op::Receiver(i) => {
+                let proto = self.frame().proto.clone();
+                let path = &proto.chunk.paths[i as usize];
+                let keys_start = self.stack.len() - path.keys();
+                let value = self.read_path(path, &self.stack[keys_start..])?;
+                if matches!(op, Op::LoadPath(_)) {
+                    self.stack.truncate(keys_start);
+                }
+                self.stack.push(value);
+            }
+            Op::Assign(i) => {
+                let proto = self.frame().proto.clone();
+                let path = &proto.chunk.paths[i as usize];
+                // Keys were pushed innermost first
+                let keys: Vec<Object> = self.stack.drain(self.stack.len() - path.keys()..).rev().collect();
+                let rhs = self.pop();
+                let result = self.update_path(path, &keys, |slot| {
+                    let new_value = match &path.op {
+                        Some(op) => evaluate_infix(op, slot, &rhs)?.into_value()?,
+                        None => rhs,
+                    };
+                    store(slot, new_value.clone());
+                    Ok(new_value)
+                }).map_err(|e| e.at(span))?;
+                self.stack.push(result);
+            }
+            Op::Infix(i) => {
+                let right = self.pop();
+                let left = self.pop();
+                let op = &self.frame().proto.chunk.operators[i as usize];
+                let value = evaluate_infix(op, &left, &right).and_then(|result| result.into_value()).map_err(|e| e.at(span))?;
+                self.stack.push(value);
+            }
+            Op::Prefix(i) => {
+                let right = self.pop();
+                let value = evaluate_prefix(&self.frame().proto.chunk.operators[i as usize], right).map_err(|e| e.at(span))?;
+                self.stack.push(value);
+            }
+            Op::Concat(n) => {
+                let parts = self.stack.split_off(self.stack.len() - n as usize);
+                let text: String = parts.iter().map(Object::to_string).collect();
+                self.stack.push(Object::String(text));
+            }
+
+            Op::Jump(target) => self.jump(target),
+            Op::JumpIfFalse(target) => {
+                if !self.pop().is_truthy() {
+                    self.jump(target);
+                }
+            }
+            Op::JumpIfFalseKeep(target) => {
+                if self.top().is_truthy() {
+                    self.pop();
+                } else {
+                    self.jump(target);
+                }
+            }
+            Op::JumpIfTrueKeep(target) => {
+                if self.top().is_truthy() {
+                    self.jump(target);
+                } else {
+                    self.pop();
+                }
+            }
+            Op::JumpIfNotNullKeep(target) => {
+                if matches!(self.top(), Object::Null) {
+                    self.pop();
+                } else {
+                    self.jump(target);
+                }
+            }
+
+            Op::Call(argc, name) => {
+                // The callee stays below the arguments until the call returns
+                let callee_index = self.stack.len() - argc as usize - 1;
+                let proto = self.frame().proto.clone();
+                let name = match (name, &self.stack[callee_index]) {
+                    (Some(i), _) => proto.chunk.names[i as usize].as_str(),
+                    (None, Object::Closure(_)) => "<anonymous>",
+                    (None, other) => other.type_name(),
+                };
+                if let Object::Closure(closure) = &self.stack[callee_index] {
+                    let closure = closure.clone();
+                    self.call_closure(closure, argc as usize, name, span, callee_index, None)?;
+                } else {
+                    let args = self.stack.split_off(callee_index + 1);
+                    let callee = self.pop();
+                    let result = self.call_native(callee, args, name, span)?;
+                    self.stack.push(result);
+                }
+            }
+            Op::CallMethod(i) => self.method_call(i, span)?,
+            Op::Return => {
+                let value = self.pop();
+                return self.return_from(value, base_frame);
+            }
+            Op::Halt => {
+                let value = self.pop();
+                self.pop_frame();
+                return Ok(Some(program_result(value)));
+            }
+
+            Op::Throw => {
+                let (kind, message) = match self.pop() {
+                    Object::Error { kind, message } => (kind, message),
+                    other => ("Error".to_string(), other.to_string()),
+                };
+                return Err(LangError::thrown(kind, message).at(span));
+            }
+            Op::Raise(i) => return Err(self.frame().proto.chunk.errors[i as usize].clone()),
+            Op::PushHandler(kind, target) => {
+                self.handlers.push(Handler {
+                    kind,
+                    frame: self.frames.len() - 1,
+                    height: self.stack.len(),
+                    pending: self.pending.len(),
+                    target: target as usize,
+                });
+            }
+            Op::PopHandler => {
+                self.handlers.pop();
+            }
+            Op::Rethrow => return Err(self.pending.pop().expect("an error waits for its finally block")),
+            Op::DropPending => {
+                self.pending.pop();
+            }
+            Op::Pattern(p, fail) => {
+                let proto = self.frame().proto.clone();
+                let mut bindings = Vec::new();
+                let matched = self.match_pattern(&proto.chunk.patterns[p as usize], self.top(), &mut bindings)
+                    .map_err(|e| e.at(span))?;
+                if matched {
+                    self.stack.extend(bindings.into_iter().rev());
+                } else {
+                    self.jump(fail);
+                }
+            }
+            Op::NoMatch => {
+                return Err(runtime_error(ErrorCode::NoMatchingArm, format!("No match arm matches {}", self.top()), span));
+            }
+            Op::EscapeFunction => {
+                let frame = self.pop_frame();
+                return Err(runtime_error(ErrorCode::InvalidControlFlow,
+                    "break or continue outside of loop", frame.call_span));
+            }
+        }
+        Ok(None)
+    }
+}